@@ -0,0 +1,50 @@
+//! Setup shared by the sampler benchmarks.
+
+// Not every benchmark uses every helper.
+#![allow(dead_code)]
+
+use std::hint::black_box;
+
+use criterion::{measurement::WallTime, BatchSize, BenchmarkGroup, BenchmarkId, Throughput};
+use rand::{Rng, SeedableRng};
+
+use llm_samplers::prelude::*;
+
+/// Reproducible logits uniformly distributed between `-10.0` and `10.0`.
+pub fn uniform_logits(n_vocab: usize) -> Logits {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(123);
+    Logits::try_from_iter((0..n_vocab).map(|_| rng.gen_range(-10f32..10f32)))
+        .expect("Invalid logits")
+}
+
+/// Benchmark `fun` on a fresh copy of `base`. Cloning the input isn't part of the
+/// measurement. Throughput is reported per token in `base`.
+pub fn bench_logits<P: std::fmt::Display>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    param: P,
+    base: &Logits,
+    mut fun: impl FnMut(&mut Logits),
+) {
+    group.throughput(Throughput::Elements(base.len() as u64));
+    group.bench_function(BenchmarkId::new(name, param), |b| {
+        b.iter_batched_ref(
+            || base.clone(),
+            |logits| fun(black_box(logits)),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Benchmark running `sampler` on a fresh copy of `base`, see [bench_logits].
+pub fn bench_sampler<P: std::fmt::Display>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    param: P,
+    base: &Logits,
+    sampler: &mut dyn Sampler,
+) {
+    bench_logits(group, name, param, base, |logits| {
+        sampler.sample(&mut (), logits).expect("Sampling failed");
+    });
+}
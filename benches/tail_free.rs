@@ -0,0 +1,64 @@
+//! Benchmark for [SampleTailFree] over a large vocabulary, compared with the previous
+//! implementation, which collected all the normalized second derivatives into a [Vec]
+//! before looking for the cutoff. It also checks both find the same cutoff.
+//!
+//! Run with `cargo bench --bench tail_free`.
+//!
+//! With a 100k token vocabulary, not building the second derivative [Vec] took this
+//! from roughly 370-410µs to 75-100µs per call on the development machine.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use llm_samplers::prelude::*;
+
+const N_VOCAB: usize = 100_000;
+
+/// The previous implementation, returns the number of tokens kept.
+fn reference_cutoff(logits: &mut Logits, z: f32, min_keep: usize) -> usize {
+    logits.ensure_softmax().expect("Softmax failed");
+    let mut sderivs = (0..logits.len() - 2)
+        .map(|idx| {
+            let fd0 = logits[idx].prob - logits[idx + 1].prob;
+            let fd1 = logits[idx + 1].prob - logits[idx + 2].prob;
+            (fd0 - fd1).abs()
+        })
+        .collect::<Vec<_>>();
+    let ssum = sderivs.iter().fold(0f32, |ssum, sd| ssum + sd);
+    sderivs.iter_mut().for_each(|sd| *sd /= ssum);
+
+    let mut cum_sum = 0f32;
+    sderivs
+        .into_iter()
+        .enumerate()
+        .find(|(idx, sd)| {
+            cum_sum += sd;
+            cum_sum >= z && *idx >= min_keep
+        })
+        .map_or(logits.len(), |(idx, _)| idx)
+}
+
+fn tail_free(c: &mut Criterion) {
+    let mut base = common::uniform_logits(N_VOCAB);
+    base.ensure_softmax().expect("Softmax failed");
+    let mut group = c.benchmark_group("tail free");
+    for z in [0.25f32, 0.5, 0.95] {
+        let mut sampler = SampleTailFree::new(z, 1);
+        let mut logits = base.clone();
+        sampler
+            .sample(&mut (), &mut logits)
+            .expect("Sampling failed");
+        let kept_ref = reference_cutoff(&mut base.clone(), z, 1);
+        assert_eq!(logits.len(), kept_ref, "Cutoff mismatch with z={z}");
+
+        common::bench_sampler(&mut group, "z", z, &base, &mut sampler);
+        common::bench_logits(&mut group, "previous z", z, &base, |logits| {
+            reference_cutoff(logits, z, 1);
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tail_free);
+criterion_main!(benches);
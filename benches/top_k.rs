@@ -0,0 +1,43 @@
+//! Benchmark for [SampleTopK] over a large vocabulary, compared with sorting the
+//! whole list and then truncating, which is what the sampler used to do. It also
+//! checks both give the same result.
+//!
+//! Run with `cargo bench --bench top_k`.
+//!
+//! With a 128k token vocabulary, selecting the top k instead of sorting everything
+//! took this from roughly 15-17ms to 0.5-0.7ms per call on the development machine.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use llm_samplers::prelude::*;
+
+const N_VOCAB: usize = 128_000;
+
+fn full_sort(logits: &mut Logits, k: usize) {
+    logits.ensure_sorted().expect("Sorting failed").truncate(k);
+}
+
+fn top_k(c: &mut Criterion) {
+    let base = common::uniform_logits(N_VOCAB);
+    let mut group = c.benchmark_group("top-k");
+    for k in [40usize, 200, 2000] {
+        let mut sampler = SampleTopK::new(k, 1);
+        let (mut logits, mut logits_ref) = (base.clone(), base.clone());
+        sampler
+            .sample(&mut (), &mut logits)
+            .expect("Sampling failed");
+        full_sort(&mut logits_ref, k);
+        assert_eq!(&*logits, &*logits_ref, "Mismatch with k={k}");
+
+        common::bench_sampler(&mut group, "k", k, &base, &mut sampler);
+        common::bench_logits(&mut group, "full sort k", k, &base, |logits| {
+            full_sort(logits, k)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, top_k);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+//! Benchmark for [SampleTopP] and [SampleTopK] at several vocabulary sizes,
+//! using [Logits::random] for the input.
+//!
+//! Run with `cargo bench --features testing --bench vocab_sizes`.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::SeedableRng;
+
+use llm_samplers::prelude::*;
+
+const VOCAB_SIZES: &[usize] = &[1_000, 32_000, 128_000];
+
+fn vocab_sizes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vocab sizes");
+    for &n_vocab in VOCAB_SIZES {
+        let base = Logits::random(n_vocab, &mut rand::rngs::StdRng::seed_from_u64(123));
+        let samplers: [(&str, Box<dyn Sampler>); 2] = [
+            ("top-p p=0.9", SampleTopP::new(0.9, 1).boxed()),
+            ("top-k k=40", SampleTopK::new(40, 1).boxed()),
+        ];
+        for (name, mut sampler) in samplers {
+            common::bench_sampler(&mut group, name, n_vocab, &base, &mut sampler);
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, vocab_sizes);
+criterion_main!(benches);
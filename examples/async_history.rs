@@ -0,0 +1,102 @@
+//! Example of sampling with token history that comes from an async source.
+//!
+//! Run with: `cargo run --example async_history --features async`
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+};
+
+use anyhow::Result;
+use rand::{rngs::StdRng, SeedableRng};
+
+use llm_samplers::prelude::*;
+
+/// Stands in for something like a database or a remote session store. In a real
+/// inference server, this would probably be behind a network call.
+#[derive(Clone, Default)]
+struct HistoryStore {
+    tokens: Arc<Mutex<Vec<TID>>>,
+}
+
+impl HistoryStore {
+    async fn load(&self) -> Vec<TID> {
+        self.tokens.lock().unwrap().clone()
+    }
+
+    async fn append(&self, tid: TID) {
+        self.tokens.lock().unwrap().push(tid);
+    }
+}
+
+struct SessionResources {
+    rng: StdRng,
+    history: HistoryStore,
+}
+
+impl AsyncHasSamplerResources for SessionResources {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        fun(&mut self.rng);
+        Ok(())
+    }
+
+    async fn last_tokens(&mut self) -> Result<Vec<TID>, SamplerError> {
+        Ok(self.history.load().await)
+    }
+}
+
+async fn generate(steps: usize) -> Result<Vec<TID>> {
+    let history = HistoryStore::default();
+    let mut res = SessionResources {
+        rng: StdRng::seed_from_u64(123),
+        history: history.clone(),
+    };
+    let mut sc = SamplerChain::new()
+        + SampleRepetition::new(1.5, 64)
+        + SampleTemperature::new(0.8)
+        + SampleRandDistrib::new();
+
+    for _ in 0..steps {
+        // Pretend these came from the model.
+        let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+        let tid = sc
+            .sample_token_async(&mut res, &mut logits)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No token sampled"))?;
+        history.append(tid).await;
+    }
+    Ok(history.load().await)
+}
+
+/// Minimal executor so the example doesn't need an async runtime dependency.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = pin!(fut);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(result) => return result,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let tokens = block_on(generate(16))?;
+    println!("Generated tokens: {tokens:?}");
+    Ok(())
+}
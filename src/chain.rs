@@ -1,18 +1,46 @@
 use std::{
-    fmt::Debug,
+    fmt::{Debug, Display, Formatter},
     ops::{Add, AddAssign},
 };
 
-use crate::{types::{HasSamplerResources, Logits, Sampler, TID}, prelude::SamplerError};
+use rand::{rngs::StdRng, SeedableRng};
 
-#[derive(Default, Debug)]
+use crate::{
+    configure::SamplerMetadata,
+    position::PositionAware,
+    prelude::SamplerError,
+    report::{RankedCandidates, SampleLog, SampleReport},
+    samplers::{SampleGreedy, SampleRandDistrib, SampleTemperature, SampleTopK, SampleTopP},
+    types::{HasSamplerResources, Logits, SampleRequirements, Sampler, TID},
+};
+
+#[derive(Default, Debug, Clone)]
 /// A list of [Sampler]s that can be run in sequence. It implements `Sampler`
 /// so you can build samplers as modular components. A typical use case would
 /// be to have several filtering samplers and then a token-picking sampler as the last
 /// item to enable calling [Sampler::sample_token] on the chain.
+///
+/// Cloning a chain clones each of its samplers, including their current state.
+///
+/// A chain can optionally have a warmup period (see [SamplerChain::set_warmup_steps])
+/// during which it always selects the most probable token.
+///
+/// The tokens a chain selects can be recorded with [SamplerChain::record] and
+/// replayed later with [SamplerChain::replay].
 pub struct SamplerChain {
     samplers: Vec<Box<dyn Sampler>>,
     token: Option<TID>,
+    warmup_steps: usize,
+    position: PositionAware,
+    replay: ReplayMode,
+}
+
+#[derive(Default, Debug, Clone)]
+enum ReplayMode {
+    #[default]
+    Off,
+    Record(SampleLog),
+    Replay(std::vec::IntoIter<Option<TID>>),
 }
 
 impl SamplerChain {
@@ -20,14 +48,247 @@ impl SamplerChain {
         Self {
             samplers: vec![],
             token: None,
+            warmup_steps: 0,
+            position: PositionAware::new(),
+            replay: ReplayMode::Off,
         }
     }
 
-    pub fn push_sampler(&mut self, sampler: impl Sampler + Send + Sync + 'static) -> &mut Self {
+    /// Preset chain that always selects the most probable token.
+    ///
+    /// Samplers: [SampleGreedy]
+    pub fn greedy() -> Self {
+        Self::new() + SampleGreedy::new()
+    }
+
+    /// Preset chain for varied, creative output. Requires the RNG resource.
+    ///
+    /// Samplers: [SampleTopP] (`p=0.95`), [SampleTemperature] (`temperature=1.0`),
+    /// [SampleRandDistrib]
+    pub fn creative() -> Self {
+        Self::new()
+            + SampleTopP::new(0.95, 1)
+            + SampleTemperature::new(1.0)
+            + SampleRandDistrib::new()
+    }
+
+    /// Preset chain for focused, more deterministic output. Requires the RNG resource.
+    ///
+    /// Samplers: [SampleTopK] (`k=20`), [SampleTemperature] (`temperature=0.3`),
+    /// [SampleRandDistrib]
+    pub fn precise() -> Self {
+        Self::new()
+            + SampleTopK::new(20, 1)
+            + SampleTemperature::new(0.3)
+            + SampleRandDistrib::new()
+    }
+
+    /// Chain for the common temperature, top-k, top-p sequence. Requires the RNG resource.
+    ///
+    /// Temperature runs first so top-p sees the scaled probabilities. Each sampler
+    /// keeps at least `min_keep` tokens.
+    ///
+    /// Samplers: [SampleTemperature] (`temperature`), [SampleTopK] (`top_k`, `min_keep`),
+    /// [SampleTopP] (`top_p`, `min_keep`), [SampleRandDistrib]
+    pub fn standard(temperature: f32, top_k: usize, top_p: f32, min_keep: usize) -> Self {
+        Self::new()
+            + SampleTemperature::new(temperature)
+            + SampleTopK::new(top_k, min_keep)
+            + SampleTopP::new(top_p, min_keep)
+            + SampleRandDistrib::new()
+    }
+
+    pub fn push_sampler(&mut self, sampler: impl Sampler + 'static) -> &mut Self {
         self.token = None;
         self.samplers.push(Box::new(sampler));
         self
     }
+
+    /// Returns the number of samplers in the chain.
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    /// Returns `true` if the chain has no samplers.
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    /// Returns the sampler at `index`, in the order they run. Use
+    /// [dyn Sampler::downcast_ref] to get the concrete type.
+    pub fn get(&self, index: usize) -> Option<&(dyn Sampler + 'static)> {
+        self.samplers.get(index).map(|sampler| sampler.as_ref())
+    }
+
+    /// Returns the sampler at `index` mutably. This is useful for changing the options
+    /// of a specific sampler when more than one sampler of the same type is in the
+    /// chain. Use [dyn Sampler::downcast_mut] to get the concrete type.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut (dyn Sampler + 'static)> {
+        self.token = None;
+        self.samplers.get_mut(index).map(|sampler| sampler.as_mut())
+    }
+
+    /// Returns the metadata of each sampler in the chain along with its index, in the
+    /// order they run. The index can be used with [SamplerChain::get_mut] to change the
+    /// options of that sampler. This is useful for advertising what can be tuned, for
+    /// example from a server endpoint. Samplers that don't provide metadata (see
+    /// [Sampler::metadata]) aren't included.
+    pub fn parameter_schema(&self) -> Vec<(usize, SamplerMetadata)> {
+        self.samplers
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, sampler)| Some((idx, sampler.metadata()?)))
+            .collect()
+    }
+
+    /// Set the number of warmup steps. For the first `steps` tokens the chain ignores its
+    /// samplers and selects the most probable token, after that it reverts to running
+    /// its samplers normally. When this is greater than `0` the chain requires the last
+    /// tokens resource to determine the current step.
+    ///
+    /// The step count starts from the length of the last tokens the first time the chain
+    /// samples, use [SamplerChain::reset] to start a new warmup period.
+    pub fn set_warmup_steps(&mut self, steps: usize) -> &mut Self {
+        self.warmup_steps = steps;
+        self
+    }
+
+    /// Start over with a new generation: the warmup period starts over (see
+    /// [SamplerChain::set_warmup_steps]) and each sampler in the chain is reset with
+    /// [Sampler::reset].
+    pub fn reset(&mut self) -> &mut Self {
+        self.position.reset();
+        self.samplers.iter_mut().for_each(|sampler| sampler.reset());
+        self
+    }
+
+    /// Start recording the token selected at each step. This replaces any recording or
+    /// replay in progress. Use [SamplerChain::take_recording] to get the [SampleLog].
+    pub fn record(&mut self) -> &mut Self {
+        self.replay = ReplayMode::Record(SampleLog::default());
+        self
+    }
+
+    /// Stop recording and return the [SampleLog], or `None` if the chain wasn't
+    /// recording.
+    pub fn take_recording(&mut self) -> Option<SampleLog> {
+        match std::mem::take(&mut self.replay) {
+            ReplayMode::Record(log) => Some(log),
+            replay => {
+                self.replay = replay;
+                None
+            }
+        }
+    }
+
+    /// Replay a [SampleLog]. Each step selects the next recorded token without
+    /// running the samplers, so the result doesn't depend on the RNG or sampler
+    /// state. Once the log is used up, the chain goes back to sampling normally.
+    /// This replaces any recording or replay in progress.
+    ///
+    /// An error is returned if a recorded token isn't in the [Logits].
+    pub fn replay(&mut self, log: SampleLog) -> &mut Self {
+        self.replay = ReplayMode::Replay(log.into_vec().into_iter());
+        self
+    }
+
+    /// Returns `true` while the chain is replaying a [SampleLog].
+    pub fn is_replaying(&self) -> bool {
+        matches!(&self.replay, ReplayMode::Replay(it) if it.len() > 0)
+    }
+
+    fn sample_inner<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> Result<&'a mut Logits, SamplerError> {
+        if self.warmup_steps > 0 && self.position.step(res)? < self.warmup_steps {
+            let mut greedy = SampleGreedy::new();
+            let logits = greedy.sample(res, logits)?;
+            self.token = greedy.sampled_token_id();
+            return Ok(logits);
+        }
+        self.samplers
+            .iter_mut()
+            .try_fold(logits, |logits, sampler| {
+                sampler.requires().ensure(logits)?;
+                let new_logits = sampler.sample(res, logits)?;
+                self.token = sampler.sampled_token_id();
+                Ok(new_logits)
+            })
+    }
+
+    /// Run the chain like [Sampler::sample_token] and return a [SampleReport]
+    /// containing the selected token and up to `n` of the most probable
+    /// surviving alternatives.
+    pub fn sample_report(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &mut Logits,
+        n: usize,
+    ) -> Result<SampleReport, SamplerError> {
+        let token_id = self.sample_token(res, logits)?;
+        SampleReport::from_logits(logits, token_id, n)
+    }
+
+    /// Run the chain like [Sampler::sample] and return all the surviving tokens
+    /// as [RankedCandidates], most probable first.
+    pub fn ranked_candidates(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &mut Logits,
+    ) -> Result<RankedCandidates, SamplerError> {
+        let logits = self.sample(res, logits)?;
+        RankedCandidates::from_logits(logits)
+    }
+
+    /// Dry run of the chain that reports the name of each [Sampler] along with
+    /// the number of tokens that survived after it ran. This is useful for visualizing
+    /// how much each stage filters. Tokens count as surviving if their logit is finite,
+    /// so samplers that eliminate tokens by setting the logit to negative infinity
+    /// are counted as filtering as well.
+    ///
+    /// The chain runs on clones of its samplers and the [Logits], so neither they nor
+    /// the sampler state are changed. Samplers that need the RNG get a separate RNG
+    /// with a fixed seed rather than the one in `res`, and mutable access to
+    /// the last tokens is not available.
+    pub fn stage_survivor_counts(
+        &self,
+        res: &dyn HasSamplerResources,
+        logits: &Logits,
+    ) -> Result<Vec<(&'static str, usize)>, SamplerError> {
+        let mut res = DryRunResources {
+            res,
+            rng: StdRng::seed_from_u64(0),
+        };
+        let mut logits = logits.clone();
+        self.samplers
+            .iter()
+            .map(|sampler| {
+                let mut sampler = sampler.clone();
+                sampler.requires().ensure(&mut logits)?;
+                sampler.sample(&mut res, &mut logits)?;
+                let survivors = logits.iter().filter(|l| l.logit.is_finite()).count();
+                Ok((sampler.name(), survivors))
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "async")]
+    /// Like [Sampler::sample_token] but using resources from an
+    /// [AsyncHasSamplerResources](crate::types::AsyncHasSamplerResources).
+    /// The last tokens and prompt length are fetched once before running the chain.
+    ///
+    /// **Note**: Samplers that need mutable access to the last tokens will
+    /// get a missing resource error.
+    pub async fn sample_token_async<R: crate::types::AsyncHasSamplerResources>(
+        &mut self,
+        res: &mut R,
+        logits: &mut Logits,
+    ) -> Result<Option<TID>, SamplerError> {
+        let mut res = crate::resource::FetchedSamplerResources::fetch(res).await?;
+        self.sample_token(&mut res, logits)
+    }
 }
 
 impl Sampler for SamplerChain {
@@ -44,18 +305,85 @@ impl Sampler for SamplerChain {
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits, SamplerError> {
         self.token = None;
-        self.samplers
-            .iter_mut()
-            .try_fold(logits, |logits, sampler| {
-                let new_logits = sampler.sample(res, logits)?;
-                self.token = sampler.sampled_token_id();
-                Ok(new_logits)
-            })
+        if let ReplayMode::Replay(it) = &mut self.replay {
+            if let Some(token_id) = it.next() {
+                if self.warmup_steps > 0 {
+                    self.position.step(res)?;
+                }
+                if let Some(tid) = token_id {
+                    if !logits.iter().any(|l| l.token_id == tid) {
+                        Err(SamplerError::InternalError(format!(
+                            "Replayed token id {tid} isn't in the logits"
+                        )))?
+                    }
+                }
+                self.token = token_id;
+                return Ok(logits);
+            }
+            self.replay = ReplayMode::Off;
+        }
+        let logits = self.sample_inner(res, logits)?;
+        if let ReplayMode::Record(log) = &mut self.replay {
+            log.push(self.token);
+        }
+        Ok(logits)
     }
 
     fn sampled_token_id(&self) -> Option<TID> {
         self.token
     }
+
+    fn options_summary(&self) -> Option<String> {
+        Some(format!("({self})"))
+    }
+
+    fn is_stochastic(&self) -> bool {
+        self.samplers.iter().any(|sampler| sampler.is_stochastic())
+    }
+
+    /// The chain makes sure the requirements of each of its samplers are met,
+    /// so it only needs the requirements of the first one.
+    fn requires(&self) -> SampleRequirements {
+        self.samplers
+            .first()
+            .map_or(SampleRequirements::NONE, |sampler| sampler.requires())
+    }
+
+    /// Forwards the notification to each of the samplers in the chain.
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.position.context_shift(dropped);
+        self.samplers
+            .iter_mut()
+            .for_each(|sampler| sampler.notify_context_shift(dropped));
+    }
+
+    fn reset(&mut self) {
+        SamplerChain::reset(self);
+    }
+}
+
+/// Displays the samplers in the order they run along with their current option values.
+/// For example: `SampleTopK [k: 40, min_keep: 1] -> SampleRandDistrib`
+impl Display for SamplerChain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.samplers
+            .iter()
+            .enumerate()
+            .try_for_each(|(idx, sampler)| {
+                if idx > 0 {
+                    f.write_str(" -> ")?;
+                }
+                f.write_str(sampler.name())?;
+                match sampler.options_summary() {
+                    Some(summary) => write!(f, " {summary}"),
+                    None => Ok(()),
+                }
+            })?;
+        if self.warmup_steps > 0 {
+            write!(f, " (warmup_steps: {})", self.warmup_steps)?;
+        }
+        Ok(())
+    }
 }
 
 impl<Rhs> AddAssign<Rhs> for SamplerChain
@@ -78,3 +406,62 @@ where
         self
     }
 }
+
+impl FromIterator<Box<dyn Sampler>> for SamplerChain {
+    fn from_iter<I: IntoIterator<Item = Box<dyn Sampler>>>(iter: I) -> Self {
+        Self {
+            samplers: iter.into_iter().collect(),
+            token: None,
+            warmup_steps: 0,
+            position: PositionAware::new(),
+            replay: ReplayMode::Off,
+        }
+    }
+}
+
+/// Appends the samplers after the existing ones, in order.
+impl Extend<Box<dyn Sampler>> for SamplerChain {
+    fn extend<I: IntoIterator<Item = Box<dyn Sampler>>>(&mut self, iter: I) {
+        self.token = None;
+        self.samplers.extend(iter);
+    }
+}
+
+impl IntoIterator for SamplerChain {
+    type Item = Box<dyn Sampler>;
+    type IntoIter = std::vec::IntoIter<Box<dyn Sampler>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.samplers.into_iter()
+    }
+}
+
+/// Resources for [SamplerChain::stage_survivor_counts] that don't allow
+/// changing the wrapped resources.
+#[derive(Debug)]
+struct DryRunResources<'a> {
+    res: &'a dyn HasSamplerResources,
+    rng: StdRng,
+}
+
+impl HasSamplerResources for DryRunResources<'_> {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        fun(&mut self.rng);
+        Ok(())
+    }
+
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&Vec<TID>)) -> Result<(), SamplerError> {
+        self.res.with_last_tokens(fun)
+    }
+
+    fn with_prompt_len(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        self.res.with_prompt_len(fun)
+    }
+
+    fn with_penalty_last_n(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        self.res.with_penalty_last_n(fun)
+    }
+}
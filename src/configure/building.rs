@@ -29,21 +29,68 @@ where
     UI: ConfigurableNumValue,
     F: ConfigurableNumValue,
 {
+    /// Clone the sampler into a new [Box].
+    fn clone_buildable(&self) -> Box<dyn BuildableSampler<UI, F>>;
 }
 
 impl<T, UI, F> BuildableSampler<UI, F> for T
 where
-    Self: Sampler + ConfigurableSampler<UI, F> + Send + Sync + std::fmt::Debug + 'static,
+    Self: Sampler + ConfigurableSampler<UI, F> + Clone + Send + Sync + std::fmt::Debug + 'static,
     UI: ConfigurableNumValue,
     F: ConfigurableNumValue,
 {
+    fn clone_buildable(&self) -> Box<dyn BuildableSampler<UI, F>> {
+        Box::new(self.clone())
+    }
 }
 
-impl<UI, F> Sampler for Box<dyn BuildableSampler<UI, F>> {
+impl<UI, F> Clone for Box<dyn BuildableSampler<UI, F>>
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+{
+    fn clone(&self) -> Self {
+        (**self).clone_buildable()
+    }
+}
+
+impl<UI, F> Sampler for Box<dyn BuildableSampler<UI, F>>
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+{
     fn sampled_token_id(&self) -> Option<TID> {
         (**self).sampled_token_id()
     }
 
+    fn is_stochastic(&self) -> bool {
+        (**self).is_stochastic()
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        (**self).requires()
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn options_summary(&self) -> Option<String> {
+        (**self).options_summary()
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        (**self).metadata()
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        (**self).notify_context_shift(dropped)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
     fn sample_token(
         &mut self,
         res: &mut dyn HasSamplerResources,
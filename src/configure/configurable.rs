@@ -53,7 +53,7 @@ where
     }
 
     /// Gets an option by name.
-    fn get_option(&self, key: &str) -> Result<SamplerOptionValue> {
+    fn get_option(&self, key: &str) -> Result<SamplerOptionValue<'_>> {
         configurable_sampler::get_option(self, key)
     }
 
@@ -63,7 +63,8 @@ where
     /// `key1=value1:key2=value2:keyN=valueN`
     ///
     /// The key be a prefix of the option name as long as it's not
-    /// ambiguous. It's also possible to just specify the value,
+    /// ambiguous. A key that exactly matches an option name always
+    /// selects that option. It's also possible to just specify the value,
     /// which is equivalent to `=value` (i.e. a blank key name).
     ///
     /// Values in this default implementation cannot contain `=` or `:`
@@ -1,3 +1,5 @@
+use std::fmt::{Display, Formatter};
+
 use anyhow::Result;
 
 use super::*;
@@ -13,6 +15,10 @@ pub struct SamplerOptionMetadata {
 
     /// The type of option.
     pub option_type: SamplerOptionType,
+
+    /// Whether the option is advanced or rarely changed, for example `min_keep`.
+    /// Front-ends may want to hide these by default.
+    pub advanced: bool,
 }
 
 /// Structure that defines a sampler's metadata.
@@ -23,6 +29,26 @@ pub struct SamplerMetadata {
     pub options: Vec<SamplerOptionMetadata>,
 }
 
+/// Displays the sampler name, the description if available and the option types.
+/// For example: `top-p: <description> [p: float, min_keep: uint]`
+impl Display for SamplerMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)?;
+        if let Some(desc) = self.description {
+            write!(f, ": {desc}")?;
+        }
+        if self.options.is_empty() {
+            return Ok(());
+        }
+        f.write_str(" [")?;
+        self.options.iter().enumerate().try_for_each(|(idx, omd)| {
+            let sep = if idx == 0 { "" } else { ", " };
+            write!(f, "{sep}{}: {}", omd.key, omd.option_type)
+        })?;
+        f.write_str("]")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SamplerOptions<T>(Vec<(SamplerOptionMetadata, Option<T>)>);
 
@@ -40,6 +66,23 @@ impl<T> std::ops::DerefMut for SamplerOptions<T> {
     }
 }
 
+/// Displays the options with their values, for example: `[p: 0.9, min_keep: 1]`
+///
+/// Options where the value isn't available are displayed as just the key.
+impl<T: Display> Display for SamplerOptions<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        self.iter().enumerate().try_for_each(|(idx, (omd, val))| {
+            let sep = if idx == 0 { "" } else { ", " };
+            match val {
+                Some(val) => write!(f, "{sep}{}: {val}", omd.key),
+                None => write!(f, "{sep}{}", omd.key),
+            }
+        })?;
+        f.write_str("]")
+    }
+}
+
 impl<T> Default for SamplerOptions<T> {
     fn default() -> Self {
         Self(Vec::default())
@@ -71,6 +114,10 @@ impl<T> SamplerOptions<T> {
         key: &str,
     ) -> Result<(SamplerOptionMetadata, Option<usize>)> {
         let key = key.trim();
+        // An exact match wins even when the key is also a prefix of other keys.
+        if let Some((idx, (omd, acc))) = self.iter().enumerate().find(|(_idx, (omd, _acc))| omd.key == key) {
+            return Ok((omd.clone(), acc.is_some().then_some(idx)));
+        }
         let mut it = self.iter().enumerate().filter(|&(_idx, (omd, _acc))| omd.key
                 .starts_with(key)).map(|(idx, (omd, acc))| (omd.clone(), acc.is_some().then_some(idx)));
         let Some((optdef, optidx)) = it.next() else {
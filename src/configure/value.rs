@@ -1,4 +1,8 @@
-use std::{borrow::Cow, str::FromStr};
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
 
 use anyhow::Result;
 use num_traits::{Float, FromPrimitive, NumCast};
@@ -21,6 +25,17 @@ pub enum SamplerOptionValue<'a, UI = u64, F = f64> {
     String(Cow<'a, str>),
 }
 
+impl<UI: Display, F: Display> Display for SamplerOptionValue<'_, UI, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UInt(v) => v.fmt(f),
+            Self::Float(v) => v.fmt(f),
+            Self::Bool(v) => v.fmt(f),
+            Self::String(v) => v.fmt(f),
+        }
+    }
+}
+
 /// Enum that holds a mutable reference to a sampler option value.
 /// It's only necessary to worry about this when writing your own samplers and
 /// implementing option parsing/handling.
@@ -55,6 +70,17 @@ pub enum SamplerOptionType {
     String,
 }
 
+impl Display for SamplerOptionType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::UInt => "uint",
+            Self::Float => "float",
+            Self::Bool => "bool",
+            Self::String => "string",
+        })
+    }
+}
+
 /// Numeric values that can be used for configuring samplers.
 pub trait ConfigurableNumValue: 'static + Copy + NumCast + FromPrimitive {}
 impl<T> ConfigurableNumValue for T where T: 'static + Copy + NumCast + FromPrimitive {}
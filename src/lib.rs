@@ -135,6 +135,12 @@ mod chain;
 /// Sampler resources
 mod resource;
 
+/// Sampling reports
+mod report;
+
+/// Generation step tracking
+mod position;
+
 /// Configuring sampler options
 pub mod configure;
 
@@ -144,11 +150,41 @@ mod tests;
 /// Convenient rexports. The simplest way to use the crate is to just throw a
 /// `use llm_samplers::prelude::*;`
 /// into your project.
+///
+/// This includes the [Sampler](crate::types::Sampler) trait, [SamplerChain](crate::types::SamplerChain),
+/// [Logits](crate::types::Logits), the error types, resources, all the samplers and the traits needed
+/// to configure them. Less commonly used items like the sampler builder live in their own modules.
+///
+/// ```rust
+/// use llm_samplers::prelude::*;
+///
+/// let mut sc = SamplerChain::new()
+///     + SampleTopK::new(2, 1)
+///     + SampleTemperature::new(0.8)
+///     + SampleGreedy::new();
+///
+/// let mut temp = SampleTemperature::default();
+/// ConfigurableSampler::<usize, f32>::set_option(
+///     &mut temp,
+///     "temperature",
+///     SamplerOptionValue::Float(0.5),
+/// )?;
+/// assert_eq!(HasSamplerMetadata::<usize, f32>::sampler_metadata(&temp).name, "temperature");
+///
+/// let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+/// let token: Option<TID> = sc.sample_token(&mut NilSamplerResources, &mut logits)?;
+/// assert_eq!(token, Some(3));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
 pub mod prelude {
     #[doc(inline)]
     pub use crate::{
-        chain::*,
-        configure::{ConfigurableSampler, ConfigureSamplerError},
+        configure::{
+            ConfigurableSampler, ConfigureSamplerError, HasSamplerMetadata, SamplerMetadata,
+            SamplerOptionValue,
+        },
+        position::*,
+        report::*,
         resource::*,
         samplers::*,
         types::*,
@@ -0,0 +1,76 @@
+use crate::types::{HasSamplerResources, SamplerError, TID};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Helper for samplers that need to know the current generation step, for example
+/// to only act at specific positions.
+///
+/// The step is determined from the length of the last tokens resource: the length
+/// the first time [PositionAware::step] is called (or the first time after
+/// [PositionAware::reset]) is step `0`. If the host drops tokens from the start of
+/// the last tokens, call [PositionAware::context_shift] so the step stays correct.
+///
+/// It can also be used as a cursor over the last tokens with [PositionAware::new_tokens],
+/// for samplers that consume the tokens selected since they last ran.
+///
+/// Samplers that use this expose it with `position` and `set_position` methods, so they
+/// can be checkpointed mid-generation. With the `serde` feature it can be serialized.
+pub struct PositionAware {
+    start_len: Option<usize>,
+    dropped: usize,
+}
+
+impl PositionAware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start over. The next call to [PositionAware::step] will return `0`.
+    pub fn reset(&mut self) -> &mut Self {
+        self.start_len = None;
+        self.dropped = 0;
+        self
+    }
+
+    /// Account for `dropped` tokens being removed from the start of the last tokens.
+    /// This has no effect before the first call to [PositionAware::step].
+    pub fn context_shift(&mut self, dropped: usize) -> &mut Self {
+        if self.start_len.is_some() {
+            self.dropped += dropped;
+        }
+        self
+    }
+
+    /// Returns the current generation step. Requires the last tokens resource.
+    pub fn step(&mut self, res: &dyn HasSamplerResources) -> Result<usize, SamplerError> {
+        let mut len = 0;
+        res.with_last_tokens(&mut |tokens| len = tokens.len())?;
+        let start_len = *self.start_len.get_or_insert(len);
+        Ok((len + self.dropped).saturating_sub(start_len))
+    }
+
+    /// Calls `fun` with the tokens added to the last tokens since the previous call, then
+    /// starts over from the current length, so [PositionAware::step] also counts from here.
+    /// The tokens already present the first time (or the first time after
+    /// [PositionAware::reset]), for example the prompt, are skipped. Tokens dropped with
+    /// [PositionAware::context_shift] in between are accounted for. Requires the last
+    /// tokens resource.
+    pub fn new_tokens(
+        &mut self,
+        res: &dyn HasSamplerResources,
+        fun: &mut dyn FnMut(&[TID]),
+    ) -> Result<(), SamplerError> {
+        let Self { start_len, dropped } = *self;
+        let mut len = 0;
+        res.with_last_tokens(&mut |tokens| {
+            len = tokens.len();
+            let start = start_len
+                .map_or(len, |sl| sl.saturating_sub(dropped))
+                .min(len);
+            fun(&tokens[start..]);
+        })?;
+        self.start_len = Some(len);
+        self.dropped = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use crate::types::{Logit, Logits, SamplerError, L, TID};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// A record of a sampling step: the token that was selected (if any) along
+/// with the most probable alternatives that survived the samplers. Suitable
+/// for logging or displaying alternatives to a user.
+///
+/// Serializable when the `serde` feature is enabled.
+pub struct SampleReport {
+    /// The selected token, if one was selected.
+    pub chosen: Option<Logit>,
+    /// The top surviving candidates, most probable first.
+    pub alternatives: Vec<Logit>,
+}
+
+impl SampleReport {
+    /// Build a report from [Logits] after sampling has completed.
+    ///
+    /// The probabilities are renormalized over the surviving [Logits], so
+    /// this may sort them.
+    pub fn from_logits(
+        logits: &mut Logits,
+        token_id: Option<TID>,
+        n: usize,
+    ) -> Result<Self, SamplerError> {
+        let alternatives = logits.top_n(n)?.to_vec();
+        let chosen = token_id.and_then(|tid| logits.iter().find(|l| l.token_id == tid).cloned());
+        Ok(Self {
+            chosen,
+            alternatives,
+        })
+    }
+
+    /// The selected token id, if any.
+    pub fn token_id(&self) -> Option<TID> {
+        self.chosen.as_ref().map(|l| l.token_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// A candidate token in [RankedCandidates].
+pub struct Candidate {
+    /// The token id.
+    pub token: TID,
+    /// The logit for the token.
+    pub logit: L,
+    /// The probability of the token, normalized over the candidates.
+    pub prob: L,
+    /// The position of the candidate, starting from `0` for the most probable one.
+    pub rank: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// The tokens that survived sampling, most probable first. Useful for
+/// building autocomplete or suggestion interfaces.
+///
+/// Serializable when the `serde` feature is enabled.
+pub struct RankedCandidates(Vec<Candidate>);
+
+impl RankedCandidates {
+    /// Build the candidates from [Logits] after sampling has completed.
+    ///
+    /// This will ensure the [Logits] are sorted and have softmax applied.
+    pub fn from_logits(logits: &mut Logits) -> Result<Self, SamplerError> {
+        logits.ensure_softmax()?;
+        Ok(Self(
+            logits
+                .iter()
+                .enumerate()
+                .map(|(rank, l)| Candidate {
+                    token: l.token_id,
+                    logit: l.logit,
+                    prob: l.prob,
+                    rank,
+                })
+                .collect(),
+        ))
+    }
+
+    /// Consume the candidates and return the underlying [Vec].
+    pub fn into_vec(self) -> Vec<Candidate> {
+        self.0
+    }
+}
+
+impl std::ops::Deref for RankedCandidates {
+    type Target = [Candidate];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// The token selected at each step of a generation, recorded by a
+/// [SamplerChain](crate::prelude::SamplerChain). Replaying it reproduces the
+/// same tokens regardless of the RNG, which is useful for capturing a problematic
+/// generation for a bug report.
+///
+/// Serializable when the `serde` feature is enabled.
+pub struct SampleLog(Vec<Option<TID>>);
+
+impl SampleLog {
+    /// Consume the log and return the underlying [Vec].
+    pub fn into_vec(self) -> Vec<Option<TID>> {
+        self.0
+    }
+
+    pub(crate) fn push(&mut self, token_id: Option<TID>) {
+        self.0.push(token_id)
+    }
+}
+
+impl std::ops::Deref for SampleLog {
+    type Target = [Option<TID>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromIterator<Option<TID>> for SampleLog {
+    fn from_iter<I: IntoIterator<Item = Option<TID>>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
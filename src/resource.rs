@@ -24,6 +24,20 @@ pub trait HasSamplerResources: Debug {
     ) -> Result<(), SamplerError> {
         Err(SamplerError::MissingResource("last_tokens".to_string()))
     }
+
+    /// Allows a sampler to access the length of the prompt (if present). This is
+    /// the number of items at the start of the last tokens that came from the prompt
+    /// rather than being generated.
+    fn with_prompt_len(&self, _fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        Err(SamplerError::MissingResource("prompt_len".to_string()))
+    }
+
+    /// Allows a sampler to access the penalty window length (if present). When this
+    /// is available, penalty samplers use it instead of their own `last_n` value so the
+    /// window can change per request without rebuilding the samplers.
+    fn with_penalty_last_n(&self, _fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        Err(SamplerError::MissingResource("penalty_last_n".to_string()))
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -46,6 +60,10 @@ pub struct SimpleSamplerResources {
     pub(crate) rng: Option<Box<dyn rand::RngCore + Send + Sync>>,
 
     pub(crate) last_tokens: Option<Vec<TID>>,
+
+    pub(crate) prompt_len: Option<usize>,
+
+    pub(crate) penalty_last_n: Option<usize>,
 }
 
 impl Debug for SimpleSamplerResources {
@@ -53,6 +71,8 @@ impl Debug for SimpleSamplerResources {
         f.debug_struct("SamplerResources")
             .field("rng", &self.rng.is_some())
             .field("last_tokens", &self.last_tokens)
+            .field("prompt_len", &self.prompt_len)
+            .field("penalty_last_n", &self.penalty_last_n)
             .finish()
     }
 }
@@ -62,7 +82,25 @@ impl SimpleSamplerResources {
         rng: Option<Box<dyn rand::RngCore + Send + Sync>>,
         last_tokens: Option<Vec<TID>>,
     ) -> Self {
-        Self { rng, last_tokens }
+        Self {
+            rng,
+            last_tokens,
+            prompt_len: None,
+            penalty_last_n: None,
+        }
+    }
+
+    /// Set the number of items at the start of the last tokens that came
+    /// from the prompt.
+    pub fn set_prompt_len(&mut self, prompt_len: Option<usize>) -> &mut Self {
+        self.prompt_len = prompt_len;
+        self
+    }
+
+    /// Set the penalty window length, overriding the `last_n` value of penalty samplers.
+    pub fn set_penalty_last_n(&mut self, penalty_last_n: Option<usize>) -> &mut Self {
+        self.penalty_last_n = penalty_last_n;
+        self
     }
 }
 
@@ -102,4 +140,172 @@ impl HasSamplerResources for SimpleSamplerResources {
             },
         )
     }
+
+    fn with_prompt_len(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        self.prompt_len.map_or_else(
+            || Err(SamplerError::MissingResource("prompt_len".to_string())),
+            |pl| {
+                fun(pl);
+                Ok(())
+            },
+        )
+    }
+
+    fn with_penalty_last_n(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        self.penalty_last_n.map_or_else(
+            || Err(SamplerError::MissingResource("penalty_last_n".to_string())),
+            |n| {
+                fun(n);
+                Ok(())
+            },
+        )
+    }
+}
+
+/// Resolve the penalty window length: the `penalty_last_n` resource if it's
+/// available, otherwise `default`.
+pub(crate) fn resolve_penalty_last_n(
+    res: &dyn HasSamplerResources,
+    default: usize,
+) -> Result<usize, SamplerError> {
+    let mut last_n = default;
+    match res.with_penalty_last_n(&mut |n| last_n = n) {
+        Ok(()) | Err(SamplerError::MissingResource(_)) => Ok(last_n),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(feature = "async")]
+/// Trait for providing resources to samplers from asynchronous sources, for example
+/// when the token history lives in a database or behind a network call.
+///
+/// This is used by [crate::types::SamplerChain::sample_token_async]: the async resources
+/// are fetched once up front and then exposed to the samplers in the chain through
+/// [HasSamplerResources] as usual, so samplers don't need to do anything special
+/// to support this.
+///
+/// Implementations can use `async fn` for the async methods.
+pub trait AsyncHasSamplerResources: Send {
+    /// Allows a sampler to mutably access the RNG (if present).
+    fn with_rng_mut(
+        &mut self,
+        _fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        Err(SamplerError::MissingResource("rng".to_string()))
+    }
+
+    /// Fetch the last tokens (if present).
+    fn last_tokens(
+        &mut self,
+    ) -> impl std::future::Future<Output = Result<Vec<TID>, SamplerError>> + Send {
+        async { Err(SamplerError::MissingResource("last_tokens".to_string())) }
+    }
+
+    /// Fetch the length of the prompt (if present). See [HasSamplerResources::with_prompt_len].
+    fn prompt_len(
+        &mut self,
+    ) -> impl std::future::Future<Output = Result<usize, SamplerError>> + Send {
+        async { Err(SamplerError::MissingResource("prompt_len".to_string())) }
+    }
+
+    /// Fetch the penalty window length (if present).
+    /// See [HasSamplerResources::with_penalty_last_n].
+    fn penalty_last_n(
+        &mut self,
+    ) -> impl std::future::Future<Output = Result<usize, SamplerError>> + Send {
+        async { Err(SamplerError::MissingResource("penalty_last_n".to_string())) }
+    }
+}
+
+#[cfg(feature = "async")]
+/// Resources fetched from an [AsyncHasSamplerResources] so they can be
+/// passed to synchronous samplers.
+pub(crate) struct FetchedSamplerResources<'a, R: AsyncHasSamplerResources> {
+    pub(crate) res: &'a mut R,
+
+    pub(crate) last_tokens: Option<Vec<TID>>,
+
+    pub(crate) prompt_len: Option<usize>,
+
+    pub(crate) penalty_last_n: Option<usize>,
+}
+
+#[cfg(feature = "async")]
+impl<'a, R: AsyncHasSamplerResources> FetchedSamplerResources<'a, R> {
+    /// Fetch the async resources. Missing resources aren't an error here, only
+    /// when a sampler actually tries to use them.
+    pub(crate) async fn fetch(res: &'a mut R) -> Result<Self, SamplerError> {
+        let last_tokens = match res.last_tokens().await {
+            Ok(lt) => Some(lt),
+            Err(SamplerError::MissingResource(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let prompt_len = match res.prompt_len().await {
+            Ok(pl) => Some(pl),
+            Err(SamplerError::MissingResource(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let penalty_last_n = match res.penalty_last_n().await {
+            Ok(n) => Some(n),
+            Err(SamplerError::MissingResource(_)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            res,
+            last_tokens,
+            prompt_len,
+            penalty_last_n,
+        })
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncHasSamplerResources> Debug for FetchedSamplerResources<'_, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FetchedSamplerResources")
+            .field("last_tokens", &self.last_tokens)
+            .field("prompt_len", &self.prompt_len)
+            .field("penalty_last_n", &self.penalty_last_n)
+            .finish()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncHasSamplerResources> HasSamplerResources for FetchedSamplerResources<'_, R> {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        self.res.with_rng_mut(fun)
+    }
+
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&Vec<TID>)) -> Result<(), SamplerError> {
+        self.last_tokens.as_ref().map_or_else(
+            || Err(SamplerError::MissingResource("last_tokens".to_string())),
+            |lt| {
+                fun(lt);
+                Ok(())
+            },
+        )
+    }
+
+    fn with_prompt_len(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        self.prompt_len.map_or_else(
+            || Err(SamplerError::MissingResource("prompt_len".to_string())),
+            |pl| {
+                fun(pl);
+                Ok(())
+            },
+        )
+    }
+
+    fn with_penalty_last_n(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        self.penalty_last_n.map_or_else(
+            || Err(SamplerError::MissingResource("penalty_last_n".to_string())),
+            |n| {
+                fun(n);
+                Ok(())
+            },
+        )
+    }
 }
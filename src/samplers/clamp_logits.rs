@@ -0,0 +1,169 @@
+use crate::{configure::*, types::*};
+
+/// # Clamp logits sampling
+/// Clamps every logit into the range `min..=max`. Some models (quantized ones in
+/// particular) can produce extreme outliers, this keeps them from dominating later
+/// processing. It's intended to run on the raw logits, before other samplers.
+///
+/// Negative infinity is how tokens get eliminated, so those logits are left alone.
+/// NaN logits are handled according to `nan_policy` first. The NaN policy can only be
+/// set with [SampleClampLogits::nan_policy], it isn't exposed as a configurable option.
+///
+/// The bounds are checked when sampling: a NaN bound or `min` greater than `max` is an
+/// error.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `min`: The lowest allowed logit. (default: `-100.0`)
+/// - `max`: The highest allowed logit. (default: `100.0`)
+/// - `nan_policy`: How to handle NaN logits. (default: [NanPolicy::Error])
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleClampLogits {
+    pub(crate) min: L,
+    pub(crate) max: L,
+    pub(crate) nan_policy: NanPolicy,
+}
+
+impl Default for SampleClampLogits {
+    fn default() -> Self {
+        Self {
+            min: -100f32,
+            max: 100f32,
+            nan_policy: NanPolicy::default(),
+        }
+    }
+}
+
+impl SampleClampLogits {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleClampLogits::try_new].
+    pub fn new(min: L, max: L) -> Self {
+        Self {
+            min,
+            max,
+            nan_policy: NanPolicy::default(),
+        }
+    }
+
+    /// Like [SampleClampLogits::new] but the parameters are validated: `min` can't be NaN
+    /// and `max` must be at least `min`.
+    pub fn try_new(min: L, max: L) -> Result<Self, SamplerError> {
+        Self::check_bounds(min, max)?;
+        Ok(Self::new(min, max))
+    }
+
+    /// This runs on every step, so the requirement for `max` is only formatted when
+    /// the bounds are actually invalid.
+    fn check_bounds(min: L, max: L) -> Result<(), SamplerError> {
+        check_option("min", !min.is_nan(), "a number", min)?;
+        if max < min || max.is_nan() {
+            check_option("max", false, &format!("at least {min}"), max)?;
+        }
+        Ok(())
+    }
+
+    pub fn min(mut self, val: L) -> Self {
+        self.min = val;
+        self
+    }
+
+    pub fn max(mut self, val: L) -> Self {
+        self.max = val;
+        self
+    }
+
+    pub fn nan_policy(mut self, val: NanPolicy) -> Self {
+        self.nan_policy = val;
+        self
+    }
+}
+
+impl Sampler for SampleClampLogits {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let Self {
+            min,
+            max,
+            nan_policy,
+        } = *self;
+        Self::check_bounds(min, max)?;
+        logits.apply_nan_policy(nan_policy)?;
+        let mut changed = false;
+
+        logits
+            .iter_mut()
+            .filter(|l| l.logit != f32::NEG_INFINITY && (l.logit < min || l.logit > max))
+            .for_each(|l| {
+                l.logit = l.logit.clamp(min, max);
+                changed = true;
+            });
+        // Clamping keeps the order, so only the softmax is affected.
+        if changed {
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+}
+
+// FIXME: Find a sane way to implement this for the NaN policy.
+impl ConfigurableSampler<usize, L> for SampleClampLogits {}
+
+impl HasSamplerMetadata<usize, L> for SampleClampLogits {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "clamp logits",
+            description: Some("Clamps every logit into the range min to max."),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "min",
+                    description: Some("The lowest allowed logit."),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "max",
+                    description: Some("The highest allowed logit."),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.min)),
+                    Some(SamplerOptionValueMut::Float(&mut self.max)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.min)),
+                    Some(SamplerOptionValue::Float(self.max)),
+                ],
+            )
+        }
+    }
+}
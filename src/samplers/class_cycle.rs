@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use crate::{configure::*, prelude::PositionAware, types::*};
+
+/// # Class cycle sampling
+/// Takes a mapping from token ids to classes and a sequence of classes, and only allows
+/// tokens from the class expected at the current step. The sequence repeats, so with
+/// classes `[0, 1]` the output alternates between tokens of class `0` and class `1`. This
+/// is useful for highly structured output, for example filling a template that alternates
+/// between literal and variable tokens.
+///
+/// Tokens that aren't in the expected class, including tokens without a class, get
+/// their logit set to negative infinity. If no token in the [Logits] is in the expected
+/// class an error is returned.
+///
+/// The step is tracked with [PositionAware] from the length of the last tokens resource:
+/// the length when the sampler first runs (or first runs after [SampleClassCycle::reset])
+/// is step `0`.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `classes`: Token id to class pairs. (default: empty)
+/// - `cycle`: The classes to allow, in order. Does nothing when empty. (default: empty)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleClassCycle {
+    pub(crate) classes: HashMap<TID, usize>,
+    pub(crate) cycle: Vec<usize>,
+    position: PositionAware,
+}
+
+impl SampleClassCycle {
+    pub fn new<I: IntoIterator<Item = (TID, usize)>, C: IntoIterator<Item = usize>>(
+        classes: I,
+        cycle: C,
+    ) -> Self {
+        Self {
+            classes: HashMap::from_iter(classes),
+            cycle: Vec::from_iter(cycle),
+            position: PositionAware::new(),
+        }
+    }
+
+    /// Start over. The next time the sampler runs will be step `0`.
+    pub fn reset(&mut self) -> &mut Self {
+        self.position.reset();
+        self
+    }
+
+    /// Returns the position state, see [PositionAware].
+    pub fn position(&self) -> PositionAware {
+        self.position
+    }
+
+    /// Restore the position state, for example one returned by [SampleClassCycle::position].
+    pub fn set_position(&mut self, position: PositionAware) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    /// Returns the class allowed at the specified step, or `None` if the cycle is empty.
+    pub fn class_at(&self, step: usize) -> Option<usize> {
+        if self.cycle.is_empty() {
+            return None;
+        }
+        Some(self.cycle[step % self.cycle.len()])
+    }
+}
+
+impl Sampler for SampleClassCycle {
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        if self.cycle.is_empty() || logits.is_empty() {
+            return Ok(logits);
+        }
+        let step = self.position.step(res)?;
+        let class = self.class_at(step).unwrap_or_default();
+
+        let mut changed = false;
+        let mut any_allowed = false;
+        logits.iter_mut().for_each(|l| {
+            if self.classes.get(&l.token_id) == Some(&class) {
+                any_allowed = true;
+            } else if l.logit != f32::NEG_INFINITY {
+                l.logit = f32::NEG_INFINITY;
+                changed = true;
+            }
+        });
+        if !any_allowed {
+            Err(SamplerError::InternalError(format!(
+                "No token in class {class} for step {step}"
+            )))?
+        }
+        if changed {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.position.context_shift(dropped);
+    }
+
+    fn reset(&mut self) {
+        SampleClassCycle::reset(self);
+    }
+}
+
+// FIXME: Find a sane way to implement this for the mapping and cycle.
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F>
+    for SampleClassCycle
+{
+}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F>
+    for SampleClassCycle
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "class cycle",
+            description: Some(concat!(
+                "Only allows tokens from the class expected at the current step, ",
+                "cycling through a sequence of classes."
+            )),
+            options: vec![],
+        }
+    }
+}
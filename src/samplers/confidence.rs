@@ -0,0 +1,137 @@
+use crate::{configure::*, types::*};
+
+/// # Confidence sampling
+/// When the most probable token's probability is higher than `confidence`, the
+/// [Logits] are truncated to just that token, like greedy sampling. Otherwise they're
+/// left alone for the following samplers. In other words: be deterministic when the model
+/// is sure and sample when it isn't.
+///
+/// When the [Logits] get truncated, the remaining token is also reported as the
+/// sampled token id. Samplers after this one in a chain will only see a single token,
+/// so they'll generally end up selecting it as well.
+///
+/// **Properties**:
+/// - Filters logits
+/// - May select a token
+///
+/// **Parameters**:
+/// - `confidence`: Probability threshold for the most probable token. (default: `1.0`, meaning
+///   disabled)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleConfidence {
+    pub(crate) confidence: L,
+    token: Option<TID>,
+}
+
+impl Default for SampleConfidence {
+    fn default() -> Self {
+        Self {
+            confidence: 1f32,
+            token: None,
+        }
+    }
+}
+
+impl SampleConfidence {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleConfidence::try_new].
+    pub fn new(confidence: L) -> Self {
+        Self {
+            confidence,
+            token: None,
+        }
+    }
+
+    /// Like [SampleConfidence::new] but the parameters are validated: `confidence` must be
+    /// between `0.0` and `1.0`.
+    pub fn try_new(confidence: L) -> Result<Self, SamplerError> {
+        check_option(
+            "confidence",
+            (0f32..=1f32).contains(&confidence),
+            "between 0.0 and 1.0",
+            confidence,
+        )?;
+        Ok(Self::new(confidence))
+    }
+
+    pub fn confidence(mut self, val: L) -> Self {
+        self.confidence = val;
+        self
+    }
+}
+
+impl Sampler for SampleConfidence {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        self.token = None;
+        if logits.is_empty() || self.confidence >= 1f32 {
+            return Ok(logits);
+        }
+        logits.ensure_softmax().map_err(|e| {
+            SamplerError::InternalError(format!("Failed to ensure softmax before sampling: {}", e))
+        })?;
+        if logits[0].prob > self.confidence {
+            logits.truncate(1);
+            logits[0].prob = 1f32;
+            self.token = Some(logits[0].token_id);
+        }
+        Ok(logits)
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(self.confidence < 1f32)
+    }
+
+    fn sampled_token_id(&self) -> Option<TID> {
+        self.token
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleConfidence {}
+
+impl HasSamplerMetadata<usize, L> for SampleConfidence {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "confidence",
+            description: Some(concat!(
+                "When the most probable token's probability is higher than confidence, ",
+                "only that token is kept."
+            )),
+            options: vec![SamplerOptionMetadata {
+                key: "confidence",
+                description: Some("Probability threshold for the most probable token."),
+                option_type: SamplerOptionType::Float,
+                advanced: false,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValueMut::Float(&mut self.confidence))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValue::Float(self.confidence))],
+            )
+        }
+    }
+}
@@ -7,7 +7,8 @@ use crate::{configure::*, types::*};
 /// tokens.
 ///
 /// This sampler implements [std::ops::Deref] and [std::ops::DerefMut] to the
-/// internal [Vec] so you can freely manipulate the bias list.
+/// internal [Vec] so you can freely manipulate the bias list. Large bias tables can be
+/// loaded with [SampleFlatBias::from_reader].
 ///
 /// **Properties**:
 /// - Modifies logits
@@ -41,9 +42,47 @@ impl SampleFlatBias {
             bias: Vec::from_iter(it),
         }
     }
+
+    /// Construct the sampler from a bias table with one `token_id<TAB>bias` entry
+    /// per line. Empty lines and lines starting with `#` are ignored.
+    ///
+    /// An error is returned if reading fails or a line can't be parsed, in which
+    /// case the message includes the line number.
+    pub fn from_reader<R: std::io::BufRead>(reader: R) -> Result<Self, SamplerError> {
+        let mut bias = vec![];
+        for (idx, line) in reader.lines().enumerate() {
+            let line_num = idx + 1;
+            let line = line.map_err(|e| {
+                SamplerError::InternalError(format!(
+                    "Failed to read bias table line {line_num}: {e}"
+                ))
+            })?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line.split_once('\t').and_then(|(tid, bv)| {
+                Some((
+                    tid.trim().parse::<TID>().ok()?,
+                    bv.trim().parse::<L>().ok()?,
+                ))
+            });
+            let Some(item) = parsed.filter(|(_tid, bv)| !bv.is_nan()) else {
+                Err(SamplerError::InternalError(format!(
+                    "Failed to parse bias table line {line_num}: expected token_id<TAB>bias but got {line:?}"
+                )))?
+            };
+            bias.push(item);
+        }
+        Ok(Self { bias })
+    }
 }
 
 impl Sampler for SampleFlatBias {
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
@@ -0,0 +1,111 @@
+use crate::{configure::*, prelude::PositionAware, types::*};
+
+/// # Forced prefix sampling
+/// Forces the first generated tokens to be `tokens`, in order. For the first `tokens.len()`
+/// steps, the [Logits] are replaced with just the forced token and it's reported as the
+/// sampled token id. After that, the sampler doesn't do anything.
+///
+/// The current step is tracked with [PositionAware], so this requires the last tokens
+/// resource. Call [SampleForcedPrefix::reset] before starting a new generation.
+///
+/// **Properties**:
+/// - Filters logits
+/// - Selects a token
+///
+/// **Parameters**:
+/// - `tokens`: The token ids to force. (default: empty)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleForcedPrefix {
+    pub(crate) tokens: Vec<TID>,
+    position: PositionAware,
+    token: Option<TID>,
+}
+
+impl SampleForcedPrefix {
+    pub fn new<I: IntoIterator<Item = TID>>(it: I) -> Self {
+        Self {
+            tokens: Vec::from_iter(it),
+            position: PositionAware::new(),
+            token: None,
+        }
+    }
+
+    /// Start over. The next time the sampler runs will force the first token again.
+    pub fn reset(&mut self) -> &mut Self {
+        self.position.reset();
+        self.token = None;
+        self
+    }
+
+    /// Returns the position state, see [PositionAware].
+    pub fn position(&self) -> PositionAware {
+        self.position
+    }
+
+    /// Restore the position state, for example one returned by [SampleForcedPrefix::position].
+    pub fn set_position(&mut self, position: PositionAware) -> &mut Self {
+        self.position = position;
+        self
+    }
+}
+
+impl Sampler for SampleForcedPrefix {
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        self.token = None;
+        let step = self.position.step(res)?;
+        let Some(&token_id) = self.tokens.get(step) else {
+            return Ok(logits);
+        };
+        let logit = logits
+            .iter()
+            .find(|l| l.token_id == token_id)
+            .map_or(0f32, |l| l.logit);
+        logits.clear();
+        logits.push(Logit {
+            token_id,
+            logit,
+            prob: 1f32,
+        });
+        logits.set_sorted(true).set_softmax(true);
+        self.token = Some(token_id);
+        Ok(logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<TID> {
+        self.token
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.position.context_shift(dropped);
+    }
+
+    fn reset(&mut self) {
+        SampleForcedPrefix::reset(self);
+    }
+}
+
+// FIXME: Find a sane way to implement this for the list of tokens.
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F>
+    for SampleForcedPrefix
+{
+}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F>
+    for SampleForcedPrefix
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "forced prefix",
+            description: Some("Forces the first generated tokens to be the specified tokens."),
+            options: vec![],
+        }
+    }
+}
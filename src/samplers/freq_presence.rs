@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::{configure::*, types::*};
+use crate::{configure::*, resource::resolve_penalty_last_n, types::*};
 
 /// # Presence and frequency penalty sampling
 /// The **presence** penalty applies to a token that appears at least once in the `last_n` tokens.
@@ -14,15 +14,22 @@ use crate::{configure::*, types::*};
 /// - Filters logits
 ///
 /// **Parameters**:
-/// - `last_n`: Number of last tokens to consider. (default: `64`)
+/// - `last_n`: Number of last tokens to consider. Overridden by the `penalty_last_n`
+///   resource when it's available. (default: `64`)
 /// - `presence_penalty`: Penalty to apply to tokens that are already present. (default: `0.0`)
 /// - `frequency_penalty`: Penalty to apply to tokens based on frequency. (default: `0.0`)
+/// - `skip_prompt`: Don't penalize tokens that came from the prompt. Requires the
+///   `prompt_len` resource. (default: `false`)
+/// - `exclude`: Token ids that are never penalized, for example newlines or punctuation.
+///   (default: empty)
 
 #[derive(Debug, Clone)]
 pub struct SampleFreqPresence {
     pub(crate) frequency_penalty: L,
     pub(crate) presence_penalty: L,
     pub(crate) last_n: usize,
+    pub(crate) skip_prompt: bool,
+    pub(crate) exclude: HashSet<TID>,
 }
 
 impl Default for SampleFreqPresence {
@@ -31,19 +38,47 @@ impl Default for SampleFreqPresence {
             frequency_penalty: 0f32,
             presence_penalty: 0f32,
             last_n: 64,
+            skip_prompt: false,
+            exclude: HashSet::default(),
         }
     }
 }
 
 impl SampleFreqPresence {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleFreqPresence::try_new].
     pub fn new(frequency_penalty: L, presence_penalty: L, last_n: usize) -> Self {
         Self {
             frequency_penalty,
             presence_penalty,
             last_n,
+            skip_prompt: false,
+            exclude: HashSet::default(),
         }
     }
 
+    /// Like [SampleFreqPresence::new] but the parameters are validated: the penalties must
+    /// be finite.
+    pub fn try_new(
+        frequency_penalty: L,
+        presence_penalty: L,
+        last_n: usize,
+    ) -> Result<Self, SamplerError> {
+        check_option(
+            "frequency_penalty",
+            frequency_penalty.is_finite(),
+            "finite",
+            frequency_penalty,
+        )?;
+        check_option(
+            "presence_penalty",
+            presence_penalty.is_finite(),
+            "finite",
+            presence_penalty,
+        )?;
+        Ok(Self::new(frequency_penalty, presence_penalty, last_n))
+    }
+
     pub fn last_n(mut self, val: usize) -> Self {
         self.last_n = val;
         self
@@ -58,9 +93,27 @@ impl SampleFreqPresence {
         self.presence_penalty = val;
         self
     }
+
+    pub fn skip_prompt(mut self, val: bool) -> Self {
+        self.skip_prompt = val;
+        self
+    }
+
+    pub fn exclude<I: IntoIterator<Item = TID>>(mut self, it: I) -> Self {
+        self.exclude = HashSet::from_iter(it);
+        self
+    }
 }
 
 impl Sampler for SampleFreqPresence {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         res: &mut dyn HasSamplerResources,
@@ -70,8 +123,10 @@ impl Sampler for SampleFreqPresence {
             frequency_penalty,
             presence_penalty,
             last_n,
+            skip_prompt,
             ..
         } = *self;
+        let last_n = resolve_penalty_last_n(res, last_n)?;
 
         if logits.is_empty()
             || last_n == 0
@@ -80,20 +135,30 @@ impl Sampler for SampleFreqPresence {
             return Ok(logits);
         }
 
+        let mut prompt_len = 0;
+        if skip_prompt {
+            res.with_prompt_len(&mut |pl| prompt_len = pl)?;
+        }
+
         let mut counts = HashMap::<TID, L>::default();
         let mut changed = 0;
 
         res.with_last_tokens(&mut |orig_tokens| {
+            let orig_tokens = &orig_tokens[prompt_len.min(orig_tokens.len())..];
             let tokens = if last_n > orig_tokens.len() {
                 orig_tokens
             } else {
                 &orig_tokens[orig_tokens.len() - last_n..]
             };
             counts.reserve(tokens.len());
-            tokens.iter().copied().for_each(|tid| {
-                let cnt = counts.entry(tid).or_insert(0f32);
-                *cnt += 1f32
-            });
+            tokens
+                .iter()
+                .copied()
+                .filter(|tid| !self.exclude.contains(tid))
+                .for_each(|tid| {
+                    let cnt = counts.entry(tid).or_insert(0f32);
+                    *cnt += 1f32
+                });
         })?;
 
         logits.iter_mut().for_each(|l| {
@@ -136,6 +201,7 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                         "3 * frequency_penalty."
                     )),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "presence_penalty",
@@ -144,6 +210,7 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                         "within the last_n tokens."
                     )),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: ("last_n"),
@@ -152,6 +219,15 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                         "determining sequence repetition."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "skip_prompt",
+                    description: Some(
+                        "Don't apply the penalties to tokens that came from the prompt.",
+                    ),
+                    option_type: SamplerOptionType::Bool,
+                    advanced: false,
                 },
             ],
         }
@@ -165,6 +241,7 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                     Some(SamplerOptionValueMut::Float(&mut self.frequency_penalty)),
                     Some(SamplerOptionValueMut::Float(&mut self.presence_penalty)),
                     Some(SamplerOptionValueMut::UInt(&mut self.last_n)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.skip_prompt)),
                 ],
             )
         }
@@ -178,6 +255,7 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                     Some(SamplerOptionValue::Float(self.frequency_penalty)),
                     Some(SamplerOptionValue::Float(self.presence_penalty)),
                     Some(SamplerOptionValue::UInt(self.last_n)),
+                    Some(SamplerOptionValue::Bool(self.skip_prompt)),
                 ],
             )
         }
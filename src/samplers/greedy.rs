@@ -32,6 +32,10 @@ impl std::ops::Deref for SampleGreedy {
 }
 
 impl Sampler for SampleGreedy {
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::{configure::*, types::*};
+
+/// # Grouped top-P sampling
+/// Takes a mapping from token ids to groups and applies [top-p](crate::samplers::SampleTopP)
+/// within each group separately: the probabilities of the tokens in a group are normalized
+/// to the group, then tokens are kept until their cumulative probability is greater or
+/// equal to `p` and at least `min_keep` tokens of the group have been kept. So each group,
+/// for example each domain in multi-domain generation, keeps its own plausible set rather
+/// than one group dominating the global nucleus.
+///
+/// Tokens without a group are treated as one more group.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `min_keep`: Minimum number of entries to keep in each group. (default: `1`)
+/// - `p`: Target value. (default: `0.9`)
+/// - `groups`: Token id to group pairs. (default: empty)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleGroupedTopP {
+    pub(crate) p: L,
+    pub(crate) min_keep: usize,
+    pub(crate) groups: HashMap<TID, usize>,
+}
+
+impl Default for SampleGroupedTopP {
+    fn default() -> Self {
+        Self {
+            p: 0.9f32,
+            min_keep: 1,
+            groups: HashMap::default(),
+        }
+    }
+}
+
+impl SampleGroupedTopP {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleGroupedTopP::try_new].
+    pub fn new<I: IntoIterator<Item = (TID, usize)>>(p: L, min_keep: usize, groups: I) -> Self {
+        Self {
+            p,
+            min_keep,
+            groups: HashMap::from_iter(groups),
+        }
+    }
+
+    /// Like [SampleGroupedTopP::new] but the parameters are validated: `p` must be between
+    /// `0.0` and `1.0` and `min_keep` must be at least `1`.
+    pub fn try_new<I: IntoIterator<Item = (TID, usize)>>(
+        p: L,
+        min_keep: usize,
+        groups: I,
+    ) -> Result<Self, SamplerError> {
+        check_option("p", (0f32..=1f32).contains(&p), "between 0.0 and 1.0", p)?;
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(p, min_keep, groups))
+    }
+
+    pub fn min_keep(mut self, val: usize) -> Self {
+        self.min_keep = val;
+        self
+    }
+
+    pub fn p(mut self, val: L) -> Self {
+        self.p = val;
+        self
+    }
+}
+
+impl Sampler for SampleGroupedTopP {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let Self { p, min_keep, .. } = *self;
+        if logits.is_empty() || p >= 1f32 {
+            return Ok(logits);
+        }
+        logits.ensure_softmax().map_err(|e| {
+            SamplerError::InternalError(format!("Failed to ensure softmax before sampling: {}", e))
+        })?;
+
+        let group = |l: &Logit| self.groups.get(&l.token_id).copied();
+        let mut totals = HashMap::<Option<usize>, L>::default();
+        logits
+            .iter()
+            .for_each(|l| *totals.entry(group(l)).or_default() += l.prob);
+
+        // The logits are sorted, so walking them in order visits each group's tokens
+        // from most to least probable.
+        let mut kept = HashMap::<Option<usize>, (usize, L)>::default();
+        let len = logits.len();
+        logits.retain(|l| {
+            let group = group(l);
+            let (count, cum_sum) = kept.entry(group).or_default();
+            if *count >= min_keep && *cum_sum >= p {
+                return false;
+            }
+            let total = totals[&group];
+            if total > 0f32 {
+                *cum_sum += l.prob / total;
+            }
+            *count += 1;
+            true
+        });
+        if logits.len() != len {
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(self.p < 1f32)
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleGroupedTopP {}
+
+impl HasSamplerMetadata<usize, L> for SampleGroupedTopP {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "grouped top-p",
+            description: Some(concat!(
+                "Applies top-p separately within each group of tokens, ",
+                "so each group keeps its own nucleus."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "p",
+                    description: Some("Target value for cumulative probabilities in each group."),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "min_keep",
+                    description: Some(concat!(
+                        "Minimum number of tokens to keep in each group after sampling. ",
+                        "Setting this to 0 is not recommended."
+                    )),
+                    option_type: SamplerOptionType::UInt,
+                    advanced: true,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.p)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.p)),
+                    Some(SamplerOptionValue::UInt(self.min_keep)),
+                ],
+            )
+        }
+    }
+}
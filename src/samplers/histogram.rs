@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use crate::{configure::*, prelude::PositionAware, types::*};
+
+/// # Histogram sampling
+/// Instrumentation sampler that counts how often each token id gets selected, for
+/// example to check whether a chain over-selects certain tokens. It doesn't change
+/// the [Logits], so it can go anywhere in a chain.
+///
+/// The selected tokens are read from the last tokens resource: each time the sampler
+/// runs, the tokens that were added since the previous run are counted, so this requires
+/// the last tokens resource. The tokens already present the first time the sampler runs
+/// (for example the prompt) are skipped. The token selected in the final step of a run
+/// is counted the next time the sampler runs, or call [SampleHistogram::observe] to
+/// count it right away.
+///
+/// **Properties**:
+/// - (none)
+///
+/// **Parameters**:
+/// - (none)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampleHistogram {
+    counts: HashMap<TID, usize>,
+    position: PositionAware,
+}
+
+impl SampleHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the tokens added to the last tokens since the previous call.
+    pub fn observe(&mut self, res: &dyn HasSamplerResources) -> Result<(), SamplerError> {
+        let Self { counts, position } = self;
+        position.new_tokens(res, &mut |tokens| {
+            tokens
+                .iter()
+                .for_each(|tid| *counts.entry(*tid).or_default() += 1)
+        })
+    }
+
+    /// Returns the number of times `token_id` was selected.
+    pub fn count(&self, token_id: TID) -> usize {
+        self.counts.get(&token_id).copied().unwrap_or_default()
+    }
+
+    /// Returns the selection counts by token id.
+    pub fn counts(&self) -> &HashMap<TID, usize> {
+        &self.counts
+    }
+
+    /// Returns the total number of selections counted.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Returns up to `n` of the most frequently selected token ids with their counts,
+    /// most frequent first. Ties are ordered by token id.
+    pub fn most_common(&self, n: usize) -> Vec<(TID, usize)> {
+        let mut counts = self
+            .counts
+            .iter()
+            .map(|(tid, cnt)| (*tid, *cnt))
+            .collect::<Vec<_>>();
+        counts.sort_unstable_by(|(a_tid, a_cnt), (b_tid, b_cnt)| {
+            b_cnt.cmp(a_cnt).then(a_tid.cmp(b_tid))
+        });
+        counts.truncate(n);
+        counts
+    }
+
+    /// Clear the counts and start over.
+    pub fn reset(&mut self) -> &mut Self {
+        self.counts.clear();
+        self.position.reset();
+        self
+    }
+
+    /// Returns the position state, see [PositionAware].
+    pub fn position(&self) -> PositionAware {
+        self.position
+    }
+
+    /// Restore the position state, for example one returned by [SampleHistogram::position].
+    pub fn set_position(&mut self, position: PositionAware) -> &mut Self {
+        self.position = position;
+        self
+    }
+}
+
+impl Sampler for SampleHistogram {
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        self.observe(res)?;
+        Ok(logits)
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.position.context_shift(dropped);
+    }
+
+    fn reset(&mut self) {
+        SampleHistogram::reset(self);
+    }
+}
+
+impl<UI, F> ConfigurableSampler<UI, F> for SampleHistogram
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+{
+}
+
+impl<UI, F> HasSamplerMetadata<UI, F> for SampleHistogram
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "histogram",
+            description: Some("Counts how often each token id gets selected."),
+            options: vec![],
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use std::sync::Arc;
+
+use crate::{configure::*, prelude::PositionAware, types::*};
+
+/// # JSON sampling
+/// Constrains generation to structurally valid JSON. A small JSON state machine tracks
+/// the output so far (open objects and arrays, strings, numbers and so on) and tokens that
+/// would make it invalid get their logit set to negative infinity.
+///
+/// The host provides the text for each token id, `token_strings[token_id]`. Token ids
+/// without text or with empty text are always eliminated, except for `eos_token` which is
+/// only allowed once the JSON value is complete. After that, only whitespace and
+/// `eos_token` are allowed.
+///
+/// The sampler is stateful: it feeds the tokens that were added to the last tokens
+/// resource since the previous step into the state machine, so this requires the last
+/// tokens resource. The tokens already present the first time the sampler runs (for
+/// example the prompt) are skipped. Call [SampleJson::reset] before starting a new
+/// generation.
+///
+/// An error is returned if a token that was added has no text or would make the JSON
+/// invalid, or if no token can continue it.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `token_strings`: The text for each token id. (default: empty)
+/// - `eos_token`: The token id that ends generation. (default: `None`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleJson {
+    pub(crate) token_strings: Arc<[String]>,
+    pub(crate) eos_token: Option<TID>,
+    state: JsonState,
+    position: PositionAware,
+}
+
+impl SampleJson {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(token_strings: I) -> Self {
+        Self {
+            token_strings: token_strings.into_iter().map(Into::into).collect(),
+            eos_token: None,
+            state: JsonState::default(),
+            position: PositionAware::new(),
+        }
+    }
+
+    pub fn eos_token(mut self, val: Option<TID>) -> Self {
+        self.eos_token = val;
+        self
+    }
+
+    /// Start over with empty output.
+    pub fn reset(&mut self) -> &mut Self {
+        self.state = JsonState::default();
+        self.position.reset();
+        self
+    }
+
+    /// Returns the position state, see [PositionAware].
+    pub fn position(&self) -> PositionAware {
+        self.position
+    }
+
+    /// Restore the position state, for example one returned by [SampleJson::position].
+    pub fn set_position(&mut self, position: PositionAware) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    /// Returns `true` if the output so far is a complete JSON value.
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    fn token_str(&self, token_id: TID) -> Option<&str> {
+        self.token_strings
+            .get(token_id as usize)
+            .map(String::as_str)
+            .filter(|s| !s.is_empty())
+    }
+
+    fn allowed(&self, token_id: TID) -> bool {
+        if Some(token_id) == self.eos_token {
+            return self.state.is_complete();
+        }
+        // This runs for every token in the vocabulary, so the stack is borrowed
+        // rather than cloned.
+        self.token_str(token_id).is_some_and(|s| {
+            let mut state = JsonState {
+                stack: StackDelta::new(&self.state.stack),
+                mode: self.state.mode,
+            };
+            state.push_str(s)
+        })
+    }
+
+    /// Feed the tokens added since the last step into the state machine.
+    fn feed(&mut self, res: &dyn HasSamplerResources) -> Result<(), SamplerError> {
+        let mut new_tokens = vec![];
+        self.position
+            .new_tokens(res, &mut |tokens| new_tokens.extend_from_slice(tokens))?;
+
+        new_tokens.into_iter().try_for_each(|tid| {
+            if Some(tid) == self.eos_token {
+                return Ok(());
+            }
+            let Some(s) = self.token_str(tid).map(str::to_string) else {
+                Err(SamplerError::InternalError(format!(
+                    "Token id {tid} has no text, so it can't be part of the JSON output"
+                )))?
+            };
+            if self.state.push_str(&s) {
+                Ok(())
+            } else {
+                Err(SamplerError::InternalError(format!(
+                    "Token id {tid} made the JSON output invalid"
+                )))
+            }
+        })
+    }
+}
+
+impl Sampler for SampleJson {
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        self.feed(res)?;
+        if logits.is_empty() {
+            return Ok(logits);
+        }
+
+        let mut changed = false;
+        let mut any_allowed = false;
+        logits.iter_mut().for_each(|l| {
+            if self.allowed(l.token_id) {
+                any_allowed = true;
+            } else if l.logit != f32::NEG_INFINITY {
+                l.logit = f32::NEG_INFINITY;
+                changed = true;
+            }
+        });
+        if !any_allowed {
+            Err(SamplerError::InternalError(String::from(
+                "No token can continue the JSON output",
+            )))?
+        }
+        if changed {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.position.context_shift(dropped);
+    }
+
+    fn reset(&mut self) {
+        SampleJson::reset(self);
+    }
+}
+
+// FIXME: Find a sane way to implement this for the list of token strings.
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F> for SampleJson {}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F> for SampleJson {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "json",
+            description: Some(concat!(
+                "Constrains generation to structurally valid JSON by eliminating ",
+                "tokens that would make the output invalid."
+            )),
+            options: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Object,
+    Array,
+}
+
+/// The stack of open containers in [JsonState].
+trait ContainerStack {
+    fn push(&mut self, container: Container);
+    fn pop(&mut self) -> Option<Container>;
+    fn last(&self) -> Option<Container>;
+    fn is_empty(&self) -> bool;
+}
+
+impl ContainerStack for Vec<Container> {
+    fn push(&mut self, container: Container) {
+        Vec::push(self, container)
+    }
+
+    fn pop(&mut self) -> Option<Container> {
+        Vec::pop(self)
+    }
+
+    fn last(&self) -> Option<Container> {
+        self.as_slice().last().copied()
+    }
+
+    fn is_empty(&self) -> bool {
+        Vec::is_empty(self)
+    }
+}
+
+/// Stack that borrows a base stack and only records the changes made on top of it,
+/// so a candidate token can be checked without cloning the whole stack.
+struct StackDelta<'a> {
+    /// The part of the base stack that hasn't been popped.
+    base: &'a [Container],
+    /// Containers pushed on top of `base`.
+    pushed: Vec<Container>,
+}
+
+impl<'a> StackDelta<'a> {
+    fn new(base: &'a [Container]) -> Self {
+        Self {
+            base,
+            pushed: vec![],
+        }
+    }
+}
+
+impl ContainerStack for StackDelta<'_> {
+    fn push(&mut self, container: Container) {
+        self.pushed.push(container)
+    }
+
+    fn pop(&mut self) -> Option<Container> {
+        self.pushed.pop().or_else(|| {
+            let (last, rest) = self.base.split_last()?;
+            self.base = rest;
+            Some(*last)
+        })
+    }
+
+    fn last(&self) -> Option<Container> {
+        self.pushed.as_slice().last().or(self.base.last()).copied()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pushed.is_empty() && self.base.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Number {
+    /// After the minus sign, a digit is required.
+    Minus,
+    /// After a leading zero.
+    Zero,
+    /// In the integer digits.
+    Int,
+    /// After the decimal point, a digit is required.
+    Dot,
+    /// In the fraction digits.
+    Frac,
+    /// After the exponent marker, a sign or digit is required.
+    Exp,
+    /// After the exponent sign, a digit is required.
+    ExpSign,
+    /// In the exponent digits.
+    ExpDigits,
+}
+
+impl Number {
+    fn is_terminal(self) -> bool {
+        matches!(self, Self::Zero | Self::Int | Self::Frac | Self::ExpDigits)
+    }
+
+    fn push(self, c: char) -> Option<Self> {
+        use Number::*;
+
+        Some(match (self, c) {
+            (Minus, '0') => Zero,
+            (Minus, '1'..='9') | (Int, '0'..='9') => Int,
+            (Zero | Int, '.') => Dot,
+            (Dot | Frac, '0'..='9') => Frac,
+            (Zero | Int | Frac, 'e' | 'E') => Exp,
+            (Exp, '+' | '-') => ExpSign,
+            (Exp | ExpSign | ExpDigits, '0'..='9') => ExpDigits,
+            _ => None?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Backslash,
+    /// Number of hex digits left in a `\u` escape.
+    Unicode(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Expecting a value. A closing `]` is allowed right after `[`.
+    Value {
+        allow_close: bool,
+    },
+    /// Expecting an object key. A closing `}` is allowed right after `{`.
+    Key {
+        allow_close: bool,
+    },
+    /// After an object key, expecting `:`.
+    Colon,
+    String {
+        key: bool,
+        escape: Escape,
+    },
+    Number(Number),
+    /// Matching the rest of `true`, `false` or `null`.
+    Literal(&'static str),
+    /// After a value in a container, expecting `,` or the closing bracket.
+    AfterValue,
+    /// The top level value is complete.
+    Done,
+}
+
+/// Streaming JSON validator that accepts any prefix of a valid JSON value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct JsonState<S = Vec<Container>> {
+    stack: S,
+    mode: Mode,
+}
+
+impl Default for JsonState {
+    fn default() -> Self {
+        Self {
+            stack: vec![],
+            mode: Mode::Value { allow_close: false },
+        }
+    }
+}
+
+impl<S: ContainerStack> JsonState<S> {
+    fn is_complete(&self) -> bool {
+        match self.mode {
+            Mode::Done => true,
+            Mode::Number(num) => self.stack.is_empty() && num.is_terminal(),
+            _ => false,
+        }
+    }
+
+    /// Returns `false` if `s` makes the output invalid. The state is unspecified
+    /// after that.
+    fn push_str(&mut self, s: &str) -> bool {
+        s.chars().all(|c| self.push(c))
+    }
+
+    fn value_done(&mut self) {
+        self.mode = if self.stack.is_empty() {
+            Mode::Done
+        } else {
+            Mode::AfterValue
+        };
+    }
+
+    fn close(&mut self, container: Container) -> bool {
+        if self.stack.pop() != Some(container) {
+            return false;
+        }
+        self.value_done();
+        true
+    }
+
+    fn push(&mut self, c: char) -> bool {
+        let is_ws = matches!(c, ' ' | '\t' | '\n' | '\r');
+        match self.mode {
+            Mode::Value { allow_close } => match c {
+                _ if is_ws => {}
+                '{' => {
+                    self.stack.push(Container::Object);
+                    self.mode = Mode::Key { allow_close: true };
+                }
+                '[' => {
+                    self.stack.push(Container::Array);
+                    self.mode = Mode::Value { allow_close: true };
+                }
+                ']' if allow_close => return self.close(Container::Array),
+                '"' => {
+                    self.mode = Mode::String {
+                        key: false,
+                        escape: Escape::None,
+                    }
+                }
+                '-' => self.mode = Mode::Number(Number::Minus),
+                '0' => self.mode = Mode::Number(Number::Zero),
+                '1'..='9' => self.mode = Mode::Number(Number::Int),
+                't' => self.mode = Mode::Literal("rue"),
+                'f' => self.mode = Mode::Literal("alse"),
+                'n' => self.mode = Mode::Literal("ull"),
+                _ => return false,
+            },
+            Mode::Key { allow_close } => match c {
+                _ if is_ws => {}
+                '"' => {
+                    self.mode = Mode::String {
+                        key: true,
+                        escape: Escape::None,
+                    }
+                }
+                '}' if allow_close => return self.close(Container::Object),
+                _ => return false,
+            },
+            Mode::Colon => match c {
+                _ if is_ws => {}
+                ':' => self.mode = Mode::Value { allow_close: false },
+                _ => return false,
+            },
+            Mode::String { key, escape } => {
+                let escape = match (escape, c) {
+                    (Escape::None, '"') => {
+                        if key {
+                            self.mode = Mode::Colon;
+                        } else {
+                            self.value_done();
+                        }
+                        return true;
+                    }
+                    (Escape::None, '\\') => Escape::Backslash,
+                    (Escape::None, c) if (c as u32) < 0x20 => return false,
+                    (Escape::None, _) => Escape::None,
+                    (Escape::Backslash, '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't') => {
+                        Escape::None
+                    }
+                    (Escape::Backslash, 'u') => Escape::Unicode(4),
+                    (Escape::Unicode(n), c) if c.is_ascii_hexdigit() => match n {
+                        1 => Escape::None,
+                        n => Escape::Unicode(n - 1),
+                    },
+                    _ => return false,
+                };
+                self.mode = Mode::String { key, escape };
+            }
+            Mode::Number(num) => match num.push(c) {
+                Some(num) => self.mode = Mode::Number(num),
+                None if num.is_terminal() => {
+                    self.value_done();
+                    return self.push(c);
+                }
+                None => return false,
+            },
+            Mode::Literal(rest) => {
+                if !rest.starts_with(c) {
+                    return false;
+                }
+                let rest = &rest[1..];
+                if rest.is_empty() {
+                    self.value_done();
+                } else {
+                    self.mode = Mode::Literal(rest);
+                }
+            }
+            Mode::AfterValue => match (c, self.stack.last()) {
+                _ if is_ws => {}
+                (',', Some(Container::Object)) => self.mode = Mode::Key { allow_close: false },
+                (',', Some(Container::Array)) => self.mode = Mode::Value { allow_close: false },
+                ('}', _) => return self.close(Container::Object),
+                (']', _) => return self.close(Container::Array),
+                _ => return false,
+            },
+            Mode::Done => return is_ws,
+        }
+        true
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{configure::*, prelude::PositionAware, types::*};
+
+/// # Length bias sampling
+/// Biases the end of sequence token(s) based on the number of generated tokens, as a
+/// soft way to control the output length. The bias is `slope * (length - target_length)`,
+/// so with a positive `slope` the end of sequence tokens are suppressed before
+/// `target_length`, left alone at `target_length` and increasingly encouraged after it.
+///
+/// The generated length is tracked with [PositionAware] from the length of the last tokens
+/// resource: the length when the sampler first runs (or first runs after
+/// [SampleLengthBias::reset]) is length `0`.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `eos_tokens`: The end of sequence token ids to bias. (default: empty)
+/// - `target_length`: Generated length where the bias is `0.0`. (default: `256`)
+/// - `slope`: Bias change per generated token. (default: `0.1`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleLengthBias {
+    pub(crate) eos_tokens: Vec<TID>,
+    pub(crate) target_length: usize,
+    pub(crate) slope: L,
+    position: PositionAware,
+}
+
+impl Default for SampleLengthBias {
+    fn default() -> Self {
+        Self {
+            eos_tokens: vec![],
+            target_length: 256,
+            slope: 0.1f32,
+            position: PositionAware::new(),
+        }
+    }
+}
+
+impl SampleLengthBias {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleLengthBias::try_new].
+    pub fn new<I: IntoIterator<Item = TID>>(eos_tokens: I, target_length: usize, slope: L) -> Self {
+        Self {
+            eos_tokens: Vec::from_iter(eos_tokens),
+            target_length,
+            slope,
+            position: PositionAware::new(),
+        }
+    }
+
+    /// Like [SampleLengthBias::new] but the parameters are validated: `slope` must be finite.
+    pub fn try_new<I: IntoIterator<Item = TID>>(
+        eos_tokens: I,
+        target_length: usize,
+        slope: L,
+    ) -> Result<Self, SamplerError> {
+        check_option("slope", slope.is_finite(), "finite", slope)?;
+        Ok(Self::new(eos_tokens, target_length, slope))
+    }
+
+    pub fn eos_tokens<I: IntoIterator<Item = TID>>(mut self, it: I) -> Self {
+        self.eos_tokens = Vec::from_iter(it);
+        self
+    }
+
+    pub fn target_length(mut self, val: usize) -> Self {
+        self.target_length = val;
+        self
+    }
+
+    pub fn slope(mut self, val: L) -> Self {
+        self.slope = val;
+        self
+    }
+
+    /// Start over. The next time the sampler runs will be length `0`.
+    pub fn reset(&mut self) -> &mut Self {
+        self.position.reset();
+        self
+    }
+
+    /// Returns the position state, see [PositionAware].
+    pub fn position(&self) -> PositionAware {
+        self.position
+    }
+
+    /// Restore the position state, for example one returned by [SampleLengthBias::position].
+    pub fn set_position(&mut self, position: PositionAware) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    /// Returns the bias applied to the end of sequence tokens at the specified length.
+    pub fn bias_at(&self, length: usize) -> L {
+        self.slope * (length as L - self.target_length as L)
+    }
+}
+
+impl Sampler for SampleLengthBias {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        if self.eos_tokens.is_empty() {
+            return Ok(logits);
+        }
+        let length = self.position.step(res)?;
+        let bias = self.bias_at(length);
+        if bias == 0f32 {
+            return Ok(logits);
+        }
+        let mut changed = 0;
+
+        logits
+            .iter_mut()
+            .filter(|l| self.eos_tokens.contains(&l.token_id))
+            .for_each(|l| {
+                l.logit += bias;
+                changed += 1;
+            });
+        if changed > 0 {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.position.context_shift(dropped);
+    }
+
+    fn reset(&mut self) {
+        SampleLengthBias::reset(self);
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleLengthBias {}
+
+impl HasSamplerMetadata<usize, L> for SampleLengthBias {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "length bias",
+            description: Some(concat!(
+                "Biases the end of sequence tokens by slope * (length - target_length) ",
+                "based on the number of generated tokens."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "target_length",
+                    description: Some("Generated length where the bias is 0."),
+                    option_type: SamplerOptionType::UInt,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "slope",
+                    description: Some("Bias change per generated token."),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::UInt(&mut self.target_length)),
+                    Some(SamplerOptionValueMut::Float(&mut self.slope)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::UInt(self.target_length)),
+                    Some(SamplerOptionValue::Float(self.slope)),
+                ],
+            )
+        }
+    }
+}
@@ -10,6 +10,10 @@ use crate::{configure::*, types::*};
 ///
 /// See: <https://arxiv.org/abs/2202.00666>
 ///
+/// Tokens are kept, starting with the most typical, until their cumulative probability
+/// is greater or equal to `p`. Like [SampleTopP](crate::samplers::SampleTopP), a
+/// threshold that lands exactly on a boundary is inclusive.
+///
 /// **Properties**:
 /// - Modifies logits
 /// - Filters logits
@@ -35,10 +39,20 @@ impl Default for SampleLocallyTypical {
 }
 
 impl SampleLocallyTypical {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleLocallyTypical::try_new].
     pub fn new(p: L, min_keep: usize) -> Self {
         Self { p, min_keep }
     }
 
+    /// Like [SampleLocallyTypical::new] but the parameters are validated: `p` must be
+    /// between `0.0` and `1.0` and `min_keep` must be at least `1`.
+    pub fn try_new(p: L, min_keep: usize) -> Result<Self, SamplerError> {
+        check_option("p", (0f32..=1f32).contains(&p), "between 0.0 and 1.0", p)?;
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(p, min_keep))
+    }
+
     pub fn min_keep(mut self, val: usize) -> Self {
         self.min_keep = val;
         self
@@ -51,6 +65,14 @@ impl SampleLocallyTypical {
 }
 
 impl Sampler for SampleLocallyTypical {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
@@ -59,7 +81,7 @@ impl Sampler for SampleLocallyTypical {
         use std::ops::ControlFlow::*;
 
         let Self { p, min_keep } = *self;
-        let min_keep = if min_keep == 0 { 0 } else { min_keep - 1 };
+        let min_keep = logits.effective_min_keep(min_keep).saturating_sub(1);
         logits.ensure_softmax().map_err(|e| {
             SamplerError::InternalError(format!(
                 "Failed to ensure softmax before sampling: {}",
@@ -93,7 +115,7 @@ impl Sampler for SampleLocallyTypical {
             shifted.len(),
             |last_idx, (idx, (logit, _score))| {
                 cum_sum += logit.prob;
-                if cum_sum > p && idx >= min_keep {
+                if cum_sum >= p && idx >= min_keep {
                     return Break(idx + 1);
                 }
                 Continue(last_idx)
@@ -111,6 +133,10 @@ impl Sampler for SampleLocallyTypical {
             .for_each(|(logit, _score)| logits.push(logit));
         Ok(logits)
     }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX
+    }
 }
 
 impl ConfigurableSampler<usize, L> for SampleLocallyTypical {}
@@ -134,6 +160,7 @@ impl HasSamplerMetadata<usize, L> for SampleLocallyTypical {
                         "presumably this means more factual output)."
                     )),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "min_keep",
@@ -142,6 +169,7 @@ impl HasSamplerMetadata<usize, L> for SampleLocallyTypical {
                         "Setting this to 0 is not recommended."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
             ],
         }
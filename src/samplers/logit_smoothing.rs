@@ -0,0 +1,218 @@
+use crate::{configure::*, types::*};
+
+/// The running state of a [SampleLogitSmoothing] sampler: the EMA indexed by token id,
+/// where `None` means there's no finite logit to blend with. This can be used to checkpoint
+/// a sampler mid-generation and restore it later. With the `serde` feature it can be
+/// serialized.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogitSmoothingState {
+    /// EMA of the logits for each token id.
+    pub ema: Vec<Option<L>>,
+}
+
+/// # Logit smoothing sampling
+/// Keeps an exponential moving average (EMA) of the logits across steps and blends it with
+/// the current logits: `effective = (1 - alpha) * current + alpha * ema`. The effective
+/// logits then become the new EMA. This dampens abrupt shifts in the distribution from
+/// one step to the next.
+///
+/// The EMA is tracked per token id. A token that's missing in the current [Logits], for
+/// example because an earlier sampler eliminated it, keeps its previous EMA value, so the
+/// sampler can run after filters. Non-finite logits like `-inf` aren't blended.
+///
+/// When `n_vocab` is set, the EMA starts over if it changes and token ids outside the
+/// vocabulary are an error. Otherwise the EMA grows to fit the largest token id seen.
+///
+/// The EMA is part of the sampler state, so call [SampleLogitSmoothing::reset] before
+/// starting a new generation.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `alpha`: Weight of the EMA. Use `0.0` to disable. (default: `0.0`)
+/// - `n_vocab`: Model vocabulary size. Use `0` if unknown. (default: `0`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleLogitSmoothing {
+    pub(crate) alpha: L,
+    pub(crate) n_vocab: usize,
+    ema: Vec<L>,
+}
+
+impl SampleLogitSmoothing {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleLogitSmoothing::try_new].
+    pub fn new(alpha: L) -> Self {
+        Self {
+            alpha,
+            n_vocab: 0,
+            ema: vec![],
+        }
+    }
+
+    /// Like [SampleLogitSmoothing::new] but the parameters are validated: `alpha` must be
+    /// between `0.0` and `1.0`.
+    pub fn try_new(alpha: L) -> Result<Self, SamplerError> {
+        check_option(
+            "alpha",
+            (0f32..=1f32).contains(&alpha),
+            "between 0.0 and 1.0",
+            alpha,
+        )?;
+        Ok(Self::new(alpha))
+    }
+
+    pub fn alpha(mut self, val: L) -> Self {
+        self.alpha = val;
+        self
+    }
+
+    pub fn n_vocab(mut self, val: usize) -> Self {
+        self.n_vocab = val;
+        self
+    }
+
+    /// Returns the running state, see [LogitSmoothingState].
+    pub fn state(&self) -> LogitSmoothingState {
+        LogitSmoothingState {
+            ema: self
+                .ema
+                .iter()
+                .map(|&ema| ema.is_finite().then_some(ema))
+                .collect(),
+        }
+    }
+
+    /// Restore the running state, for example one returned by
+    /// [SampleLogitSmoothing::state].
+    pub fn set_state(&mut self, state: LogitSmoothingState) -> &mut Self {
+        self.ema = state
+            .ema
+            .into_iter()
+            .map(|ema| ema.unwrap_or(f32::NAN))
+            .collect();
+        self
+    }
+
+    /// Clear the EMA. The next time the sampler runs, the logits will be left alone.
+    pub fn reset(&mut self) -> &mut Self {
+        self.ema.clear();
+        self
+    }
+}
+
+impl Sampler for SampleLogitSmoothing {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let alpha = self.alpha;
+        if alpha == 0f32 || logits.is_empty() {
+            return Ok(logits);
+        }
+        let n_vocab = self.n_vocab;
+        if n_vocab > 0 {
+            if let Some(l) = logits.iter().find(|l| l.token_id as usize >= n_vocab) {
+                Err(SamplerError::InternalError(format!(
+                    "Token id {} is outside the vocabulary of size {n_vocab}",
+                    l.token_id
+                )))?
+            }
+            if self.ema.len() != n_vocab {
+                self.reset();
+                self.ema.resize(n_vocab, f32::NAN);
+            }
+        }
+
+        let mut changed = false;
+        logits.iter_mut().for_each(|l| {
+            let idx = l.token_id as usize;
+            if idx >= self.ema.len() {
+                self.ema.resize(idx + 1, f32::NAN);
+            }
+            let ema = self.ema[idx];
+            if ema.is_finite() && l.logit.is_finite() {
+                l.logit = (1f32 - alpha) * l.logit + alpha * ema;
+                changed = true;
+            }
+            self.ema[idx] = l.logit;
+        });
+        if changed {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    fn reset(&mut self) {
+        SampleLogitSmoothing::reset(self);
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleLogitSmoothing {}
+
+impl HasSamplerMetadata<usize, L> for SampleLogitSmoothing {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "logit smoothing",
+            description: Some(concat!(
+                "Blends the current logits with an exponential moving average of the ",
+                "logits from previous steps to dampen abrupt changes in the distribution."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "alpha",
+                    description: Some(concat!(
+                        "Weight of the moving average when blending it with the current logits. ",
+                        "0 disables smoothing."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "n_vocab",
+                    description: Some(concat!(
+                        "Model vocabulary size. The moving average starts over when this changes. ",
+                        "0 means unknown."
+                    )),
+                    option_type: SamplerOptionType::UInt,
+                    advanced: true,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.alpha)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.n_vocab)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.alpha)),
+                    Some(SamplerOptionValue::UInt(self.n_vocab)),
+                ],
+            )
+        }
+    }
+}
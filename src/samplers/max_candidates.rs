@@ -0,0 +1,97 @@
+use crate::{configure::*, types::*};
+
+/// # Max candidates
+/// This sampler truncates the sorted logits to at most `n` entries, regardless of what
+/// earlier samplers in the chain decided to keep. It's meant to be appended at the end
+/// of a chain (before the sampler that actually picks a token) as a safety net to guarantee
+/// a bounded number of candidates.
+///
+/// The difference from top-k is mostly intent: top-k respects `min_keep`, this sampler doesn't.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `n`: Maximum number of entries to keep. Setting this to `0` disables the sampler. (default: `0`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SampleMaxCandidates {
+    pub(crate) n: usize,
+}
+
+impl SampleMaxCandidates {
+    pub fn new(n: usize) -> Self {
+        Self { n }
+    }
+
+    pub fn n(mut self, val: usize) -> Self {
+        self.n = val;
+        self
+    }
+}
+
+impl Sampler for SampleMaxCandidates {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        if self.n == 0 || logits.len() <= self.n {
+            return Ok(logits);
+        }
+        logits
+            .ensure_sorted()
+            .map_err(|e| SamplerError::InternalError(format!("Failed to ensure sorted: {}", e)))?;
+        logits.truncate(self.n);
+        logits.set_softmax(false);
+        Ok(logits)
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleMaxCandidates {}
+
+impl HasSamplerMetadata<usize, L> for SampleMaxCandidates {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "max candidates",
+            description: Some(concat!(
+                "Truncates the sorted logits to at most n tokens, ",
+                "regardless of what earlier samplers kept."
+            )),
+            options: vec![SamplerOptionMetadata {
+                key: "n",
+                description: Some(concat!(
+                    "Maximum number of tokens to keep. ",
+                    "Setting this to 0 disables the sampler."
+                )),
+                option_type: SamplerOptionType::UInt,
+                advanced: false,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValueMut::UInt(&mut self.n))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValue::UInt(self.n))],
+            )
+        }
+    }
+}
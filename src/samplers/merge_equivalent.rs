@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use crate::{configure::*, types::*};
+
+/// # Merge equivalent tokens sampling
+/// Some tokenizers have several token ids for what's effectively the same text, for example
+/// casing or leading space variants. This sampler takes a mapping from token ids to their
+/// canonical token id and moves the probability of equivalent tokens onto the canonical
+/// one, so the probability mass for a choice isn't split across duplicates.
+///
+/// The merged tokens end up with a probability of `0` (their logit is set to negative
+/// infinity). The logit of the canonical token is adjusted to match its new probability.
+/// Tokens whose canonical token isn't in the [Logits] or has a probability of `0`, for
+/// example because an earlier sampler eliminated it, are left alone.
+///
+/// Chained mappings are resolved when the sampler is created, so with `0 -> 1` and `1 -> 2`
+/// both `0` and `1` are merged into `2`. If the mapping contains a cycle, the lowest token
+/// id in the cycle is used as the canonical token.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `mapping`: Token id to canonical token id pairs. (default: empty)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleMergeEquivalent {
+    pub(crate) mapping: HashMap<TID, TID>,
+}
+
+impl SampleMergeEquivalent {
+    pub fn new<I: IntoIterator<Item = (TID, TID)>>(it: I) -> Self {
+        let mapping = HashMap::from_iter(it);
+        Self {
+            mapping: mapping
+                .keys()
+                .map(|&tid| (tid, Self::resolve(&mapping, tid)))
+                .collect(),
+        }
+    }
+
+    /// Follows the mapping from the token id until reaching a token that maps to itself
+    /// or isn't mapped. For a cycle, the lowest token id in the cycle is returned.
+    fn resolve(mapping: &HashMap<TID, TID>, token_id: TID) -> TID {
+        let mut path = vec![token_id];
+        let mut current = token_id;
+        while let Some(&next) = mapping.get(&current) {
+            if next == current {
+                break;
+            }
+            if let Some(idx) = path.iter().position(|&tid| tid == next) {
+                return path[idx..].iter().copied().min().unwrap_or(next);
+            }
+            path.push(next);
+            current = next;
+        }
+        current
+    }
+}
+
+impl Sampler for SampleMergeEquivalent {
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        if self.mapping.is_empty() || logits.is_empty() {
+            return Ok(logits);
+        }
+        logits.ensure_softmax().map_err(|e| {
+            SamplerError::InternalError(format!("Failed to ensure softmax before sampling: {}", e))
+        })?;
+
+        let positions = logits
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.prob > 0f32)
+            .map(|(idx, l)| (l.token_id, idx))
+            .collect::<HashMap<_, _>>();
+        let mut merged = HashMap::<usize, L>::default();
+        logits.iter_mut().for_each(|l| {
+            let Some(canonical) = self.mapping.get(&l.token_id) else {
+                return;
+            };
+            let Some(&canonical_idx) = positions.get(canonical) else {
+                return;
+            };
+            // The canonical token hasn't been merged itself, since mappings are
+            // resolved to their final canonical token.
+            if *canonical == l.token_id {
+                return;
+            }
+            *merged.entry(canonical_idx).or_insert(0f32) += l.prob;
+            l.prob = 0f32;
+            l.logit = f32::NEG_INFINITY;
+        });
+        if merged.is_empty() {
+            return Ok(logits);
+        }
+
+        let base = logits.get_softmax_base();
+        merged.into_iter().for_each(|(idx, extra)| {
+            let l = &mut logits[idx];
+            l.logit += base.log((l.prob + extra) / l.prob);
+            l.prob += extra;
+        });
+        logits.set_sorted(false);
+        logits
+            .ensure_sorted()
+            .map_err(|e| SamplerError::InternalError(format!("Failed to ensure sorted: {}", e)))?;
+        // Merging moves probability around without changing the total.
+        logits.set_softmax(true);
+        Ok(logits)
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(!self.mapping.is_empty())
+    }
+}
+
+// FIXME: Find a sane way to implement this for the mapping.
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F>
+    for SampleMergeEquivalent
+{
+}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F>
+    for SampleMergeEquivalent
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "merge equivalent",
+            description: Some(concat!(
+                "Moves the probability of tokens that are equivalent to a canonical ",
+                "token onto the canonical token."
+            )),
+            options: vec![],
+        }
+    }
+}
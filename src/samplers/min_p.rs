@@ -8,7 +8,10 @@ use crate::{configure::*, types::*};
 ///
 /// Credit to @kalomaze on GitHub for design. See this link for a more in-depth
 /// explanation: https://github.com/ggerganov/llama.cpp/issues/3483#issuecomment-1783920998
-
+///
+/// The number of tokens kept by the last run and whether `min_keep` made it keep tokens
+/// below the threshold are available from [SampleMinP::cutoff_index] and
+/// [SampleMinP::min_keep_widened].
 ///
 /// **Properties**:
 /// - Filters logits
@@ -16,10 +19,15 @@ use crate::{configure::*, types::*};
 /// **Parameters**:
 /// - `min_keep`: Minimum number of entries to keep. (default: `1`)
 /// - `p`: Threshold value. Use `0.0` to disable. (default: `0.9`)
+/// - `floor`: When set, eliminated tokens are kept with up to this probability instead of
+///   being removed, so they can still be selected rarely. See [Logits::truncate_with_floor].
+///   This isn't available as a configurable option. (default: `None`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleMinP {
     pub(crate) p: L,
     pub(crate) min_keep: usize,
+    pub(crate) floor: Option<L>,
+    cutoff: Option<(usize, bool)>,
 }
 
 impl Default for SampleMinP {
@@ -27,13 +35,30 @@ impl Default for SampleMinP {
         Self {
             p: 0.05f32,
             min_keep: 1,
+            floor: None,
+            cutoff: None,
         }
     }
 }
 
 impl SampleMinP {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleMinP::try_new].
     pub fn new(p: L, min_keep: usize) -> Self {
-        Self { p, min_keep }
+        Self {
+            p,
+            min_keep,
+            floor: None,
+            cutoff: None,
+        }
+    }
+
+    /// Like [SampleMinP::new] but the parameters are validated: `p` must be between `0.0`
+    /// and `1.0` and `min_keep` must be at least `1`.
+    pub fn try_new(p: L, min_keep: usize) -> Result<Self, SamplerError> {
+        check_option("p", (0f32..=1f32).contains(&p), "between 0.0 and 1.0", p)?;
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(p, min_keep))
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -45,16 +70,48 @@ impl SampleMinP {
         self.p = val;
         self
     }
+
+    pub fn floor(mut self, val: Option<L>) -> Self {
+        self.floor = val;
+        self
+    }
+
+    /// Returns the number of tokens kept by the last run of the sampler, if it has run.
+    pub fn cutoff_index(&self) -> Option<usize> {
+        self.cutoff.map(|(idx, _)| idx)
+    }
+
+    /// Returns `true` if the last run of the sampler kept tokens below the threshold
+    /// because of `min_keep`, if it has run.
+    pub fn min_keep_widened(&self) -> Option<bool> {
+        self.cutoff.map(|(_, widened)| widened)
+    }
 }
 
 impl Sampler for SampleMinP {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits, SamplerError> {
-        let Self { p, min_keep } = *self;
-        if p == 0f32 || logits.is_empty() {
+        let Self {
+            p, min_keep, floor, ..
+        } = *self;
+        let min_keep = logits.effective_min_keep(min_keep);
+        self.cutoff = None;
+        if logits.is_empty() {
+            return Ok(logits);
+        }
+        if p == 0f32 {
+            self.cutoff = Some((logits.len(), false));
             return Ok(logits);
         }
 
@@ -62,11 +119,17 @@ impl Sampler for SampleMinP {
             SamplerError::InternalError(format!("Failed to ensure softmax before sampling: {}", e))
         })?;
 
+        let threshold = logits[0].prob * p;
+        // The logits are sorted, so if any token that min_keep forces us to keep is below
+        // the threshold, the last one is.
+        let widened = logits[..min_keep.min(logits.len())]
+            .last()
+            .is_some_and(|l| l.prob < threshold);
         if logits.len() <= min_keep {
+            self.cutoff = Some((logits.len(), widened));
             return Ok(logits);
         }
 
-        let threshold = logits[0].prob * p;
         let last_idx = logits
             .iter()
             .enumerate()
@@ -74,12 +137,16 @@ impl Sampler for SampleMinP {
             .find(|(_, l)| l.prob < threshold)
             .map(|(idx, _)| idx)
             .unwrap_or_else(|| logits.len());
-        if last_idx != logits.len() {
-            logits.truncate(last_idx);
-            logits.set_softmax(false);
-        }
+        logits
+            .truncate_with_floor(last_idx, floor)
+            .map_err(|e| SamplerError::InternalError(format!("Failed to apply floor: {}", e)))?;
+        self.cutoff = Some((last_idx, widened));
         Ok(logits)
     }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(self.p != 0f32)
+    }
 }
 
 impl ConfigurableSampler<usize, L> for SampleMinP {}
@@ -99,6 +166,7 @@ impl HasSamplerMetadata<usize, L> for SampleMinP {
                     key: "p",
                     description: Some("Threshold value."),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "min_keep",
@@ -107,6 +175,7 @@ impl HasSamplerMetadata<usize, L> for SampleMinP {
                         "Setting this to 0 is not recommended."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
             ],
         }
@@ -1,4 +1,3 @@
-#![deny(implied_bounds_entailment)]
 use std::fmt::Debug;
 
 use anyhow::Result;
@@ -10,6 +9,34 @@ use crate::{
     types::*,
 };
 
+/// Statistics from the last step of a Mirostat sampler, useful for checking
+/// whether the controller is tracking the target surprise when tuning `tau` and `eta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MirostatStats {
+    /// Observed surprise of the selected token: `-log2(prob)`.
+    pub observed_surprise: L,
+    /// Target surprise (`tau`).
+    pub target_surprise: L,
+    /// The value of `mu` after it was updated for this step.
+    pub mu: L,
+}
+
+/// The running state of a Mirostat sampler: everything that changes while generating,
+/// as opposed to the parameters. This can be used to checkpoint a sampler mid-generation
+/// and restore it later, for example in another process. With the `serde` feature it
+/// can be serialized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MirostatState {
+    /// Current learning state.
+    pub mu: L,
+    /// The last selected token id.
+    pub token: Option<TID>,
+    /// Statistics from the last step.
+    pub stats: Option<MirostatStats>,
+}
+
 /// # Mirostat V1 sampling
 /// See: <https://arxiv.org/abs/2007.14966>
 ///
@@ -27,6 +54,9 @@ use crate::{
 /// - `tau`: Target entropy. (default: `5.0`)
 /// - `m`: Unknown. Can be set manually after construction. (default: `100`)
 /// - `mu`: Current learning state. Can be set manually after construction. (default: `tau * 2`)
+/// - `shift_decay`: How far to move `mu` back toward `tau * 2` when the host notifies
+///   the sampler of a context shift with [Sampler::notify_context_shift]. `0.0` leaves `mu`
+///   unchanged and `1.0` resets it. (default: `0.0`)
 #[derive(Debug, Clone)]
 pub struct SampleMirostat1 {
     pub(crate) n_vocab: usize,
@@ -34,7 +64,9 @@ pub struct SampleMirostat1 {
     pub(crate) tau: L,
     pub(crate) eta: L,
     pub(crate) mu: L,
+    pub(crate) shift_decay: L,
     pub(crate) token: Option<TID>,
+    stats: Option<MirostatStats>,
     rd_sampler: SampleRandDistrib,
 }
 
@@ -48,7 +80,9 @@ impl Default for SampleMirostat1 {
             eta: 1f32 / ten,
             tau: five,
             mu: ten,
+            shift_decay: 0f32,
             token: None,
+            stats: None,
             rd_sampler: SampleRandDistrib::new(),
             n_vocab: 0,
         }
@@ -56,6 +90,8 @@ impl Default for SampleMirostat1 {
 }
 
 impl SampleMirostat1 {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleMirostat1::try_new].
     pub fn new(n_vocab: usize, tau: L, eta: L) -> Self {
         Self {
             n_vocab,
@@ -63,11 +99,32 @@ impl SampleMirostat1 {
             eta,
             m: 100,
             mu: tau * (1f32 + 1f32),
+            shift_decay: 0f32,
             rd_sampler: SampleRandDistrib::new(),
             token: None,
+            stats: None,
         }
     }
 
+    /// Like [SampleMirostat1::new] but the parameters are validated: `n_vocab` must be at
+    /// least `1` and `tau` and `eta` must be finite and at least `0.0`.
+    pub fn try_new(n_vocab: usize, tau: L, eta: L) -> Result<Self, SamplerError> {
+        check_option("n_vocab", n_vocab >= 1, "at least 1", n_vocab)?;
+        check_option(
+            "tau",
+            tau >= 0f32 && tau.is_finite(),
+            "a finite value of at least 0.0",
+            tau,
+        )?;
+        check_option(
+            "eta",
+            eta >= 0f32 && eta.is_finite(),
+            "a finite value of at least 0.0",
+            eta,
+        )?;
+        Ok(Self::new(n_vocab, tau, eta))
+    }
+
     pub fn n_vocab(mut self, val: usize) -> Self {
         self.n_vocab = val;
         self
@@ -96,9 +153,63 @@ impl SampleMirostat1 {
         self.mu = val;
         self
     }
+
+    pub fn shift_decay(mut self, val: L) -> Self {
+        self.shift_decay = val;
+        self
+    }
+
+    /// Returns statistics from the last step, if a token was selected.
+    pub fn last_stats(&self) -> Option<MirostatStats> {
+        self.stats
+    }
+
+    /// Returns the running state, see [MirostatState].
+    pub fn state(&self) -> MirostatState {
+        MirostatState {
+            mu: self.mu,
+            token: self.token,
+            stats: self.stats,
+        }
+    }
+
+    /// Restore the running state, for example one returned by [SampleMirostat1::state]
+    /// earlier. After this the sampler continues exactly where that one left off,
+    /// given the same parameters and RNG state.
+    pub fn set_state(&mut self, state: MirostatState) -> &mut Self {
+        self.mu = state.mu;
+        self.token = state.token;
+        self.stats = state.stats;
+        self
+    }
+
+    #[cfg(feature = "serde")]
+    /// Serialize the running state with `serializer`. Requires the `serde` feature.
+    pub fn serialize_state<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.state(), serializer)
+    }
+
+    #[cfg(feature = "serde")]
+    /// Restore the running state serialized with [SampleMirostat1::serialize_state]
+    /// from `deserializer`. Requires the `serde` feature.
+    pub fn deserialize_state<'de, D: serde::Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+    ) -> Result<&mut Self, D::Error> {
+        let state = <MirostatState as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(self.set_state(state))
+    }
 }
 
 impl Sampler for SampleMirostat1 {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         res: &mut dyn HasSamplerResources,
@@ -113,6 +224,7 @@ impl Sampler for SampleMirostat1 {
             ..
         } = *self;
         self.token = None;
+        self.stats = None;
         if logits.is_empty() || m < 1 {
             return Ok(logits);
         }
@@ -151,15 +263,43 @@ impl Sampler for SampleMirostat1 {
                 SamplerError::InternalError(String::from("Impossible: sample token not in logits?"))
             })?;
 
-            self.mu -= eta * (-logit.prob.log2() - tau);
+            let observed_surprise = -logit.prob.log2();
+            self.mu -= eta * (observed_surprise - tau);
             self.token = Some(tid);
+            self.stats = Some(MirostatStats {
+                observed_surprise,
+                target_surprise: tau,
+                mu: self.mu,
+            });
         }
         Ok(logits)
     }
 
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(self.m >= 1)
+    }
+
     fn sampled_token_id(&self) -> Option<TID> {
         self.token
     }
+
+    fn is_stochastic(&self) -> bool {
+        true
+    }
+
+    /// Moves `mu` toward its initial value of `tau * 2` by `shift_decay`.
+    fn notify_context_shift(&mut self, dropped: usize) {
+        if dropped > 0 {
+            self.mu += self.shift_decay * (self.tau * (1f32 + 1f32) - self.mu);
+        }
+    }
+
+    /// Resets `mu` to its initial value of `tau * 2` and clears the last token and stats.
+    fn reset(&mut self) {
+        self.mu = self.tau * (1f32 + 1f32);
+        self.token = None;
+        self.stats = None;
+    }
 }
 
 impl ConfigurableSampler<usize, L> for SampleMirostat1 {
@@ -181,26 +321,40 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat1 {
                     key: "tau",
                     description: None,
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "eta",
                     description: None,
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "mu",
                     description: None,
                     option_type: SamplerOptionType::Float,
+                    advanced: true,
+                },
+                SamplerOptionMetadata {
+                    key: "shift_decay",
+                    description: Some(concat!(
+                        "How far to move mu back toward its initial value ",
+                        "when the context is shifted."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                    advanced: true,
                 },
                 SamplerOptionMetadata {
                     key: "m",
                     description: None,
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
                 SamplerOptionMetadata {
                     key: "n_vocab",
                     description: None,
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
             ],
         }
@@ -214,6 +368,7 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat1 {
                     Some(SamplerOptionValueMut::Float(&mut self.tau)),
                     Some(SamplerOptionValueMut::Float(&mut self.eta)),
                     Some(SamplerOptionValueMut::Float(&mut self.mu)),
+                    Some(SamplerOptionValueMut::Float(&mut self.shift_decay)),
                     Some(SamplerOptionValueMut::UInt(&mut self.m)),
                     Some(SamplerOptionValueMut::UInt(&mut self.n_vocab)),
                 ],
@@ -229,6 +384,7 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat1 {
                     Some(SamplerOptionValue::Float(self.tau)),
                     Some(SamplerOptionValue::Float(self.eta)),
                     Some(SamplerOptionValue::Float(self.mu)),
+                    Some(SamplerOptionValue::Float(self.shift_decay)),
                     Some(SamplerOptionValue::UInt(self.m)),
                     Some(SamplerOptionValue::UInt(self.n_vocab)),
                 ],
@@ -251,12 +407,17 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat1 {
 /// - `eta`: Learning rate. (default: `0.1`)
 /// - `tau`: Target entropy. (default: `5.0`)
 /// - `mu`: Current learning state. Can be set manually after construction. (default: `tau * 2`)
+/// - `shift_decay`: How far to move `mu` back toward `tau * 2` when the host notifies
+///   the sampler of a context shift with [Sampler::notify_context_shift]. `0.0` leaves `mu`
+///   unchanged and `1.0` resets it. (default: `0.0`)
 #[derive(Debug, Clone)]
 pub struct SampleMirostat2<TID = u32, L = f32> {
     pub(crate) tau: L,
     pub(crate) eta: L,
     pub(crate) mu: L,
+    pub(crate) shift_decay: L,
     pub(crate) token: Option<TID>,
+    stats: Option<MirostatStats>,
     rd_sampler: SampleRandDistrib,
 }
 
@@ -269,23 +430,47 @@ impl Default for SampleMirostat2 {
             eta: 1f32 / ten,
             tau: five,
             mu: ten,
+            shift_decay: 0f32,
             token: None,
+            stats: None,
             rd_sampler: SampleRandDistrib::new(),
         }
     }
 }
 
 impl SampleMirostat2 {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleMirostat2::try_new].
     pub fn new(tau: L, eta: L) -> Self {
         Self {
             tau,
             eta,
             mu: tau * (1f32 + 1f32),
+            shift_decay: 0f32,
             rd_sampler: SampleRandDistrib::new(),
             token: None,
+            stats: None,
         }
     }
 
+    /// Like [SampleMirostat2::new] but the parameters are validated: `tau` and `eta` must
+    /// be finite and at least `0.0`.
+    pub fn try_new(tau: L, eta: L) -> Result<Self, SamplerError> {
+        check_option(
+            "tau",
+            tau >= 0f32 && tau.is_finite(),
+            "a finite value of at least 0.0",
+            tau,
+        )?;
+        check_option(
+            "eta",
+            eta >= 0f32 && eta.is_finite(),
+            "a finite value of at least 0.0",
+            eta,
+        )?;
+        Ok(Self::new(tau, eta))
+    }
+
     /// Note: Setting tau will automatically set
     /// mu to `tau * 2`. If you need a custom
     /// value for mu, be sure to set it after tau.
@@ -304,15 +489,70 @@ impl SampleMirostat2 {
         self.mu = val;
         self
     }
+
+    pub fn shift_decay(mut self, val: L) -> Self {
+        self.shift_decay = val;
+        self
+    }
+
+    /// Returns statistics from the last step, if a token was selected.
+    pub fn last_stats(&self) -> Option<MirostatStats> {
+        self.stats
+    }
+
+    /// Returns the running state, see [MirostatState].
+    pub fn state(&self) -> MirostatState {
+        MirostatState {
+            mu: self.mu,
+            token: self.token,
+            stats: self.stats,
+        }
+    }
+
+    /// Restore the running state, for example one returned by [SampleMirostat2::state]
+    /// earlier. After this the sampler continues exactly where that one left off,
+    /// given the same parameters and RNG state.
+    pub fn set_state(&mut self, state: MirostatState) -> &mut Self {
+        self.mu = state.mu;
+        self.token = state.token;
+        self.stats = state.stats;
+        self
+    }
+
+    #[cfg(feature = "serde")]
+    /// Serialize the running state with `serializer`. Requires the `serde` feature.
+    pub fn serialize_state<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.state(), serializer)
+    }
+
+    #[cfg(feature = "serde")]
+    /// Restore the running state serialized with [SampleMirostat2::serialize_state]
+    /// from `deserializer`. Requires the `serde` feature.
+    pub fn deserialize_state<'de, D: serde::Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+    ) -> Result<&mut Self, D::Error> {
+        let state = <MirostatState as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(self.set_state(state))
+    }
 }
 
 impl Sampler for SampleMirostat2 {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits, SamplerError> {
         self.token = None;
+        self.stats = None;
         if logits.is_empty() {
             return Ok(logits);
         }
@@ -341,15 +581,43 @@ impl Sampler for SampleMirostat2 {
                 SamplerError::InternalError(String::from("Impossible: sample token not in logits?"))
             })?;
 
-            self.mu -= eta * (-logit.prob.log2() - tau);
+            let observed_surprise = -logit.prob.log2();
+            self.mu -= eta * (observed_surprise - tau);
             self.token = Some(tid);
+            self.stats = Some(MirostatStats {
+                observed_surprise,
+                target_surprise: tau,
+                mu: self.mu,
+            });
         }
         Ok(logits)
     }
 
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX
+    }
+
     fn sampled_token_id(&self) -> Option<TID> {
         self.token
     }
+
+    fn is_stochastic(&self) -> bool {
+        true
+    }
+
+    /// Moves `mu` toward its initial value of `tau * 2` by `shift_decay`.
+    fn notify_context_shift(&mut self, dropped: usize) {
+        if dropped > 0 {
+            self.mu += self.shift_decay * (self.tau * (1f32 + 1f32) - self.mu);
+        }
+    }
+
+    /// Resets `mu` to its initial value of `tau * 2` and clears the last token and stats.
+    fn reset(&mut self) {
+        self.mu = self.tau * (1f32 + 1f32);
+        self.token = None;
+        self.stats = None;
+    }
 }
 
 impl ConfigurableSampler<usize, L> for SampleMirostat2 {
@@ -371,16 +639,28 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat2 {
                     key: "tau",
                     description: None,
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "eta",
                     description: None,
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "mu",
                     description: None,
                     option_type: SamplerOptionType::Float,
+                    advanced: true,
+                },
+                SamplerOptionMetadata {
+                    key: "shift_decay",
+                    description: Some(concat!(
+                        "How far to move mu back toward its initial value ",
+                        "when the context is shifted."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                    advanced: true,
                 },
             ],
         }
@@ -394,6 +674,7 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat2 {
                     Some(SamplerOptionValueMut::Float(&mut self.tau)),
                     Some(SamplerOptionValueMut::Float(&mut self.eta)),
                     Some(SamplerOptionValueMut::Float(&mut self.mu)),
+                    Some(SamplerOptionValueMut::Float(&mut self.shift_decay)),
                 ],
             )
         }
@@ -407,6 +688,7 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat2 {
                     Some(SamplerOptionValue::Float(self.tau)),
                     Some(SamplerOptionValue::Float(self.eta)),
                     Some(SamplerOptionValue::Float(self.mu)),
+                    Some(SamplerOptionValue::Float(self.shift_decay)),
                 ],
             )
         }
@@ -0,0 +1,143 @@
+use rand::Rng;
+
+use crate::{configure::*, types::*};
+
+/// # Mixture sampling
+/// Combines two samplers: for each step, the first sampler is run with probability `w`,
+/// otherwise the second one is. The coin is drawn from the RNG resource, so results are
+/// reproducible with a seeded RNG. For example, this could be used to mostly use top-p
+/// sampling but occasionally pick the most probable token.
+///
+/// The sampled token id (if any) comes from whichever sampler was run.
+///
+/// Since either sampler might run, this doesn't have any requirements itself: the
+/// requirements of the chosen sampler are ensured before it runs.
+///
+/// **Properties**:
+/// - Depends on the samplers it contains
+///
+/// **Parameters**:
+/// - `w`: Probability of running the first sampler. (default: `0.5`)
+#[derive(Debug, Clone)]
+pub struct SampleMixture {
+    pub(crate) w: L,
+    first: Box<dyn Sampler>,
+    second: Box<dyn Sampler>,
+    token: Option<TID>,
+}
+
+impl SampleMixture {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleMixture::try_new].
+    pub fn new(first: impl Sampler + 'static, second: impl Sampler + 'static, w: L) -> Self {
+        Self {
+            w,
+            first: Box::new(first),
+            second: Box::new(second),
+            token: None,
+        }
+    }
+
+    /// Like [SampleMixture::new] but the parameters are validated: `w` must be between
+    /// `0.0` and `1.0`.
+    pub fn try_new(
+        first: impl Sampler + 'static,
+        second: impl Sampler + 'static,
+        w: L,
+    ) -> Result<Self, SamplerError> {
+        check_option("w", (0f32..=1f32).contains(&w), "between 0.0 and 1.0", w)?;
+        Ok(Self::new(first, second, w))
+    }
+
+    pub fn w(mut self, val: L) -> Self {
+        self.w = val;
+        self
+    }
+}
+
+impl Sampler for SampleMixture {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        self.token = None;
+        let mut coin = 0f32;
+        res.with_rng_mut(&mut |r| coin = r.gen::<f32>())?;
+
+        let sampler = if coin < self.w {
+            &mut self.first
+        } else {
+            &mut self.second
+        };
+        sampler.requires().ensure(logits)?;
+        let logits = sampler.sample(res, logits)?;
+        self.token = sampler.sampled_token_id();
+        Ok(logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<TID> {
+        self.token
+    }
+
+    fn is_stochastic(&self) -> bool {
+        true
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.first.notify_context_shift(dropped);
+        self.second.notify_context_shift(dropped);
+    }
+
+    fn reset(&mut self) {
+        self.token = None;
+        self.first.reset();
+        self.second.reset();
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleMixture {}
+
+impl HasSamplerMetadata<usize, L> for SampleMixture {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "mixture",
+            description: Some(concat!(
+                "Runs the first sampler with probability w, ",
+                "otherwise runs the second sampler."
+            )),
+            options: vec![SamplerOptionMetadata {
+                key: "w",
+                description: Some("Probability of running the first sampler."),
+                option_type: SamplerOptionType::Float,
+                advanced: false,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValueMut::Float(&mut self.w))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValue::Float(self.w))],
+            )
+        }
+    }
+}
@@ -1,21 +1,43 @@
+pub mod clamp_logits;
+pub mod class_cycle;
+pub mod confidence;
 pub mod flat_bias;
+pub mod forced_prefix;
 pub mod freq_presence;
 pub mod greedy;
+pub mod grouped_top_p;
+pub mod histogram;
+pub mod json;
+pub mod length_bias;
 pub mod locally_typical;
+pub mod logit_smoothing;
+pub mod max_candidates;
+pub mod merge_equivalent;
 pub mod min_p;
 pub mod mirostat;
+pub mod mixture;
 pub mod rand_distrib;
+pub mod rank_penalty;
+pub mod renyi_p;
 pub mod repetition;
 pub mod sequence_repetition;
 pub mod tail_free;
+pub mod temp_schedule;
 pub mod temperature;
 pub mod top_a;
 pub mod top_k;
+pub mod top_kp;
 pub mod top_p;
+pub mod typed_temperature;
+pub mod vocab_clamp;
+pub mod when;
 
 #[doc(inline)]
 pub use self::{
-    flat_bias::*, freq_presence::*, greedy::*, locally_typical::*, min_p::*, mirostat::*,
-    rand_distrib::*, repetition::*, sequence_repetition::*, tail_free::*, temperature::*, top_a::*,
-    top_k::*, top_p::*,
+    clamp_logits::*, class_cycle::*, confidence::*, flat_bias::*, forced_prefix::*,
+    freq_presence::*, greedy::*, grouped_top_p::*, histogram::*, json::*, length_bias::*,
+    locally_typical::*, logit_smoothing::*, max_candidates::*, merge_equivalent::*, min_p::*,
+    mirostat::*, mixture::*, rand_distrib::*, rank_penalty::*, renyi_p::*, repetition::*,
+    sequence_repetition::*, tail_free::*, temp_schedule::*, temperature::*, top_a::*, top_k::*,
+    top_kp::*, top_p::*, typed_temperature::*, vocab_clamp::*, when::*,
 };
@@ -1,6 +1,6 @@
 use std::fmt::Debug;
 
-use rand::distributions::{Distribution, WeightedIndex};
+use rand::distributions::{Distribution, Uniform, WeightedError, WeightedIndex};
 
 use crate::{configure::*, types::*};
 
@@ -11,24 +11,54 @@ use crate::{configure::*, types::*};
 /// can say token X will be twice as likely to be randomly
 /// selected by this sampler.
 ///
+/// With `gamma`, the draw can be sharpened (or flattened) without changing the
+/// probabilities that earlier samplers used for filtering: tokens are drawn with
+/// weights proportional to `prob^gamma`. `gamma` greater than `1.0` concentrates
+/// the draws on the most probable tokens, `0.0` draws uniformly from the tokens with
+/// non-zero probability.
+///
 /// **Properties**:
 /// - Modifies logits
 /// - Selects a token
 ///
 /// **Parameters**:
-/// - (none)
-#[derive(Debug, Default, Clone)]
+/// - `gamma`: Exponent applied to the probabilities for the draw. Must be at least
+///   `0.0`. (default: `1.0`)
+#[derive(Debug, Clone)]
 pub struct SampleRandDistrib {
+    pub(crate) gamma: L,
     token_id: Option<TID>,
 }
 
+impl Default for SampleRandDistrib {
+    fn default() -> Self {
+        Self {
+            gamma: 1f32,
+            token_id: None,
+        }
+    }
+}
+
 impl SampleRandDistrib {
     pub fn new() -> Self {
-        Self { token_id: None }
+        Self::default()
+    }
+
+    pub fn gamma(mut self, val: L) -> Self {
+        self.gamma = val;
+        self
     }
 }
 
 impl Sampler for SampleRandDistrib {
+    fn options_summary(&self) -> Option<String> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_options(self).to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
     fn sample<'a>(
         &mut self,
         res: &mut dyn HasSamplerResources,
@@ -41,32 +71,93 @@ impl Sampler for SampleRandDistrib {
         logits.ensure_softmax().map_err(|e| {
             SamplerError::InternalError(format!("Failed to ensure softmax before sampling: {}", e))
         })?;
-        let dist = WeightedIndex::new(logits.iter().map(|l| l.prob))
-            .map_err(SamplerError::RandWeightedError)?;
+        if self.gamma == 1f32 {
+            // Same draw as WeightedIndex, but the cached probability sum means the
+            // cumulative weights don't need to be collected first.
+            let total = logits.prob_sum();
+            if !(total > 0f32 && total.is_finite()) {
+                Err(SamplerError::RandWeightedError(
+                    WeightedError::AllWeightsZero,
+                ))?
+            }
+            let mut chosen = 0f32;
+            res.with_rng_mut(&mut |r| chosen = Uniform::new(0f32, total).sample(r))?;
+            let mut cum_sum = 0f32;
+            let idx = logits
+                .iter()
+                .position(|l| {
+                    cum_sum += l.prob;
+                    cum_sum > chosen
+                })
+                .unwrap_or(logits.len() - 1);
+            self.token_id = Some(logits[idx].token_id);
+            return Ok(logits);
+        }
+        let dist = {
+            // Relative to the most probable token to avoid underflow with large gamma.
+            let (gamma, max_ln) = (self.gamma, logits[0].prob.ln());
+            WeightedIndex::new(logits.iter().map(|l| {
+                if l.prob > 0f32 {
+                    (gamma * (l.prob.ln() - max_ln)).exp()
+                } else {
+                    0f32
+                }
+            }))
+        }
+        .map_err(SamplerError::RandWeightedError)?;
         res.with_rng_mut(&mut |r| {
             self.token_id = Some(logits[dist.sample(r)].token_id);
         })?;
         Ok(logits)
     }
 
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX
+    }
+
     fn sampled_token_id(&self) -> Option<TID> {
         self.token_id
     }
-}
 
-impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F>
-    for SampleRandDistrib
-{
+    fn is_stochastic(&self) -> bool {
+        true
+    }
 }
 
-impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F>
-    for SampleRandDistrib
-{
+impl<UI: ConfigurableNumValue> ConfigurableSampler<UI, L> for SampleRandDistrib {}
+
+impl<UI: ConfigurableNumValue> HasSamplerMetadata<UI, L> for SampleRandDistrib {
     fn sampler_metadata(&self) -> SamplerMetadata {
         SamplerMetadata {
             name: "random distribution",
             description: Some("Randomly selects a token based on its probability."),
-            options: vec![],
+            options: vec![SamplerOptionMetadata {
+                key: "gamma",
+                description: Some(concat!(
+                    "Draw with weights proportional to the probability raised to this power. ",
+                    "Higher values make the draw favor the most probable tokens more."
+                )),
+                option_type: SamplerOptionType::Float,
+                advanced: true,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, UI, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                HasSamplerMetadata::<UI, L>::sampler_metadata(self).options,
+                [Some(SamplerOptionValueMut::Float(&mut self.gamma))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, UI, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                HasSamplerMetadata::<UI, L>::sampler_metadata(self).options,
+                [Some(SamplerOptionValue::Float(self.gamma))],
+            )
         }
     }
 }
@@ -0,0 +1,119 @@
+use crate::{configure::*, types::*};
+
+/// # Rank penalty sampling
+/// Penalizes tokens based on their rank in the sorted distribution rather than their
+/// probability: `penalty * ln(rank + 1)` is subtracted from each logit, where the most
+/// probable token has rank `0` (and so isn't penalized). Less probable tokens get larger
+/// penalties, which stretches the distribution in a rank-aware way that differs from
+/// both temperature and top-k.
+///
+/// Since the penalty never decreases with rank, the sorted order is preserved.
+/// Tokens with equal logits will end up with different logits based on their position.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `penalty`: Penalty scale. Setting this to `0.0` or lower disables the sampler. (default: `0.0`)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SampleRankPenalty {
+    pub(crate) penalty: L,
+}
+
+impl SampleRankPenalty {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleRankPenalty::try_new].
+    pub fn new(penalty: L) -> Self {
+        Self { penalty }
+    }
+
+    /// Like [SampleRankPenalty::new] but the parameters are validated: `penalty` must be
+    /// finite and at least `0.0`.
+    pub fn try_new(penalty: L) -> Result<Self, SamplerError> {
+        check_option(
+            "penalty",
+            penalty >= 0f32 && penalty.is_finite(),
+            "a finite value of at least 0.0",
+            penalty,
+        )?;
+        Ok(Self::new(penalty))
+    }
+
+    pub fn penalty(mut self, val: L) -> Self {
+        self.penalty = val;
+        self
+    }
+}
+
+impl Sampler for SampleRankPenalty {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let penalty = self.penalty;
+        if penalty <= 0f32 || logits.len() < 2 {
+            return Ok(logits);
+        }
+        logits
+            .ensure_sorted()
+            .map_err(|e| SamplerError::InternalError(format!("Failed to ensure sorted: {}", e)))?;
+        logits
+            .iter_mut()
+            .enumerate()
+            .skip(1)
+            .for_each(|(rank, l)| l.logit -= penalty * (rank as L + 1f32).ln());
+        logits.set_softmax(false);
+        Ok(logits)
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SORTED.when(self.penalty > 0f32)
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleRankPenalty {}
+
+impl HasSamplerMetadata<usize, L> for SampleRankPenalty {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "rank penalty",
+            description: Some(concat!(
+                "Penalizes tokens based on their rank in the sorted distribution ",
+                "by subtracting penalty * ln(rank + 1) from each logit."
+            )),
+            options: vec![SamplerOptionMetadata {
+                key: "penalty",
+                description: Some("Penalty scale."),
+                option_type: SamplerOptionType::Float,
+                advanced: false,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValueMut::Float(&mut self.penalty))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValue::Float(self.penalty))],
+            )
+        }
+    }
+}
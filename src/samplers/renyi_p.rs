@@ -0,0 +1,162 @@
+use crate::{configure::*, types::*};
+
+/// # Rényi-P sampling
+/// A variant of top-p sampling that adds up the squared token probabilities
+/// (related to the collision entropy, Rényi entropy of order 2) instead of the
+/// probabilities. Tokens are kept until the sum is greater or equal to `q` times
+/// the sum of all squared probabilities and at least `min_keep` tokens have
+/// been encountered. The remaining tokens are eliminated.
+///
+/// Squaring emphasizes the most probable tokens, so for peaked distributions the
+/// cutoff is sharper than top-p with the same value. For flat distributions it
+/// behaves much like top-p.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `min_keep`: Minimum number of entries to keep. (default: `1`)
+/// - `q`: Target value. Use `1.0` to disable. (default: `0.9`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleRenyiP {
+    pub(crate) q: L,
+    pub(crate) min_keep: usize,
+}
+
+impl Default for SampleRenyiP {
+    fn default() -> Self {
+        Self {
+            q: 0.9f32,
+            min_keep: 1,
+        }
+    }
+}
+
+impl SampleRenyiP {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleRenyiP::try_new].
+    pub fn new(q: L, min_keep: usize) -> Self {
+        Self { q, min_keep }
+    }
+
+    /// Like [SampleRenyiP::new] but the parameters are validated: `q` must be between `0.0`
+    /// and `1.0` and `min_keep` must be at least `1`.
+    pub fn try_new(q: L, min_keep: usize) -> Result<Self, SamplerError> {
+        check_option("q", (0f32..=1f32).contains(&q), "between 0.0 and 1.0", q)?;
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(q, min_keep))
+    }
+
+    pub fn min_keep(mut self, val: usize) -> Self {
+        self.min_keep = val;
+        self
+    }
+
+    pub fn q(mut self, val: L) -> Self {
+        self.q = val;
+        self
+    }
+}
+
+impl Sampler for SampleRenyiP {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let Self { q, min_keep } = *self;
+        let min_keep = logits.effective_min_keep(min_keep);
+        if q >= 1f32 || logits.is_empty() {
+            return Ok(logits);
+        }
+
+        logits.ensure_softmax().map_err(|e| {
+            SamplerError::InternalError(format!("Failed to ensure softmax before sampling: {}", e))
+        })?;
+
+        let target = q * logits.iter().map(|l| l.prob * l.prob).sum::<L>();
+        let mut cum_sum = 0f32;
+        let last_idx = logits
+            .iter()
+            .position(|l| {
+                cum_sum += l.prob * l.prob;
+                cum_sum >= target
+            })
+            .map_or(logits.len(), |idx| (idx + 1).max(min_keep));
+        if last_idx < logits.len() {
+            logits.truncate(last_idx);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(self.q < 1f32)
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleRenyiP {}
+
+impl HasSamplerMetadata<usize, L> for SampleRenyiP {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "renyi-p",
+            description: Some(concat!(
+                "This sampler adds up the squared token probabilities until the value is ",
+                "greater or equal to q times the sum of all squared probabilities and at ",
+                "least min_keep tokens have been encountered. The remaining tokens are eliminated."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "q",
+                    description: Some(
+                        "Target value for cumulative squared probabilities, relative to their total.",
+                    ),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "min_keep",
+                    description: Some(concat!(
+                        "Minimum number of tokens to keep after sampling. ",
+                        "Setting this to 0 is not recommended."
+                    )),
+                    option_type: SamplerOptionType::UInt,
+                    advanced: true,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.q)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.q)),
+                    Some(SamplerOptionValue::UInt(self.min_keep)),
+                ],
+            )
+        }
+    }
+}
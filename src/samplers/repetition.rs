@@ -1,6 +1,9 @@
-use std::marker::PhantomData;
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+};
 
-use crate::{configure::*, types::*};
+use crate::{configure::*, resource::resolve_penalty_last_n, types::*};
 
 // FIXME: Complete documentation.
 /// # Repetition penalty sampling
@@ -11,12 +14,33 @@ use crate::{configure::*, types::*};
 /// - Modifies logits
 ///
 /// **Parameters**:
-/// - `last_n`: Number of last tokens to consider. (default: `64`)
+/// - `last_n`: Number of last tokens to consider. Overridden by the `penalty_last_n`
+///   resource when it's available. (default: `64`)
 /// - `repetition_penalty`: Penalty to apply to repeated tokens. (default: `1.1`)
+/// - `skip_prompt`: Don't penalize tokens that came from the prompt. Requires the
+///   `prompt_len` resource. (default: `false`)
+/// - `min_logit_floor`: The penalty won't push a logit below this value. Logits that
+///   already start out below it are left alone. (default: `-inf`, meaning no floor)
+/// - `exclude`: Token ids that are never penalized, for example newlines or punctuation.
+///   (default: empty)
+/// - `windows`: Additional `(last_n, repetition_penalty)` pairs, for example a light
+///   penalty over a long window combined with a strong penalty over a short one.
+///   (default: empty)
+///
+/// When there are additional windows, the main `last_n`/`repetition_penalty` pair is
+/// applied first followed by the additional windows in the order they were specified.
+/// A token that appears in several windows is penalized by each of them in turn, so
+/// the result is the same as stacking a [SampleRepetition] per window but the token
+/// history only gets scanned once. Only the main `last_n` is overridden by the
+/// `penalty_last_n` resource.
 #[derive(Debug, Clone)]
 pub struct SampleRepetition<TID = u32, L = f32> {
     pub(crate) repetition_penalty: L,
     pub(crate) last_n: usize,
+    pub(crate) skip_prompt: bool,
+    pub(crate) min_logit_floor: L,
+    pub(crate) exclude: HashSet<TID>,
+    pub(crate) windows: Vec<(usize, L)>,
     marker: PhantomData<TID>,
 }
 
@@ -25,20 +49,42 @@ impl Default for SampleRepetition {
         Self {
             repetition_penalty: 1.1f32,
             last_n: 64,
+            skip_prompt: false,
+            min_logit_floor: f32::NEG_INFINITY,
+            exclude: HashSet::default(),
+            windows: vec![],
             marker: PhantomData,
         }
     }
 }
 
 impl SampleRepetition {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleRepetition::try_new].
     pub fn new(repetition_penalty: L, last_n: usize) -> Self {
         Self {
             repetition_penalty,
             last_n,
+            skip_prompt: false,
+            min_logit_floor: f32::NEG_INFINITY,
+            exclude: HashSet::default(),
+            windows: vec![],
             marker: PhantomData,
         }
     }
 
+    /// Like [SampleRepetition::new] but the parameters are validated: `repetition_penalty`
+    /// must be finite and at least `1.0`.
+    pub fn try_new(repetition_penalty: L, last_n: usize) -> Result<Self, SamplerError> {
+        check_option(
+            "repetition_penalty",
+            repetition_penalty >= 1f32 && repetition_penalty.is_finite(),
+            "a finite value of at least 1.0",
+            repetition_penalty,
+        )?;
+        Ok(Self::new(repetition_penalty, last_n))
+    }
+
     pub fn last_n(mut self, val: usize) -> Self {
         self.last_n = val;
         self
@@ -48,9 +94,37 @@ impl SampleRepetition {
         self.repetition_penalty = val;
         self
     }
+
+    pub fn skip_prompt(mut self, val: bool) -> Self {
+        self.skip_prompt = val;
+        self
+    }
+
+    pub fn min_logit_floor(mut self, val: L) -> Self {
+        self.min_logit_floor = val;
+        self
+    }
+
+    pub fn exclude<I: IntoIterator<Item = TID>>(mut self, it: I) -> Self {
+        self.exclude = HashSet::from_iter(it);
+        self
+    }
+
+    pub fn windows<I: IntoIterator<Item = (usize, L)>>(mut self, it: I) -> Self {
+        self.windows = it.into_iter().collect();
+        self
+    }
 }
 
 impl Sampler for SampleRepetition {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         res: &mut dyn HasSamplerResources,
@@ -59,33 +133,60 @@ impl Sampler for SampleRepetition {
         let Self {
             repetition_penalty,
             last_n,
+            skip_prompt,
+            min_logit_floor,
             ..
         } = *self;
+        let last_n = resolve_penalty_last_n(res, last_n)?;
 
-        if logits.is_empty() || last_n == 0 || repetition_penalty <= 1f32 {
+        let windows = std::iter::once((last_n, repetition_penalty))
+            .chain(self.windows.iter().copied())
+            .filter(|&(n, penalty)| n > 0 && penalty > 1f32)
+            .collect::<Vec<_>>();
+        if logits.is_empty() || windows.is_empty() {
             return Ok(logits);
         }
+        let max_n = windows.iter().map(|(n, _)| *n).max().unwrap_or(0);
+
+        let mut prompt_len = 0;
+        if skip_prompt {
+            res.with_prompt_len(&mut |pl| prompt_len = pl)?;
+        }
 
+        let exclude = &self.exclude;
         let mut changed = 0;
+        // Distance of the most recent occurrence of each token from the end of the history.
+        let mut distances = HashMap::<TID, usize>::default();
         res.with_last_tokens(&mut |tokens| {
-            let tokens = if last_n > tokens.len() {
-                tokens
-            } else {
-                &tokens[tokens.len() - last_n..]
-            };
+            let tokens = &tokens[prompt_len.min(tokens.len())..];
+            tokens
+                .iter()
+                .rev()
+                .take(max_n)
+                .enumerate()
+                .filter(|(_, tid)| !exclude.contains(tid))
+                .for_each(|(dist, tid)| {
+                    distances.entry(*tid).or_insert(dist);
+                });
+        })?;
 
-            logits
-                .iter_mut()
-                .filter(|l| tokens.contains(&l.token_id))
-                .for_each(|l| {
-                    l.logit = if l.logit <= 0f32 {
+        logits.iter_mut().for_each(|l| {
+            let Some(&dist) = distances.get(&l.token_id) else {
+                return;
+            };
+            windows
+                .iter()
+                .filter(|(n, _)| dist < *n)
+                .for_each(|&(_, repetition_penalty)| {
+                    let penalized = if l.logit <= 0f32 {
                         l.logit * repetition_penalty
                     } else {
                         l.logit / repetition_penalty
                     };
+                    l.logit = penalized.max(min_logit_floor.min(l.logit));
                     changed += 1;
                 });
-        })?;
+        });
 
         if changed > 0 {
             logits.set_sorted(false);
@@ -112,6 +213,7 @@ impl HasSamplerMetadata<usize, L> for SampleRepetition {
                         "Penalty to apply to tokens that meet the repetition criteria.",
                     ),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "last_n",
@@ -119,6 +221,24 @@ impl HasSamplerMetadata<usize, L> for SampleRepetition {
                         "Number of previous tokens to consider when determining repetition.",
                     ),
                     option_type: SamplerOptionType::UInt,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "skip_prompt",
+                    description: Some(
+                        "Don't apply the penalty to tokens that came from the prompt.",
+                    ),
+                    option_type: SamplerOptionType::Bool,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "min_logit_floor",
+                    description: Some(concat!(
+                        "The penalty won't push a logit below this value. ",
+                        "Use -inf for no floor."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                    advanced: true,
                 },
             ],
         }
@@ -131,6 +251,8 @@ impl HasSamplerMetadata<usize, L> for SampleRepetition {
                 [
                     Some(SamplerOptionValueMut::Float(&mut self.repetition_penalty)),
                     Some(SamplerOptionValueMut::UInt(&mut self.last_n)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.skip_prompt)),
+                    Some(SamplerOptionValueMut::Float(&mut self.min_logit_floor)),
                 ],
             )
         }
@@ -143,6 +265,8 @@ impl HasSamplerMetadata<usize, L> for SampleRepetition {
                 [
                     Some(SamplerOptionValue::Float(self.repetition_penalty)),
                     Some(SamplerOptionValue::UInt(self.last_n)),
+                    Some(SamplerOptionValue::Bool(self.skip_prompt)),
+                    Some(SamplerOptionValue::Float(self.min_logit_floor)),
                 ],
             )
         }
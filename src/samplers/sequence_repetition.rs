@@ -27,12 +27,12 @@ use crate::{configure::*, types::*};
 /// - `min_length`: The minimum length for a sequence to match. (default: `0`)
 /// - `flat_penalty`: Flat penalty to apply to the token that would continue the matched sequence. (default: `0.0`)
 /// - `stacking_penalty`: Stacking penalty to the token that would continue the matched sequence,
-///     it is multiplied by the sequence length. (default: `0.0`)
+///   it is multiplied by the sequence length. (default: `0.0`)
 /// - `tolerance`: Tolerance basically acts like a wildcard to allow fuzzy sequence matching.
-///     For example, if tolerance is set to `1`, then `1, 6, 3` could match with `1, 2, 3`. (default: `0`)
+///   For example, if tolerance is set to `1`, then `1, 6, 3` could match with `1, 2, 3`. (default: `0`)
 /// - `max_merge`: Controls the number of consecutive non-matching tokens that
-///     the tolerance wildcard can match. Setting this to `0` or `1` deactivates it.
-///     Setting it to 2 would allow `1, 6, 6, 3` to match with `1, 2, 3`. (default: `1`)
+///   the tolerance wildcard can match. Setting this to `0` or `1` deactivates it.
+///   Setting it to 2 would allow `1, 6, 6, 3` to match with `1, 2, 3`. (default: `1`)
 
 #[derive(Debug, Clone)]
 pub struct SampleSeqRepetition {
@@ -196,6 +196,14 @@ fn fuzzy_match<T: PartialEq + std::fmt::Debug>(
 }
 
 impl Sampler for SampleSeqRepetition {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         res: &mut dyn HasSamplerResources,
@@ -287,6 +295,7 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                         "would continue the matched sequence."
                     )),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "stacking_penalty",
@@ -295,11 +304,13 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                         "it is multiplied by the sequence length."
                     )),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "min_length",
                     description: Some("The minimum length for a sequence to match."),
                     option_type: SamplerOptionType::UInt,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "tolerance",
@@ -309,6 +320,7 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                         "then [1, 6, 3] could match with [1, 2, 3]."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
                 SamplerOptionMetadata {
                     key: "max_merge",
@@ -318,6 +330,7 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                         "Setting it to 2 would allow [1, 6, 6, 3] to match with [1, 2, 3]."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
                 SamplerOptionMetadata {
                     key: ("last_n"),
@@ -326,6 +339,7 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                         "determining sequence repetition."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: false,
                 },
             ],
         }
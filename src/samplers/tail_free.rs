@@ -5,6 +5,11 @@ use crate::{configure::*, types::*};
 /// nucleus (top-p and top-k) methods.
 /// See: <https://trentbrick.github.io/Tail-Free-Sampling/>
 ///
+/// Tokens are eliminated starting from the point where the cumulative normalized
+/// second derivatives are greater or equal to `z`. Like
+/// [SampleTopP](crate::samplers::SampleTopP), a threshold that lands exactly on a
+/// boundary is inclusive.
+///
 /// **Properties**:
 /// - Modifies logits
 /// - Filters logits
@@ -29,10 +34,20 @@ impl Default for SampleTailFree {
 }
 
 impl SampleTailFree {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTailFree::try_new].
     pub fn new(z: L, min_keep: usize) -> Self {
         Self { z, min_keep }
     }
 
+    /// Like [SampleTailFree::new] but the parameters are validated: `z` must be between
+    /// `0.0` and `1.0` and `min_keep` must be at least `1`.
+    pub fn try_new(z: L, min_keep: usize) -> Result<Self, SamplerError> {
+        check_option("z", (0f32..=1f32).contains(&z), "between 0.0 and 1.0", z)?;
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(z, min_keep))
+    }
+
     pub fn min_keep(mut self, val: usize) -> Self {
         self.min_keep = val;
         self
@@ -45,6 +60,14 @@ impl SampleTailFree {
 }
 
 impl Sampler for SampleTailFree {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
@@ -53,63 +76,47 @@ impl Sampler for SampleTailFree {
         use std::ops::ControlFlow::*;
 
         let Self { z, min_keep } = *self;
+        let min_keep = logits.effective_min_keep(min_keep);
 
         if z >= 1f32 || logits.len() < 2 {
             return Ok(logits);
         }
 
-        logits.ensure_softmax().map_err(|e| {
-            SamplerError::InternalError(format!("Failed to ensure softmax: {}", e.to_string()))
-        })?;
-
-        let mut fderivs = logits
-            .iter()
-            .take(logits.len() - 1)
-            .enumerate()
-            .map(|(idx, l)| l.prob - logits[idx + 1].prob)
-            .peekable();
-
-        let want_sderivs = logits.len() - 2;
-        let mut sderivs = Vec::with_capacity(want_sderivs);
-        let mut ssum = 0f32;
-
-        while let Some(prob) = fderivs.next() {
-            let sprob = (prob
-                - *fderivs.peek().ok_or_else(|| {
-                    SamplerError::InternalError(String::from(
-                        "Impossible: missing next deriv item?",
-                    ))
-                })?)
-            .abs();
-            ssum += sprob;
-            sderivs.push(sprob);
-            if sderivs.len() == want_sderivs {
-                break;
-            }
-        }
-        sderivs.iter_mut().for_each(|prob| *prob /= ssum);
+        logits
+            .ensure_softmax()
+            .map_err(|e| SamplerError::InternalError(format!("Failed to ensure softmax: {}", e)))?;
+
+        // Second derivatives are recomputed on demand rather than collected: the first pass
+        // only needs the total for normalization and the second pass can stop as soon as
+        // the cutoff is found, which is usually long before the end of the tail.
+        let sderiv = |idx: usize| {
+            let (p0, p1, p2) = (logits[idx].prob, logits[idx + 1].prob, logits[idx + 2].prob);
+            ((p0 - p1) - (p1 - p2)).abs()
+        };
+        let n_sderivs = logits.len() - 2;
+        let ssum = (0..n_sderivs).fold(0f32, |ssum, idx| ssum + sderiv(idx));
 
         let mut cum_sum = 0f32;
-        let last_idx =
-            match sderivs
-                .into_iter()
-                .enumerate()
-                .try_fold(logits.len(), |last_idx, (idx, prob)| {
-                    cum_sum += prob;
-                    if cum_sum > z && idx >= min_keep {
-                        return Break(idx);
-                    }
-                    Continue(last_idx)
-                }) {
-                Continue(i) => i,
-                Break(i) => i,
-            };
+        let last_idx = match (0..n_sderivs).try_fold(logits.len(), |last_idx, idx| {
+            cum_sum += sderiv(idx) / ssum;
+            if cum_sum >= z && idx >= min_keep {
+                return Break(idx);
+            }
+            Continue(last_idx)
+        }) {
+            Continue(i) => i,
+            Break(i) => i,
+        };
         if last_idx != logits.len() {
             logits.truncate(last_idx);
             logits.set_softmax(false);
         }
         Ok(logits)
     }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(self.z < 1f32)
+    }
 }
 
 impl ConfigurableSampler<usize, L> for SampleTailFree {}
@@ -132,6 +139,7 @@ impl HasSamplerMetadata<usize, L> for SampleTailFree {
                         "as disabled which is similar to top-p sampling."
                     )),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "min_keep",
@@ -140,6 +148,7 @@ impl HasSamplerMetadata<usize, L> for SampleTailFree {
                         "Setting this to 0 is not recommended."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
             ],
         }
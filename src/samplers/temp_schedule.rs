@@ -0,0 +1,205 @@
+use crate::{configure::*, prelude::PositionAware, samplers::SampleTemperature, types::*};
+
+/// # Temperature schedule sampling
+/// Like [SampleTemperature] but the temperature is interpolated from `start_temp` to
+/// `end_temp` over `steps` generation steps. After `steps` have passed, `end_temp` is used.
+/// For example, this can be used to start out creative and then converge to more focused
+/// output.
+///
+/// The current step is tracked with [PositionAware] from the length of the last tokens
+/// resource: the length when the sampler first runs (or first runs after
+/// [SampleTempSchedule::reset]) is step `0`.
+///
+/// The scheduled temperature is applied with [SampleTemperature], so temperatures below
+/// [SampleTemperature::GREEDY_THRESHOLD] only keep the token with the highest logit.
+///
+/// **Properties**:
+/// - Modifies logits
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `start_temp`: Temperature at the first step. (default: `1.0`)
+/// - `end_temp`: Temperature after `steps` have passed. (default: `0.5`)
+/// - `steps`: Number of steps to interpolate over. (default: `100`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleTempSchedule {
+    pub(crate) start_temp: L,
+    pub(crate) end_temp: L,
+    pub(crate) steps: usize,
+    position: PositionAware,
+}
+
+impl Default for SampleTempSchedule {
+    fn default() -> Self {
+        Self {
+            start_temp: 1f32,
+            end_temp: 0.5f32,
+            steps: 100,
+            position: PositionAware::new(),
+        }
+    }
+}
+
+impl SampleTempSchedule {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTempSchedule::try_new].
+    pub fn new(start_temp: L, end_temp: L, steps: usize) -> Self {
+        Self {
+            start_temp,
+            end_temp,
+            steps,
+            position: PositionAware::new(),
+        }
+    }
+
+    /// Like [SampleTempSchedule::new] but the parameters are validated: the temperatures
+    /// must be finite and at least `0.0`.
+    pub fn try_new(start_temp: L, end_temp: L, steps: usize) -> Result<Self, SamplerError> {
+        check_option(
+            "start_temp",
+            start_temp >= 0f32 && start_temp.is_finite(),
+            "a finite value of at least 0.0",
+            start_temp,
+        )?;
+        check_option(
+            "end_temp",
+            end_temp >= 0f32 && end_temp.is_finite(),
+            "a finite value of at least 0.0",
+            end_temp,
+        )?;
+        Ok(Self::new(start_temp, end_temp, steps))
+    }
+
+    pub fn start_temp(mut self, val: L) -> Self {
+        self.start_temp = val;
+        self
+    }
+
+    pub fn end_temp(mut self, val: L) -> Self {
+        self.end_temp = val;
+        self
+    }
+
+    pub fn steps(mut self, val: usize) -> Self {
+        self.steps = val;
+        self
+    }
+
+    /// Restart the schedule. The next time the sampler runs will be step `0`.
+    pub fn reset(&mut self) -> &mut Self {
+        self.position.reset();
+        self
+    }
+
+    /// Returns the position state, see [PositionAware].
+    pub fn position(&self) -> PositionAware {
+        self.position
+    }
+
+    /// Restore the position state, for example one returned by [SampleTempSchedule::position].
+    pub fn set_position(&mut self, position: PositionAware) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    /// Returns the temperature the schedule will use at the specified step.
+    pub fn temperature_at(&self, step: usize) -> L {
+        let Self {
+            start_temp,
+            end_temp,
+            steps,
+            ..
+        } = *self;
+        if step >= steps {
+            return end_temp;
+        }
+        start_temp + (end_temp - start_temp) * (step as L / steps as L)
+    }
+}
+
+impl Sampler for SampleTempSchedule {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let step = self.position.step(res)?;
+        SampleTemperature::new(self.temperature_at(step)).sample(res, logits)
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.position.context_shift(dropped);
+    }
+
+    fn reset(&mut self) {
+        SampleTempSchedule::reset(self);
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleTempSchedule {}
+
+impl HasSamplerMetadata<usize, L> for SampleTempSchedule {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "temperature schedule",
+            description: Some(concat!(
+                "Interpolates the temperature from start_temp to end_temp ",
+                "over the specified number of generation steps."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "start_temp",
+                    description: Some("Temperature at the first step."),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "end_temp",
+                    description: Some("Temperature once the specified steps have passed."),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "steps",
+                    description: Some("Number of steps to interpolate the temperature over."),
+                    option_type: SamplerOptionType::UInt,
+                    advanced: false,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.start_temp)),
+                    Some(SamplerOptionValueMut::Float(&mut self.end_temp)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.steps)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.start_temp)),
+                    Some(SamplerOptionValue::Float(self.end_temp)),
+                    Some(SamplerOptionValue::UInt(self.steps)),
+                ],
+            )
+        }
+    }
+}
@@ -1,12 +1,18 @@
-use crate::{configure::*, types::*};
+use crate::{configure::*, samplers::SampleGreedy, types::*};
 
 /// # Temperature sampling
 /// **Temperature** controls how random the output is. Only relevant when using
 /// samplers that utilize RNG.
 ///
+/// A temperature of `0.0` means deterministic output: when the temperature is at least
+/// `0.0` but below [SampleTemperature::GREEDY_THRESHOLD] (`1e-4`), only the token with
+/// the highest logit is kept, like [SampleGreedy] would select.
+/// This avoids the infinite logits that dividing by a tiny temperature can produce.
+///
 /// **Properties**:
 ///
 /// - Modifies logits
+/// - Filters logits
 ///
 /// **Parameters**:
 /// - `temperature`: Temperature value. (default: `0.8`)
@@ -22,10 +28,28 @@ impl Default for SampleTemperature {
 }
 
 impl SampleTemperature {
+    /// Temperatures at least `0.0` and below this collapse the logits to the most
+    /// probable token.
+    pub const GREEDY_THRESHOLD: L = 1e-4;
+
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTemperature::try_new].
     pub fn new(temperature: L) -> Self {
         Self { temperature }
     }
 
+    /// Like [SampleTemperature::new] but the parameters are validated: `temperature` must
+    /// be finite and at least `0.0`.
+    pub fn try_new(temperature: L) -> Result<Self, SamplerError> {
+        check_option(
+            "temperature",
+            temperature >= 0f32 && temperature.is_finite(),
+            "a finite value of at least 0.0",
+            temperature,
+        )?;
+        Ok(Self::new(temperature))
+    }
+
     pub fn temperature(mut self, val: L) -> Self {
         self.temperature = val;
         self
@@ -33,13 +57,30 @@ impl SampleTemperature {
 }
 
 impl Sampler for SampleTemperature {
+    fn options_summary(&self) -> Option<String> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_options(self).to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
     fn sample<'a>(
         &mut self,
-        _res: &mut dyn HasSamplerResources,
+        res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits, SamplerError> {
         let temp = self.temperature;
-        if temp != 0f32 {
+        if (0f32..Self::GREEDY_THRESHOLD).contains(&temp) {
+            let mut greedy = SampleGreedy::new();
+            greedy.sample(res, logits)?;
+            if let Some(tid) = greedy.sampled_token_id() {
+                if logits.len() > 1 {
+                    logits.retain(|l| l.token_id == tid);
+                    logits.set_softmax(false);
+                }
+            }
+        } else if temp != 0f32 {
             logits.iter_mut().for_each(|l| l.logit /= temp);
             logits.set_softmax(false);
         }
@@ -58,6 +99,7 @@ impl<UI: ConfigurableNumValue> HasSamplerMetadata<UI, L> for SampleTemperature {
                 key: "temperature",
                 description: Some("Temperature value. Higher values make the output more random."),
                 option_type: SamplerOptionType::Float,
+                advanced: false,
             }],
         }
     }
@@ -6,7 +6,6 @@ use crate::{configure::*, types::*};
 ///
 /// Credit to @BlinkDL on GitHub for design. See this link for a more in-depth
 /// explanation: https://github.com/BlinkDL/RWKV-LM#the-top-a-sampling-method
-
 ///
 /// **Properties**:
 /// - Filters logits
@@ -33,10 +32,31 @@ impl Default for SampleTopA {
 }
 
 impl SampleTopA {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTopA::try_new].
     pub fn new(a1: L, a2: L, min_keep: usize) -> Self {
         Self { a1, a2, min_keep }
     }
 
+    /// Like [SampleTopA::new] but the parameters are validated: `a1` and `a2` must be
+    /// finite and at least `0.0` and `min_keep` must be at least `1`.
+    pub fn try_new(a1: L, a2: L, min_keep: usize) -> Result<Self, SamplerError> {
+        check_option(
+            "a1",
+            a1 >= 0f32 && a1.is_finite(),
+            "a finite value of at least 0.0",
+            a1,
+        )?;
+        check_option(
+            "a2",
+            a2 >= 0f32 && a2.is_finite(),
+            "a finite value of at least 0.0",
+            a2,
+        )?;
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(a1, a2, min_keep))
+    }
+
     pub fn min_keep(mut self, val: usize) -> Self {
         self.min_keep = val;
         self
@@ -54,12 +74,21 @@ impl SampleTopA {
 }
 
 impl Sampler for SampleTopA {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits, SamplerError> {
         let Self { a1, a2, min_keep } = *self;
+        let min_keep = logits.effective_min_keep(min_keep);
         if logits.is_empty() || a1 == 0.0 || a2 == 0.0 {
             return Ok(logits);
         }
@@ -86,6 +115,10 @@ impl Sampler for SampleTopA {
         }
         Ok(logits)
     }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::SOFTMAX.when(self.a1 != 0f32 && self.a2 != 0f32)
+    }
 }
 
 impl ConfigurableSampler<usize, L> for SampleTopA {}
@@ -103,11 +136,13 @@ impl HasSamplerMetadata<usize, L> for SampleTopA {
                     key: "a1",
                     description: Some("Threshold multiplier."),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "a2",
                     description: Some("Threshold power."),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "min_keep",
@@ -116,6 +151,7 @@ impl HasSamplerMetadata<usize, L> for SampleTopA {
                         "Setting this to 0 is not recommended."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
                 },
             ],
         }
@@ -4,27 +4,69 @@ use crate::{configure::*, types::*};
 /// This sampler retains the top `MAX(k, min_keep)` tokens
 /// with the highest probability. The remaining tokens are eliminated.
 ///
+/// Only the relative order of the logits matters here, so softmax is never applied
+/// (unless `entropy_factor` is used) and when the [Logits] aren't sorted yet only the
+/// surviving tokens are sorted.
+///
+/// The number of tokens kept by the last run and whether `min_keep` made it more than
+/// `k` are available from [SampleTopK::cutoff_index] and [SampleTopK::min_keep_widened].
+///
 /// **Properties**:
 /// - Filters logits
 ///
 /// **Parameters**:
 /// - `min_keep`: Minimum number of entries to keep. (default: `1`)
 /// - `k`: Number of entries to keep. (default: `40`)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// - `entropy_factor`: When greater than `0.0`, `min_keep` scales with the entropy of
+///   the distribution. See [Logits::adaptive_min_keep]. (default: `0.0`)
+/// - `keep_ties`: Also keep tokens with the same logit as the last token that would
+///   be kept, so tokens tied at the boundary are never dropped arbitrarily. This can
+///   result in more than `k` tokens. (default: `false`)
+/// - `floor`: When set, eliminated tokens are kept with up to this probability instead of
+///   being removed, so they can still be selected rarely. See [Logits::truncate_with_floor].
+///   This isn't available as a configurable option. (default: `None`)
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleTopK {
     pub(crate) k: usize,
     pub(crate) min_keep: usize,
+    pub(crate) entropy_factor: L,
+    pub(crate) keep_ties: bool,
+    pub(crate) floor: Option<L>,
+    cutoff: Option<(usize, bool)>,
 }
 
 impl Default for SampleTopK {
     fn default() -> Self {
-        Self { min_keep: 1, k: 40 }
+        Self {
+            min_keep: 1,
+            k: 40,
+            entropy_factor: 0f32,
+            keep_ties: false,
+            floor: None,
+            cutoff: None,
+        }
     }
 }
 
 impl SampleTopK {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTopK::try_new].
     pub fn new(k: usize, min_keep: usize) -> Self {
-        Self { k, min_keep }
+        Self {
+            k,
+            min_keep,
+            entropy_factor: 0f32,
+            keep_ties: false,
+            floor: None,
+            cutoff: None,
+        }
+    }
+
+    /// Like [SampleTopK::new] but the parameters are validated: `min_keep` must be at least
+    /// `1`.
+    pub fn try_new(k: usize, min_keep: usize) -> Result<Self, SamplerError> {
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(k, min_keep))
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -36,29 +78,115 @@ impl SampleTopK {
         self.k = val;
         self
     }
+
+    pub fn entropy_factor(mut self, val: L) -> Self {
+        self.entropy_factor = val;
+        self
+    }
+
+    pub fn keep_ties(mut self, val: bool) -> Self {
+        self.keep_ties = val;
+        self
+    }
+
+    pub fn floor(mut self, val: Option<L>) -> Self {
+        self.floor = val;
+        self
+    }
+
+    /// Returns the number of tokens kept by the last run of the sampler, if it has run.
+    pub fn cutoff_index(&self) -> Option<usize> {
+        self.cutoff.map(|(idx, _)| idx)
+    }
+
+    /// Returns `true` if the last run of the sampler kept more than `k` tokens because of
+    /// `min_keep`, if it has run.
+    pub fn min_keep_widened(&self) -> Option<bool> {
+        self.cutoff.map(|(_, widened)| widened)
+    }
 }
 
 impl Sampler for SampleTopK {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits, SamplerError> {
-        let k = self.k.max(self.min_keep).min(logits.len());
-        logits.ensure_sorted().map_err(|e| {
-            SamplerError::InternalError(format!("Failed to ensure sorted: {}", e.to_string()))
-        })?;
-        if k != logits.len() {
-            logits.truncate(k);
-            logits.set_softmax(false);
-        }
+        let min_keep = logits
+            .adaptive_min_keep(self.min_keep, self.entropy_factor)
+            .map_err(|e| {
+                SamplerError::InternalError(format!("Failed to compute min_keep: {}", e))
+            })?;
+        let k = self.k.max(min_keep).min(logits.len());
+        let widened = k > self.k;
+        let k = select_top_k(logits, k, self.keep_ties)?;
+        logits
+            .truncate_with_floor(k, self.floor)
+            .map_err(|e| SamplerError::InternalError(format!("Failed to apply floor: {}", e)))?;
+        self.cutoff = Some((k, widened));
         Ok(logits)
     }
 }
 
-impl<L: ConfigurableNumValue> ConfigurableSampler<usize, L> for SampleTopK {}
+/// Moves the top `k` tokens to the front of `logits` in sorted order and returns how
+/// many of them should be kept, which is more than `k` when `keep_ties` pulls in tokens
+/// tied with the boundary token. Nothing is truncated here.
+pub(crate) fn select_top_k(
+    logits: &mut Logits,
+    mut k: usize,
+    keep_ties: bool,
+) -> Result<usize, SamplerError> {
+    if k != logits.len() && !logits.get_sorted() {
+        // Only ordering matters here, so rather than sorting the whole list we select
+        // the top k by raw logit and only sort those. Ties are broken by token id to
+        // match the result of a stable sort on logits in token id order.
+        let cmp = |a: &Logit, b: &Logit| {
+            b.logit
+                .total_cmp(&a.logit)
+                .then(a.token_id.cmp(&b.token_id))
+        };
+        if k > 0 {
+            logits.select_nth_unstable_by(k - 1, cmp);
+            if keep_ties {
+                // Move the tokens tied with the boundary token up next to the kept ones.
+                let (boundary, start) = (logits[k - 1].logit, k);
+                for idx in start..logits.len() {
+                    if logits[idx].logit == boundary {
+                        logits.swap(k, idx);
+                        k += 1;
+                    }
+                }
+            }
+        }
+        logits[..k].sort_unstable_by(cmp);
+        logits.set_sorted(true);
+        logits.set_softmax(false);
+        return Ok(k);
+    }
+    logits
+        .ensure_sorted()
+        .map_err(|e| SamplerError::InternalError(format!("Failed to ensure sorted: {}", e)))?;
+    if keep_ties && k > 0 {
+        let boundary = logits[k - 1].logit;
+        k += logits[k..]
+            .iter()
+            .take_while(|l| l.logit == boundary)
+            .count();
+    }
+    Ok(k)
+}
+
+impl ConfigurableSampler<usize, L> for SampleTopK {}
 
-impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
+impl HasSamplerMetadata<usize, L> for SampleTopK {
     fn sampler_metadata(&self) -> SamplerMetadata {
         SamplerMetadata {
             name: "top-k",
@@ -72,6 +200,7 @@ impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
                     key: "k",
                     description: Some("Number of tokens to keep."),
                     option_type: SamplerOptionType::UInt,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "min_keep",
@@ -80,6 +209,25 @@ impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
                         "Setting this to 0 is not recommended."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
+                },
+                SamplerOptionMetadata {
+                    key: "entropy_factor",
+                    description: Some(concat!(
+                        "When greater than 0, min_keep is scaled by the normalized entropy ",
+                        "of the distribution multiplied by this value."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                    advanced: true,
+                },
+                SamplerOptionMetadata {
+                    key: "keep_ties",
+                    description: Some(concat!(
+                        "Keep all tokens tied with the last token that would be kept, ",
+                        "even if that means keeping more than k tokens."
+                    )),
+                    option_type: SamplerOptionType::Bool,
+                    advanced: true,
                 },
             ],
         }
@@ -88,10 +236,12 @@ impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
     fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
         unsafe {
             SamplerOptions::build_options(
-                HasSamplerMetadata::<usize, L>::sampler_metadata(self).options,
+                self.sampler_metadata().options,
                 [
                     Some(SamplerOptionValueMut::UInt(&mut self.k)),
                     Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Float(&mut self.entropy_factor)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.keep_ties)),
                 ],
             )
         }
@@ -100,10 +250,12 @@ impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
     fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
         unsafe {
             SamplerOptions::build_options(
-                HasSamplerMetadata::<usize, L>::sampler_metadata(self).options,
+                self.sampler_metadata().options,
                 [
                     Some(SamplerOptionValue::UInt(self.k)),
                     Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Float(self.entropy_factor)),
+                    Some(SamplerOptionValue::Bool(self.keep_ties)),
                 ],
             )
         }
@@ -0,0 +1,213 @@
+use crate::{
+    configure::*,
+    samplers::{top_k::select_top_k, SampleTopK, SampleTopP},
+    types::*,
+};
+
+/// # Top-K then top-P sampling
+/// Convenience sampler that applies [SampleTopK] followed by [SampleTopP] in one step.
+/// The result is exactly the same as stacking the two samplers with the same `min_keep`.
+///
+/// The work is fused: the top `k` tokens are selected and sorted once, the exponentials
+/// are only computed for those and the cumulative walk only normalizes the tokens it
+/// visits, so the eliminated tail is never sorted or exponentiated.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `k`: Number of entries to keep with top-k. (default: `40`)
+/// - `p`: Target value for top-p. (default: `0.9`)
+/// - `min_keep`: Minimum number of entries to keep for both. (default: `1`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleTopKP {
+    pub(crate) k: usize,
+    pub(crate) p: L,
+    pub(crate) min_keep: usize,
+    cutoff: Option<(usize, bool)>,
+}
+
+impl Default for SampleTopKP {
+    fn default() -> Self {
+        Self {
+            k: 40,
+            p: 0.9f32,
+            min_keep: 1,
+            cutoff: None,
+        }
+    }
+}
+
+impl SampleTopKP {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTopKP::try_new].
+    pub fn new(k: usize, p: L, min_keep: usize) -> Self {
+        Self {
+            k,
+            p,
+            min_keep,
+            cutoff: None,
+        }
+    }
+
+    /// Like [SampleTopKP::new] but the parameters are validated: `p` must be between `0.0`
+    /// and `1.0` and `min_keep` must be at least `1`.
+    pub fn try_new(k: usize, p: L, min_keep: usize) -> Result<Self, SamplerError> {
+        SampleTopK::try_new(k, min_keep)?;
+        SampleTopP::try_new(p, min_keep)?;
+        Ok(Self::new(k, p, min_keep))
+    }
+
+    pub fn k(mut self, val: usize) -> Self {
+        self.k = val;
+        self
+    }
+
+    pub fn p(mut self, val: L) -> Self {
+        self.p = val;
+        self
+    }
+
+    pub fn min_keep(mut self, val: usize) -> Self {
+        self.min_keep = val;
+        self
+    }
+
+    /// Returns the number of tokens kept by the last run of the sampler, if it has run.
+    pub fn cutoff_index(&self) -> Option<usize> {
+        self.cutoff.map(|(idx, _)| idx)
+    }
+
+    /// Returns `true` if the last run of the sampler kept more tokens than `k` or `p`
+    /// alone would have because of `min_keep`, if it has run.
+    pub fn min_keep_widened(&self) -> Option<bool> {
+        self.cutoff.map(|(_, widened)| widened)
+    }
+}
+
+impl Sampler for SampleTopKP {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let Self { k, p, min_keep, .. } = *self;
+        self.cutoff = None;
+        let k = k.max(min_keep).min(logits.len());
+        let mut widened = k > self.k;
+        let k = select_top_k(logits, k, false)?;
+        logits
+            .truncate_with_floor(k, None)
+            .map_err(|e| SamplerError::InternalError(format!("Failed to truncate: {}", e)))?;
+        if logits.is_empty() {
+            return Ok(logits);
+        }
+        if p >= 1f32 {
+            self.cutoff = Some((logits.len(), widened));
+            return Ok(logits);
+        }
+
+        // The survivors are sorted now, so this matches what SampleTopP does on its own.
+        let total = if logits.get_softmax() {
+            logits.prob_sum()
+        } else {
+            let (max_l, base) = (logits[0].logit, logits.get_softmax_base());
+            logits.iter_mut().fold(0f32, |cs, l| {
+                l.prob = base.exp(l.logit - max_l);
+                cs + l.prob
+            })
+        };
+        let (len, min_keep) = (logits.len(), logits.effective_min_keep(min_keep));
+        let mut cum_sum = 0f32;
+        let mut last_idx = len;
+        for (idx, logit) in logits.iter_mut().enumerate() {
+            logit.prob /= total;
+            cum_sum += logit.prob;
+            if cum_sum >= p {
+                if idx + 1 >= min_keep {
+                    last_idx = idx + 1;
+                    break;
+                }
+                // Without min_keep the cutoff would be here.
+                widened = true;
+            }
+        }
+        self.cutoff = Some((last_idx, widened));
+        if last_idx != len {
+            logits
+                .truncate_with_floor(last_idx, None)
+                .map_err(|e| SamplerError::InternalError(format!("Failed to truncate: {}", e)))?;
+        } else {
+            logits.set_softmax(true);
+        }
+        Ok(logits)
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleTopKP {}
+
+impl HasSamplerMetadata<usize, L> for SampleTopKP {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "top-k/top-p",
+            description: Some("Applies top-k followed by top-p in one step."),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "k",
+                    description: Some("Number of tokens to keep with top-k."),
+                    option_type: SamplerOptionType::UInt,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "p",
+                    description: Some("Target value for cumulative probabilities with top-p."),
+                    option_type: SamplerOptionType::Float,
+                    advanced: false,
+                },
+                SamplerOptionMetadata {
+                    key: "min_keep",
+                    description: Some(concat!(
+                        "Minimum number of tokens to keep after sampling. ",
+                        "Setting this to 0 is not recommended."
+                    )),
+                    option_type: SamplerOptionType::UInt,
+                    advanced: true,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::UInt(&mut self.k)),
+                    Some(SamplerOptionValueMut::Float(&mut self.p)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::UInt(self.k)),
+                    Some(SamplerOptionValue::Float(self.p)),
+                    Some(SamplerOptionValue::UInt(self.min_keep)),
+                ],
+            )
+        }
+    }
+}
@@ -5,16 +5,42 @@ use crate::{configure::*, types::*};
 /// greater or equal to `p` and at least `min_keep` tokens have been
 /// encountered. The remaining tokens are eliminated.
 ///
+/// If the [Logits] don't already have softmax applied, only the surviving
+/// tokens get normalized probabilities: the tail is truncated before any
+/// more work is done on it.
+///
+/// When `p` is `1.0` or higher nothing can be eliminated, so the [Logits] are
+/// returned untouched: they won't be sorted and softmax won't be applied. Samplers
+/// that need probabilities always ensure softmax themselves, so this doesn't affect
+/// later samplers in a chain.
+///
 /// **Properties**:
 /// - Filters logits
 ///
 /// **Parameters**:
 /// - `min_keep`: Minimum number of entries to keep. (default: `1`)
 /// - `p`: Target value. (default: `0.9`)
+/// - `entropy_factor`: When greater than `0.0`, `min_keep` scales with the entropy of
+///   the distribution. See [Logits::adaptive_min_keep]. (default: `0.0`)
+/// - `inclusive_boundary`: Whether to keep the boundary token: the token whose probability
+///   makes the cumulative probability reach `p`. When `true`, the kept probability mass is
+///   greater or equal to `p`. When `false`, the boundary token is eliminated so the kept mass
+///   is less than `p` (`min_keep` still takes priority). (default: `true`)
+/// - `negligible_prob`: When greater than `0.0`, only tokens with a probability above this
+///   count toward `min_keep`. If there are fewer than `min_keep` of those, only they are kept
+///   rather than padding with tokens that have effectively zero probability. (default: `0.0`)
+/// - `floor`: When set, eliminated tokens are kept with up to this probability instead of
+///   being removed, so they can still be selected rarely. See [Logits::truncate_with_floor].
+///   This isn't available as a configurable option. (default: `None`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleTopP {
     pub(crate) p: L,
     pub(crate) min_keep: usize,
+    pub(crate) entropy_factor: L,
+    pub(crate) inclusive_boundary: bool,
+    pub(crate) negligible_prob: L,
+    pub(crate) floor: Option<L>,
+    cutoff: Option<(usize, L, bool)>,
 }
 
 impl Default for SampleTopP {
@@ -22,13 +48,36 @@ impl Default for SampleTopP {
         Self {
             p: 0.9f32,
             min_keep: 1,
+            entropy_factor: 0f32,
+            inclusive_boundary: true,
+            negligible_prob: 0f32,
+            floor: None,
+            cutoff: None,
         }
     }
 }
 
 impl SampleTopP {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTopP::try_new].
     pub fn new(p: L, min_keep: usize) -> Self {
-        Self { p, min_keep }
+        Self {
+            p,
+            min_keep,
+            entropy_factor: 0f32,
+            inclusive_boundary: true,
+            negligible_prob: 0f32,
+            floor: None,
+            cutoff: None,
+        }
+    }
+
+    /// Like [SampleTopP::new] but the parameters are validated: `p` must be between `0.0`
+    /// and `1.0` and `min_keep` must be at least `1`.
+    pub fn try_new(p: L, min_keep: usize) -> Result<Self, SamplerError> {
+        check_option("p", (0f32..=1f32).contains(&p), "between 0.0 and 1.0", p)?;
+        check_option("min_keep", min_keep >= 1, "at least 1", min_keep)?;
+        Ok(Self::new(p, min_keep))
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -40,9 +89,55 @@ impl SampleTopP {
         self.p = val;
         self
     }
+
+    pub fn entropy_factor(mut self, val: L) -> Self {
+        self.entropy_factor = val;
+        self
+    }
+
+    pub fn inclusive_boundary(mut self, val: bool) -> Self {
+        self.inclusive_boundary = val;
+        self
+    }
+
+    pub fn negligible_prob(mut self, val: L) -> Self {
+        self.negligible_prob = val;
+        self
+    }
+
+    pub fn floor(mut self, val: Option<L>) -> Self {
+        self.floor = val;
+        self
+    }
+
+    /// Returns the number of tokens kept by the last run of the sampler, if it has run.
+    pub fn cutoff_index(&self) -> Option<usize> {
+        self.cutoff.map(|(idx, _, _)| idx)
+    }
+
+    /// Returns the cumulative probability of the tokens kept by the last run of the
+    /// sampler, if it has run.
+    pub fn cutoff_cum_sum(&self) -> Option<L> {
+        self.cutoff.map(|(_, cum_sum, _)| cum_sum)
+    }
+
+    /// Returns `true` if the last run of the sampler kept more tokens than `p` alone
+    /// would have because of `min_keep`, if it has run. [SampleTopP::cutoff_index] and
+    /// [SampleTopP::cutoff_cum_sum] then report the widened cutoff.
+    pub fn min_keep_widened(&self) -> Option<bool> {
+        self.cutoff.map(|(_, _, widened)| widened)
+    }
 }
 
 impl Sampler for SampleTopP {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
     fn sample<'a>(
         &mut self,
         _res: &mut dyn HasSamplerResources,
@@ -50,29 +145,93 @@ impl Sampler for SampleTopP {
     ) -> anyhow::Result<&'a mut Logits, SamplerError> {
         use std::ops::ControlFlow::*;
 
-        let Self { p, min_keep } = *self;
-        logits.ensure_softmax().map_err(|e| {
-            SamplerError::InternalError(format!("Failed to ensure softmax before sampling: {}", e))
-        })?;
+        let Self {
+            p,
+            min_keep,
+            entropy_factor,
+            inclusive_boundary,
+            negligible_prob,
+            floor,
+            ..
+        } = *self;
+        self.cutoff = None;
+        if logits.is_empty() {
+            return Ok(logits);
+        }
+        if p >= 1f32 {
+            self.cutoff = Some((logits.len(), 1f32, false));
+            return Ok(logits);
+        }
+        let mut min_keep = logits
+            .adaptive_min_keep(min_keep, entropy_factor)
+            .map_err(|e| {
+                SamplerError::InternalError(format!("Failed to compute min_keep: {}", e))
+            })?;
+
+        // When softmax hasn't been applied yet we only compute the exponentials here
+        // and normalize as we walk the list, so the eliminated tail never gets divided.
+        let total = if logits.get_softmax() {
+            logits.prob_sum()
+        } else {
+            logits.ensure_sorted().map_err(|e| {
+                SamplerError::InternalError(format!("Failed to ensure sorted: {}", e))
+            })?;
+            let (max_l, base) = (logits[0].logit, logits.get_softmax_base());
+            logits.iter_mut().fold(0f32, |cs, l| {
+                l.prob = base.exp(l.logit - max_l);
+                cs + l.prob
+            })
+        };
+
+        if negligible_prob > 0f32 {
+            // The logits are sorted at this point, so the tokens that count are at the start.
+            let non_negligible = logits
+                .iter()
+                .take(min_keep)
+                .take_while(|l| l.prob / total > negligible_prob)
+                .count();
+            min_keep = min_keep.min(non_negligible.max(1));
+        }
 
         let mut cum_sum = 0f32;
+        let mut widened = false;
+        let len = logits.len();
         let last_idx =
             match logits
-                .iter()
+                .iter_mut()
                 .enumerate()
-                .try_fold(logits.len(), |last_idx, (idx, logit)| {
+                .try_fold(len, |last_idx, (idx, logit)| {
+                    logit.prob /= total;
                     cum_sum += logit.prob;
+                    if cum_sum >= p
+                        && (idx + 1 < min_keep || (!inclusive_boundary && idx + 1 == min_keep))
+                    {
+                        // Without min_keep the cutoff would be here.
+                        widened = true;
+                    }
                     if cum_sum >= p && idx + 1 >= min_keep {
-                        return Break(idx + 1);
+                        // An exclusive boundary never drops the top token, even when
+                        // min_keep is 0.
+                        if inclusive_boundary || idx + 1 == min_keep || idx == 0 {
+                            return Break(idx + 1);
+                        }
+                        cum_sum -= logit.prob;
+                        return Break(idx);
                     }
                     Continue(last_idx)
                 }) {
                 Continue(i) => i,
                 Break(i) => i,
             };
-        if last_idx != logits.len() {
-            logits.truncate(last_idx);
-            logits.set_softmax(false);
+        self.cutoff = Some((last_idx, cum_sum, widened));
+        if last_idx != len {
+            // Without a floor this only shrinks the length and the allocation is left
+            // alone. With a floor the tail is kept with adjusted logits instead.
+            logits.truncate_with_floor(last_idx, floor).map_err(|e| {
+                SamplerError::InternalError(format!("Failed to apply floor: {}", e))
+            })?;
+        } else {
+            logits.set_softmax(true);
         }
         Ok(logits)
     }
@@ -94,6 +253,7 @@ impl HasSamplerMetadata<usize, L> for SampleTopP {
                     key: "p",
                     description: Some("Target value for cumulative probabilities."),
                     option_type: SamplerOptionType::Float,
+                    advanced: false,
                 },
                 SamplerOptionMetadata {
                     key: "min_keep",
@@ -102,6 +262,34 @@ impl HasSamplerMetadata<usize, L> for SampleTopP {
                         "Setting this to 0 is not recommended."
                     )),
                     option_type: SamplerOptionType::UInt,
+                    advanced: true,
+                },
+                SamplerOptionMetadata {
+                    key: "entropy_factor",
+                    description: Some(concat!(
+                        "When greater than 0, min_keep is scaled by the normalized entropy ",
+                        "of the distribution multiplied by this value."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                    advanced: true,
+                },
+                SamplerOptionMetadata {
+                    key: "inclusive_boundary",
+                    description: Some(concat!(
+                        "Whether to keep the token that makes the cumulative ",
+                        "probability reach p."
+                    )),
+                    option_type: SamplerOptionType::Bool,
+                    advanced: true,
+                },
+                SamplerOptionMetadata {
+                    key: "negligible_prob",
+                    description: Some(concat!(
+                        "When greater than 0, only tokens with a probability above this ",
+                        "count toward min_keep."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                    advanced: true,
                 },
             ],
         }
@@ -114,6 +302,9 @@ impl HasSamplerMetadata<usize, L> for SampleTopP {
                 [
                     Some(SamplerOptionValueMut::Float(&mut self.p)),
                     Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Float(&mut self.entropy_factor)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.inclusive_boundary)),
+                    Some(SamplerOptionValueMut::Float(&mut self.negligible_prob)),
                 ],
             )
         }
@@ -126,6 +317,9 @@ impl HasSamplerMetadata<usize, L> for SampleTopP {
                 [
                     Some(SamplerOptionValue::Float(self.p)),
                     Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Float(self.entropy_factor)),
+                    Some(SamplerOptionValue::Bool(self.inclusive_boundary)),
+                    Some(SamplerOptionValue::Float(self.negligible_prob)),
                 ],
             )
         }
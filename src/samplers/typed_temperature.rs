@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use crate::{configure::*, samplers::SampleTemperature, types::*};
+
+/// # Typed temperature sampling
+/// Like [SampleTemperature] but takes a mapping from token ids to classes and applies a
+/// different temperature to each class. For example, this could be used to make the
+/// output more deterministic for code or structural tokens while keeping prose varied.
+/// Each logit is divided by the temperature for its class. Tokens without a class, or
+/// whose class doesn't have a temperature, use `temperature`.
+///
+/// A single token can't be selected for each class, so unlike [SampleTemperature]
+/// temperatures below [SampleTemperature::GREEDY_THRESHOLD] aren't greedy: they're
+/// raised to the threshold.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `temperature`: Temperature for tokens without a class temperature. (default: `1.0`)
+/// - `classes`: Token id to class pairs. (default: empty)
+/// - `class_temperatures`: Class to temperature pairs. (default: empty)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleTypedTemperature {
+    pub(crate) temperature: L,
+    pub(crate) classes: HashMap<TID, usize>,
+    pub(crate) class_temperatures: HashMap<usize, L>,
+}
+
+impl Default for SampleTypedTemperature {
+    fn default() -> Self {
+        Self {
+            temperature: 1f32,
+            classes: HashMap::default(),
+            class_temperatures: HashMap::default(),
+        }
+    }
+}
+
+impl SampleTypedTemperature {
+    /// **Note**: The parameters aren't validated, so out of range values can silently
+    /// result in unexpected sampling. See [SampleTypedTemperature::try_new].
+    pub fn new<I: IntoIterator<Item = (TID, usize)>, C: IntoIterator<Item = (usize, L)>>(
+        temperature: L,
+        classes: I,
+        class_temperatures: C,
+    ) -> Self {
+        Self {
+            temperature,
+            classes: HashMap::from_iter(classes),
+            class_temperatures: HashMap::from_iter(class_temperatures),
+        }
+    }
+
+    /// Like [SampleTypedTemperature::new] but the parameters are validated: the
+    /// temperatures must be finite and at least `0.0`.
+    pub fn try_new<I: IntoIterator<Item = (TID, usize)>, C: IntoIterator<Item = (usize, L)>>(
+        temperature: L,
+        classes: I,
+        class_temperatures: C,
+    ) -> Result<Self, SamplerError> {
+        let valid = |temp: L| temp >= 0f32 && temp.is_finite();
+        let requirement = "a finite value of at least 0.0";
+        let sampler = Self::new(temperature, classes, class_temperatures);
+        check_option("temperature", valid(temperature), requirement, temperature)?;
+        sampler.class_temperatures.values().try_for_each(|&temp| {
+            check_option("class temperature", valid(temp), requirement, temp)
+        })?;
+        Ok(sampler)
+    }
+
+    pub fn temperature(mut self, val: L) -> Self {
+        self.temperature = val;
+        self
+    }
+
+    /// Returns the temperature applied to the specified token id.
+    pub fn temperature_for(&self, token_id: TID) -> L {
+        self.classes
+            .get(&token_id)
+            .and_then(|class| self.class_temperatures.get(class))
+            .copied()
+            .unwrap_or(self.temperature)
+    }
+}
+
+impl Sampler for SampleTypedTemperature {
+    fn options_summary(&self) -> Option<String> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_options(self).to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(HasSamplerMetadata::<usize, L>::sampler_metadata(self))
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let mut changed = 0;
+        logits.iter_mut().for_each(|l| {
+            let mut temp = self.temperature_for(l.token_id);
+            if (0f32..SampleTemperature::GREEDY_THRESHOLD).contains(&temp) {
+                temp = SampleTemperature::GREEDY_THRESHOLD;
+            }
+            if temp != 1f32 {
+                l.logit /= temp;
+                changed += 1;
+            }
+        });
+        if changed > 0 {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+}
+
+// FIXME: Find a sane way to implement this for the mappings.
+impl<UI: ConfigurableNumValue> ConfigurableSampler<UI, L> for SampleTypedTemperature {}
+
+impl<UI: ConfigurableNumValue> HasSamplerMetadata<UI, L> for SampleTypedTemperature {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "typed temperature",
+            description: Some(concat!(
+                "Applies a different temperature to each class of tokens, ",
+                "based on a mapping from token ids to classes."
+            )),
+            options: vec![SamplerOptionMetadata {
+                key: "temperature",
+                description: Some("Temperature for tokens without a class temperature."),
+                option_type: SamplerOptionType::Float,
+                advanced: false,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, UI, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                HasSamplerMetadata::<UI, L>::sampler_metadata(self).options,
+                [Some(SamplerOptionValueMut::Float(&mut self.temperature))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, UI, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                HasSamplerMetadata::<UI, L>::sampler_metadata(self).options,
+                [Some(SamplerOptionValue::Float(self.temperature))],
+            )
+        }
+    }
+}
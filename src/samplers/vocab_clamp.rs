@@ -0,0 +1,106 @@
+use crate::{configure::*, types::*};
+
+/// # Vocabulary clamp sampling
+/// Eliminates tokens with an id higher than `max_id` by setting their logits to
+/// negative infinity. Some models have reserved or unused token ids at the end of
+/// their vocabulary that are still present in the logits, this sampler prevents
+/// those ids from ever being selected.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `max_id`: The highest allowed token id. (default: no limit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleVocabClamp {
+    pub(crate) max_id: usize,
+}
+
+impl Default for SampleVocabClamp {
+    fn default() -> Self {
+        Self { max_id: usize::MAX }
+    }
+}
+
+impl SampleVocabClamp {
+    pub fn new(max_id: TID) -> Self {
+        Self {
+            max_id: max_id as usize,
+        }
+    }
+
+    pub fn max_id(mut self, val: TID) -> Self {
+        self.max_id = val as usize;
+        self
+    }
+}
+
+impl Sampler for SampleVocabClamp {
+    fn options_summary(&self) -> Option<String> {
+        Some(self.sampler_options().to_string())
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        Some(self.sampler_metadata())
+    }
+
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        let max_id = self.max_id;
+        let mut changed = 0;
+
+        logits
+            .iter_mut()
+            .filter(|l| l.token_id as usize > max_id && l.logit != f32::NEG_INFINITY)
+            .for_each(|l| {
+                l.logit = f32::NEG_INFINITY;
+                changed += 1;
+            });
+        if changed > 0 {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+}
+
+impl ConfigurableSampler<usize, L> for SampleVocabClamp {}
+
+impl HasSamplerMetadata<usize, L> for SampleVocabClamp {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "vocabulary clamp",
+            description: Some(concat!(
+                "Eliminates tokens with an id higher than max_id by ",
+                "setting their logits to negative infinity."
+            )),
+            options: vec![SamplerOptionMetadata {
+                key: "max_id",
+                description: Some("The highest allowed token id."),
+                option_type: SamplerOptionType::UInt,
+                advanced: false,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValueMut::UInt(&mut self.max_id))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValue::UInt(self.max_id))],
+            )
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::{configure::*, types::*};
+
+/// Predicate for [SampleWhen].
+pub type SampleWhenPredicate = dyn Fn(&dyn HasSamplerResources, &Logits) -> bool + Send + Sync;
+
+/// # Conditional sampling
+/// Wraps a sampler and only runs it when a predicate returns `true` for the current
+/// resources and [Logits]. For example, this could be used to apply a strong repetition
+/// penalty only when the last tokens show a lot of repetition.
+///
+/// The sampled token id (if any) comes from the wrapped sampler when it ran,
+/// otherwise it's `None`. The options are forwarded from the wrapped sampler and the
+/// metadata name includes its name, for example `when(top-k)`, so both the wrapper
+/// and the wrapped sampler can be identified.
+///
+/// Since the wrapped sampler might not run, this doesn't have any requirements itself:
+/// the requirements of the wrapped sampler are ensured when it runs.
+///
+/// **Properties**:
+/// - Depends on the sampler it contains
+///
+/// **Parameters**:
+/// - `predicate`: Function that determines whether the sampler runs.
+#[derive(Clone)]
+pub struct SampleWhen<S> {
+    sampler: S,
+    predicate: Arc<SampleWhenPredicate>,
+    token: Option<TID>,
+}
+
+impl<S: Sampler> SampleWhen<S> {
+    pub fn new(
+        sampler: S,
+        predicate: impl Fn(&dyn HasSamplerResources, &Logits) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            sampler,
+            predicate: Arc::new(predicate),
+            token: None,
+        }
+    }
+
+    /// Returns a reference to the wrapped sampler.
+    pub fn sampler(&self) -> &S {
+        &self.sampler
+    }
+
+    /// Returns a mutable reference to the wrapped sampler.
+    pub fn sampler_mut(&mut self) -> &mut S {
+        &mut self.sampler
+    }
+}
+
+/// Returns `when(<name>)` for the metadata name of a wrapped sampler. Metadata names
+/// are `&'static str`, so the string for each distinct name is leaked once and reused.
+fn when_name(name: &'static str) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashMap<&'static str, &'static str>>> = OnceLock::new();
+    let mut names = NAMES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    names
+        .entry(name)
+        .or_insert_with(|| Box::leak(format!("when({name})").into_boxed_str()))
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for SampleWhen<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SampleWhen")
+            .field("sampler", &self.sampler)
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Sampler + Clone + 'static> Sampler for SampleWhen<S> {
+    fn sample_prepend(&self, res: &mut dyn HasSamplerResources) {
+        self.sampler.sample_prepend(res)
+    }
+
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits, SamplerError> {
+        self.token = None;
+        if !(self.predicate)(res, logits) {
+            return Ok(logits);
+        }
+        self.sampler.requires().ensure(logits)?;
+        let logits = self.sampler.sample(res, logits)?;
+        self.token = self.sampler.sampled_token_id();
+        Ok(logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<TID> {
+        self.token
+    }
+
+    /// Includes the name of the wrapped sampler, for example
+    /// `(SampleTopK [k: 40, min_keep: 1])`.
+    fn options_summary(&self) -> Option<String> {
+        Some(match self.sampler.options_summary() {
+            Some(summary) => format!("({} {summary})", self.sampler.name()),
+            None => format!("({})", self.sampler.name()),
+        })
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        let SamplerMetadata { name, options, .. } = self.sampler.metadata()?;
+        Some(SamplerMetadata {
+            name: when_name(name),
+            description: Some("Runs the wrapped sampler only when a predicate is true."),
+            options,
+        })
+    }
+
+    fn is_stochastic(&self) -> bool {
+        self.sampler.is_stochastic()
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::NONE
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        self.sampler.notify_context_shift(dropped)
+    }
+
+    fn reset(&mut self) {
+        self.token = None;
+        self.sampler.reset()
+    }
+}
+
+impl<UI, F, S> ConfigurableSampler<UI, F> for SampleWhen<S>
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+    S: ConfigurableSampler<UI, F>,
+{
+}
+
+impl<UI, F, S> HasSamplerMetadata<UI, F> for SampleWhen<S>
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+    S: HasSamplerMetadata<UI, F>,
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        let SamplerMetadata { name, options, .. } = self.sampler.sampler_metadata();
+        SamplerMetadata {
+            name: when_name(name),
+            description: Some("Runs the wrapped sampler only when a predicate is true."),
+            options,
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, UI, F>> {
+        self.sampler.sampler_options_mut()
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, UI, F>> {
+        self.sampler.sampler_options()
+    }
+}
@@ -116,109 +116,1842 @@ fn test_logits_with_top_k() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_logits_from_presorted() -> Result<()> {
+    use rand::{seq::SliceRandom, SeedableRng};
+
+    let mut v = Vec::from_iter((0u32..).zip((0..200).map(|n| (n as f32 * 0.37).sin())));
+    v.shuffle(&mut rand::rngs::StdRng::seed_from_u64(123));
+    let mut unsorted = Logits::from_logits_with_ids(
+        &v.iter().map(|&(_, l)| l).collect::<Vec<_>>(),
+        &v.iter().map(|&(tid, _)| tid).collect::<Vec<_>>(),
+    )?;
+    v.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut presorted = Logits::try_from_presorted(v.iter().copied())?;
+    assert!(presorted.is_sorted() && !presorted.is_softmax());
+    assert!(matches!(
+        Logits::try_from_presorted([(1, 0.5), (2, f32::NAN)]),
+        Err(LogitsError::InvalidLogit(2))
+    ));
+
+    let mut top_p = SampleTopP::new(0.8, 1);
+    top_p.sample(&mut NilSamplerResources, &mut presorted)?;
+    top_p.sample(&mut NilSamplerResources, &mut unsorted)?;
+    assert!(presorted.len() < 200);
+    assert_eq!(*presorted, *unsorted);
+    Ok(())
+}
+
+#[test]
+fn test_logits_from_slices() -> Result<()> {
+    let logits = Logits::from_logits_slice(&[0.5, 0.1, 0.9])?;
+    assert!(!logits.get_sorted() && !logits.get_softmax());
+    assert_eq!(
+        logits
+            .iter()
+            .map(|l| (l.token_id, l.logit))
+            .collect::<Vec<_>>(),
+        [(0, 0.5), (1, 0.1), (2, 0.9)]
+    );
+
+    let logits = Logits::from_logits_with_ids(&[0.5, 0.1, 0.9], &[7, 3, 42])?;
+    assert!(!logits.get_sorted() && !logits.get_softmax());
+    assert_eq!(
+        logits
+            .iter()
+            .map(|l| (l.token_id, l.logit))
+            .collect::<Vec<_>>(),
+        [(7, 0.5), (3, 0.1), (42, 0.9)]
+    );
+
+    assert!(matches!(
+        Logits::from_logits_with_ids(&[0.5, 0.1], &[1, 2, 3]),
+        Err(LogitsError::LengthMismatch { logits: 2, ids: 3 })
+    ));
+    assert!(matches!(
+        Logits::from_logits_with_ids(&[0.5, f32::NAN], &[1, 2]),
+        Err(LogitsError::InvalidLogit(2))
+    ));
+    assert!(Logits::from_logits_slice(&[f32::NAN]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_prob_sum() -> Result<()> {
+    use rand::{distributions::Distribution, SeedableRng};
+
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4, 0.5])?;
+    logits.ensure_softmax()?;
+    assert!((logits.prob_sum() - 1.0).abs() < 1e-6);
+    // Cached value is returned again.
+    assert!((logits.prob_sum() - 1.0).abs() < 1e-6);
+
+    let expected = logits[0].prob + logits[1].prob;
+    logits.truncate(2);
+    logits.set_softmax(false);
+    assert!((logits.prob_sum() - expected).abs() < 1e-6);
+
+    logits.ensure_softmax()?;
+    assert!((logits.prob_sum() - 1.0).abs() < 1e-6);
+
+    // Drawing with the cached sum picks the same tokens as WeightedIndex.
+    let mut logits = Logits::try_from_iter((0..50).map(|i| (i as f32 * 0.3).sin() * 4.0))?;
+    logits.ensure_softmax()?;
+    let dist = rand::distributions::WeightedIndex::new(logits.iter().map(|l| l.prob))?;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(123);
+    let mut res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let mut sampler = SampleRandDistrib::new();
+    for _ in 0..200 {
+        let expected = logits[dist.sample(&mut rng)].token_id;
+        assert_eq!(logits.sample_token(&mut res, &mut sampler)?, Some(expected));
+    }
+    assert_eq!(logits.percentile_cutoff(1.0)?, logits.len() - 1);
+    Ok(())
+}
+
+#[test]
+fn test_softmax_base() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.0f32, 0.0, 0.0, 0.0])?;
+    assert!((logits.entropy()? - 4f32.ln()).abs() < 1e-6);
+    logits.set_softmax_base(SoftmaxBase::Two);
+    assert!(!logits.get_softmax());
+    // Uniform over 4 tokens is 2 bits.
+    assert!((logits.entropy()? - 2.0).abs() < 1e-6);
+
+    let mut logits2 = Logits::try_from_iter(T1.iter().copied())?;
+    logits2
+        .set_softmax_base(SoftmaxBase::Two)
+        .ensure_softmax()?;
+    assert!((logits2.iter().map(|l| l.prob).sum::<f32>() - 1.0).abs() < 1e-6);
+    // Same as natural softmax with the logits scaled by ln(2).
+    let mut logits_nat = Logits::try_from_iter(T1.iter().map(|l| l * std::f32::consts::LN_2))?;
+    logits_nat.ensure_softmax()?;
+    logits2
+        .iter()
+        .zip(logits_nat.iter())
+        .for_each(|(l2, ln)| assert!((l2.prob - ln.prob).abs() < 1e-6));
+    assert!((logits2.entropy()? - logits_nat.entropy()? / std::f32::consts::LN_2).abs() < 1e-5);
+    Ok(())
+}
+
+#[test]
+fn test_logits_clear_and_fill() -> Result<()> {
+    let mut sc = SamplerChain::new() + SampleTopK::new(3, 1) + SampleTemperature::new(0.8);
+    let mut logits = Logits::from_logits_slice(&[0.5, 0.1, 0.9, 0.2, 0.3])?;
+    sc.sample(&mut (), &mut logits)?;
+    assert!(logits.get_sorted());
+
+    for input in [
+        &[0.4f32, 0.3, 0.2, 0.1][..],
+        &[1.0, 2.0],
+        &[0.1, 0.9, 0.2, 0.8, 0.3, 0.7],
+    ] {
+        let capacity = logits.capacity();
+        logits.clear_and_fill(input)?;
+        assert!(!logits.get_sorted() && !logits.get_softmax());
+        assert_eq!(logits.len(), input.len());
+        assert!(input.len() > capacity || logits.capacity() == capacity);
+
+        let mut fresh = Logits::from_logits_slice(input)?;
+        sc.sample(&mut (), &mut logits)?;
+        sc.sample(&mut (), &mut fresh)?;
+        assert_eq!(&*logits, &*fresh);
+    }
+
+    logits.clear_and_fill_with_ids(&[0.5, 0.1], &[7, 3])?;
+    assert_eq!(
+        logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+        [7, 3]
+    );
+    assert!(logits.clear_and_fill_with_ids(&[0.5], &[1, 2]).is_err());
+    assert!(logits.clear_and_fill(&[0.5, f32::NAN]).is_err());
+    assert!(logits.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_percentile_cutoff() -> Result<()> {
+    // Probabilities are roughly 0.279, 0.252, 0.228, 0.207.
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    assert_eq!(logits.percentile_cutoff(0.5)?, 1);
+    assert_eq!(logits.percentile_cutoff(0.2)?, 0);
+    assert_eq!(logits.percentile_cutoff(-1.0)?, 0);
+    assert_eq!(logits.percentile_cutoff(2.0)?, 3);
+    assert_eq!(Logits::default().percentile_cutoff(0.5)?, 0);
+
+    for q in [0.1f32, 0.3, 0.5, 0.7, 0.9] {
+        let mut top_p = SampleTopP::new(q, 1);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        let idx = logits.clone().percentile_cutoff(q)?;
+        top_p.sample(&mut (), &mut logits)?;
+        assert_eq!(top_p.cutoff_index(), Some(idx + 1), "Mismatch with q={q}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_softmax_range() -> Result<()> {
+    let mut logits = Logits::try_from_iter(T1.iter().map(|i| i.ln()))?;
+    logits.ensure_sorted()?;
+    logits.softmax_range(0, 2)?;
+    assert!(!logits.get_softmax());
+
+    let survivors = logits[0..2].iter().map(|l| l.prob).collect::<Vec<_>>();
+    assert!((survivors.iter().sum::<f32>() - 1.0).abs() < 0.00001);
+    assert!((survivors[0] - 0.4 / 0.7).abs() < 0.00001);
+    assert!(logits[2..].iter().all(|l| l.prob == 0.0));
+
+    let len = logits.len();
+    logits.softmax_range(0, len)?;
+    assert!(logits.get_softmax());
+    assert!(logits.softmax_range(3, 5).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_debug_with_tokens() -> Result<()> {
+    const VOCAB: &[&str] = &["a", "the", "\"quoted\"", "cat"];
+    let lookup = |tid: u32| VOCAB[tid as usize].to_string();
+
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    assert_eq!(
+        logits.debug_with_tokens(lookup, 2),
+        "\"cat\" (id=3): logit=0.40 prob=0.00\n\"\\\"quoted\\\"\" (id=2): logit=0.30 prob=0.00\n"
+    );
+    logits.ensure_softmax()?;
+    assert_eq!(
+        logits.debug_with_tokens(lookup, 1),
+        "\"cat\" (id=3): logit=0.40 prob=0.29\n"
+    );
+    assert_eq!(logits.debug_with_tokens(lookup, 10).lines().count(), 4);
+    Ok(())
+}
+
+#[test]
+fn test_token_ids_preserved() -> Result<()> {
+    const T: &[f32] = &[0.05, 0.3, 0.1, 0.4, 0.15];
+    let mut logits = Logits::try_from_iter(T.iter().map(|i| i.ln()))?;
+    logits.sample(&mut (), &mut SampleTopP::new(0.8, 1))?;
+    assert_eq!(
+        logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+        [3, 1, 4]
+    );
+    assert!(logits
+        .iter()
+        .all(|l| (l.logit - T[l.token_id as usize].ln()).abs() < 0.00001));
+
+    // Scatter the survivors back into a full size buffer.
+    let mut full = vec![0f32; T.len()];
+    logits.ensure_softmax()?;
+    logits
+        .iter()
+        .for_each(|l| full[l.token_id as usize] = l.prob);
+    assert_eq!(full[0], 0.0);
+    assert_eq!(full[2], 0.0);
+    assert!((full[3] - 0.4 / 0.85).abs() < 0.00001);
+    Ok(())
+}
+
+#[test]
+fn test_nan_policy() -> Result<()> {
+    const T: &[f32] = &[0.1, f32::NAN, 0.3, 0.4];
+    let token_ids = |logits: &Logits| logits.iter().map(|l| l.token_id).collect::<Vec<_>>();
+
+    assert!(matches!(
+        Logits::try_from_iter(T.iter().copied()),
+        Err(LogitsError::InvalidLogit(1))
+    ));
+    assert!(Logits::try_from_iter_with_nan_policy(T.iter().copied(), NanPolicy::Error).is_err());
+
+    let mut logits = Logits::try_from_iter_with_nan_policy(T.iter().copied(), NanPolicy::ZeroOut)?;
+    assert_eq!(token_ids(&logits), [0, 2, 3]);
+    logits.ensure_softmax()?;
+    assert!((logits.iter().map(|l| l.prob).sum::<f32>() - 1.0).abs() < 0.00001);
+
+    let mut logits = Logits::try_from_iter_with_nan_policy(T.iter().copied(), NanPolicy::MinusInf)?;
+    assert_eq!(token_ids(&logits), [0, 1, 2, 3]);
+    assert_eq!(logits[1].logit, f32::NEG_INFINITY);
+    logits.ensure_softmax()?;
+    assert_eq!(logits.last().map(|l| (l.token_id, l.prob)), Some((1, 0.0)));
+
+    // Logits modified after being built.
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3])?;
+    logits[2].logit = f32::NAN;
+    assert!(logits.apply_nan_policy(NanPolicy::Error).is_err());
+    assert_eq!(
+        logits.clone().apply_nan_policy(NanPolicy::MinusInf)?[2].logit,
+        f32::NEG_INFINITY
+    );
+    assert_eq!(
+        token_ids(logits.apply_nan_policy(NanPolicy::ZeroOut)?),
+        [0, 1]
+    );
+    Ok(())
+}
+
 #[test]
 fn test_chain1() -> anyhow::Result<()> {
     let mut res = NilSamplerResources;
     let mut logits = Logits::try_from_iter(T1.iter().copied())?;
 
-    let mut sc = SamplerChain::new()
-        + SampleFlatBias::new([(3, f32::NEG_INFINITY)])
-        + SampleFlatBias::new([(2, f32::NEG_INFINITY)])
-        + SampleGreedy::new();
+    let mut sc = SamplerChain::new()
+        + SampleFlatBias::new([(3, f32::NEG_INFINITY)])
+        + SampleFlatBias::new([(2, f32::NEG_INFINITY)])
+        + SampleGreedy::new();
+
+    assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(1));
+    Ok(())
+}
+
+#[test]
+fn test_chain2() -> Result<()> {
+    use rand::SeedableRng;
+    let mut res = SimpleSamplerResources::new(
+        Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+        Some(vec![]),
+    );
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    let mut logits2 = logits.clone();
+
+    let mut sc = SamplerChain::new()
+        + SampleFlatBias::new([(3, f32::NEG_INFINITY)])
+        + SampleRepetition::new(1.1, 64)
+        + SampleFreqPresence::new(0.05, 0.1, 64)
+        + SampleTemperature::new(0.8)
+        + SampleMirostat1::new(4, 5.0, 0.1);
+    res.with_last_tokens_mut(&mut |tokens| tokens.push(3u32))?;
+
+    assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(2));
+
+    res.with_last_tokens_mut(&mut |tokens| tokens.push(2u32))?;
+    assert_eq!(sc.sample_token(&mut res, &mut logits2)?, Some(1));
+    Ok(())
+}
+
+#[test]
+fn test_sample_report() -> Result<()> {
+    use rand::SeedableRng;
+    let mut res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let mut res2 =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let mut sc = SamplerChain::new() + SampleTopK::new(3, 1) + SampleRandDistrib::new();
+    let mut sc2 = SamplerChain::new() + SampleTopK::new(3, 1) + SampleRandDistrib::new();
+
+    for _ in 0..8 {
+        let mut logits = Logits::try_from_iter(T1.iter().map(|i| i.ln()))?;
+        let report = sc.sample_report(&mut res, &mut logits, 2)?;
+        let expected = sc2.sample_token(
+            &mut res2,
+            &mut Logits::try_from_iter(T1.iter().map(|i| i.ln()))?,
+        )?;
+
+        assert!(expected.is_some());
+        assert_eq!(report.token_id(), expected);
+        assert_eq!(
+            report
+                .alternatives
+                .iter()
+                .map(|l| l.token_id)
+                .collect::<Vec<_>>(),
+            [3, 2]
+        );
+        let chosen = report.chosen.expect("Missing chosen token");
+        assert!(chosen.prob > 0.0);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_chain_presets() -> Result<()> {
+    use rand::SeedableRng;
+    let debug_samplers = |sc: &SamplerChain| {
+        let s = format!("{sc:?}");
+        s[s.find('[').unwrap_or_default()..=s.rfind(']').unwrap_or_default()].to_string()
+    };
+
+    assert_eq!(
+        debug_samplers(&SamplerChain::greedy()),
+        "[SampleGreedy { token_id: None }]"
+    );
+    assert_eq!(
+        debug_samplers(&SamplerChain::creative()),
+        concat!(
+            "[SampleTopP { p: 0.95, min_keep: 1, entropy_factor: 0.0, inclusive_boundary: true, negligible_prob: 0.0, floor: None, cutoff: None }, ",
+            "SampleTemperature { temperature: 1.0 }, ",
+            "SampleRandDistrib { gamma: 1.0, token_id: None }]"
+        )
+    );
+    assert_eq!(
+        debug_samplers(&SamplerChain::precise()),
+        concat!(
+            "[SampleTopK { k: 20, min_keep: 1, entropy_factor: 0.0, keep_ties: false, floor: None, cutoff: None }, ",
+            "SampleTemperature { temperature: 0.3 }, ",
+            "SampleRandDistrib { gamma: 1.0, token_id: None }]"
+        )
+    );
+
+    let mut res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    for mut sc in [
+        SamplerChain::greedy(),
+        SamplerChain::creative(),
+        SamplerChain::precise(),
+    ] {
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        assert!(sc.sample_token(&mut res, &mut logits)?.is_some());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_chain_standard() -> Result<()> {
+    use rand::SeedableRng;
+
+    let mut sc = SamplerChain::standard(0.7, 5, 0.9, 2);
+    assert_eq!(sc.len(), 4);
+    assert!(sc
+        .get(0)
+        .and_then(|s| s.downcast_ref::<SampleTemperature>())
+        .is_some());
+    assert!(sc
+        .get(1)
+        .and_then(|s| s.downcast_ref::<SampleTopK>())
+        .is_some());
+    assert!(sc
+        .get(2)
+        .and_then(|s| s.downcast_ref::<SampleTopP>())
+        .is_some());
+    assert!(sc
+        .get(3)
+        .and_then(|s| s.downcast_ref::<SampleRandDistrib>())
+        .is_some());
+
+    let mut manual = SamplerChain::new()
+        + SampleTemperature::new(0.7)
+        + SampleTopK::new(5, 2)
+        + SampleTopP::new(0.9, 2)
+        + SampleRandDistrib::new();
+    let sample = |sc: &mut SamplerChain| -> Result<Vec<Option<u32>>> {
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        (0..20)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+                Ok(sc.sample_token(&mut res, &mut logits)?)
+            })
+            .collect()
+    };
+    let tokens = sample(&mut sc)?;
+    assert!(tokens.iter().all(Option::is_some));
+    assert_eq!(tokens, sample(&mut manual)?);
+    Ok(())
+}
+
+#[test]
+fn test_chain_warmup_steps() -> Result<()> {
+    use rand::SeedableRng;
+
+    let mut res = SimpleSamplerResources::new(
+        Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+        Some(vec![]),
+    );
+    let mut sc = SamplerChain::new() + SampleRandDistrib::new();
+    sc.set_warmup_steps(3);
+
+    let mut run = |sc: &mut SamplerChain, n| -> Result<Vec<u32>> {
+        (0..n)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter([1.0f32, 1.0, 1.0, 1.1])?;
+                let tid = sc.sample_token(&mut res, &mut logits)?.expect("No token");
+                res.with_last_tokens_mut(&mut |tokens| tokens.push(tid))?;
+                Ok(tid)
+            })
+            .collect()
+    };
+
+    assert_eq!(run(&mut sc, 3)?, [3, 3, 3]);
+    let sampled = run(&mut sc, 32)?;
+    assert!(sampled.iter().any(|tid| *tid != 3));
+
+    sc.reset();
+    assert_eq!(run(&mut sc, 3)?, [3, 3, 3]);
+    Ok(())
+}
+
+#[test]
+fn test_chain_reset_samplers() -> Result<()> {
+    use rand::SeedableRng;
+
+    let mut res = SimpleSamplerResources::new(
+        Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+        Some(vec![]),
+    );
+    let mut sc = SamplerChain::new()
+        + SampleHistogram::new()
+        + SampleForcedPrefix::new([2, 0])
+        + SampleMirostat2::new(5.0, 0.1).boxed();
+    let mut run = |sc: &mut SamplerChain, n| -> Result<Vec<u32>> {
+        (0..n)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+                let tid = sc.sample_token(&mut res, &mut logits)?.expect("No token");
+                res.with_last_tokens_mut(&mut |tokens| tokens.push(tid))?;
+                Ok(tid)
+            })
+            .collect()
+    };
+
+    assert_eq!(run(&mut sc, 4)?[..2], [2, 0]);
+    let mirostat = |sc: &SamplerChain| {
+        sc.get(2)
+            .and_then(|s| s.downcast_ref::<SampleMirostat2>())
+            .map(|s| s.state())
+    };
+    assert_ne!(mirostat(&sc).map(|st| st.mu), Some(10.0));
+    assert_eq!(
+        sc.get(0)
+            .and_then(|s| s.downcast_ref::<SampleHistogram>())
+            .map(|s| s.total()),
+        Some(3)
+    );
+
+    sc.reset();
+    assert_eq!(
+        mirostat(&sc),
+        Some(MirostatState {
+            mu: 10.0,
+            token: None,
+            stats: None
+        })
+    );
+    assert_eq!(
+        sc.get(0)
+            .and_then(|s| s.downcast_ref::<SampleHistogram>())
+            .map(|s| s.total()),
+        Some(0)
+    );
+    assert_eq!(run(&mut sc, 2)?, [2, 0]);
+    Ok(())
+}
+
+#[test]
+fn test_with_temperature_probs() -> Result<()> {
+    let logits = Logits::try_from_iter([0.1f32, 0.4, 0.2, 0.3])?;
+    let mut softmaxed = logits.clone();
+    softmaxed.ensure_softmax()?;
+
+    let probs = logits.with_temperature_probs(1.0)?;
+    assert_eq!(probs.len(), 4);
+    probs
+        .iter()
+        .zip(softmaxed.iter())
+        .for_each(|((tid, prob), l)| {
+            assert_eq!(*tid, l.token_id);
+            assert!((prob - l.prob).abs() < 1e-6);
+        });
+    assert!(!logits.is_sorted() && !logits.is_softmax());
+
+    let hot = logits.with_temperature_probs(10.0)?;
+    assert!(hot[0].1 < probs[0].1 && hot[3].1 > probs[3].1);
+
+    for temp in [0.0, -1.0] {
+        assert_eq!(
+            logits.with_temperature_probs(temp)?,
+            [(1, 1.0), (3, 0.0), (2, 0.0), (0, 0.0)]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_drain_truncate() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.2, 0.3])?;
+    logits.ensure_softmax()?;
+    let orig = logits.clone();
+    let capacity = logits.capacity();
+    let head_ptr = logits.as_ptr();
+
+    let removed = logits.drain_truncate(2).collect::<Vec<_>>();
+    assert_eq!(removed, orig[2..]);
+    assert_eq!(*logits, orig[..2]);
+    assert_eq!((logits.capacity(), logits.as_ptr()), (capacity, head_ptr));
+    assert!(logits.is_sorted() && !logits.is_softmax());
+
+    logits.ensure_softmax()?;
+    assert_eq!(logits.drain_truncate(5).count(), 0);
+    assert!(logits.is_softmax());
+    drop(logits.drain_truncate(0));
+    assert!(logits.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_kl_divergence() -> Result<()> {
+    let logits = |logits: &[f32]| Logits::try_from_iter(logits.iter().copied());
+    let mut p = logits(&[0.1, 0.4, 0.2, 0.3])?;
+    assert!(kl_divergence(&mut p.clone(), &mut p)?.abs() < 1e-6);
+
+    // p = [0.5, 0.5], q = [0.25, 0.75]
+    let ln3 = 3f32.ln();
+    let mut p = logits(&[0.0, 0.0])?;
+    let mut q = logits(&[0.0, ln3])?;
+    // The softmax function is applied as needed.
+    let expected = 0.5 * 2f32.ln() + 0.5 * (2f32 / 3f32).ln();
+    assert!((kl_divergence(&mut p, &mut q)? - expected).abs() < 1e-6);
+    assert!(p.is_softmax() && q.is_softmax());
+    let expected = 0.25 * 0.5f32.ln() + 0.75 * 1.5f32.ln();
+    assert!((kl_divergence(&mut q, &mut p)? - expected).abs() < 1e-6);
+
+    // One of the tokens is missing from q, so the other has probability 1.
+    let mut q = p.clone();
+    q.truncate(1);
+    q.set_softmax(false);
+    let expected = 0.5 * 0.5f32.ln() + 0.5 * (0.5f32 / KL_EPSILON).ln();
+    assert!((kl_divergence(&mut p, &mut q)? - expected).abs() < 1e-4);
+    assert!((kl_divergence(&mut q, &mut p)? - 2f32.ln()).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn test_probs_to_logits() -> Result<()> {
+    let probs = |logits: &Logits| logits.iter().map(|l| l.prob).collect::<Vec<_>>();
+    for base in [SoftmaxBase::Natural, SoftmaxBase::Two] {
+        let mut logits = Logits::try_from_iter([1.5f32, -2.0, 0.25, 3.0, 0.0])?;
+        logits.set_softmax_base(base).ensure_softmax()?;
+        let expected = probs(&logits);
+        logits.probs_to_logits();
+        assert!(logits.is_sorted() && !logits.is_softmax());
+        logits.ensure_softmax()?;
+        assert!(probs(&logits)
+            .iter()
+            .zip(&expected)
+            .all(|(p, e)| (p - e).abs() < 1e-6));
+    }
+
+    let mut logits = Logits::try_from_iter([0.0f32; 3])?;
+    logits
+        .iter_mut()
+        .zip([0.75, 0.0, 0.25])
+        .for_each(|(l, p)| l.prob = p);
+    logits.probs_to_logits();
+    assert_eq!(logits[1].logit, Logits::ZERO_PROB_LOGIT);
+    logits.ensure_softmax()?;
+    assert!(logits.iter().map(|l| l.token_id).eq([0, 2, 1]));
+    assert!(probs(&logits)
+        .iter()
+        .zip([0.75, 0.25, 0.0])
+        .all(|(p, e)| (p - e).abs() < 1e-6));
+    Ok(())
+}
+
+#[test]
+fn test_chain_extend() -> Result<()> {
+    let mut sc = SamplerChain::new() + SampleFlatBias::new([(3, -1.0)]) + SampleTopK::new(3, 1);
+    sc.extend([
+        SampleFlatBias::new([(0, 1.0)]).boxed(),
+        SampleGreedy::new().boxed(),
+    ]);
+    assert_eq!(
+        sc.clone().into_iter().map(|s| s.name()).collect::<Vec<_>>(),
+        [
+            "SampleFlatBias",
+            "SampleTopK",
+            "SampleFlatBias",
+            "SampleGreedy"
+        ]
+    );
+
+    // Top-k eliminates token 3 after its bias, then token 0 gets boosted.
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(0));
+    assert_eq!(logits.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_chain_index() -> Result<()> {
+    let mut sc = SamplerChain::new()
+        + SampleTopK::new(3, 1)
+        + SampleTemperature::new(0.8)
+        + SampleTopK::new(2, 1).boxed();
+    assert_eq!(sc.len(), 3);
+    assert!(!sc.is_empty() && SamplerChain::new().is_empty());
+    assert_eq!(sc.get(1).map(|s| s.name()), Some("SampleTemperature"));
+    assert!(sc.get(3).is_none());
+    assert!(sc
+        .get(1)
+        .and_then(|s| s.downcast_ref::<SampleTopK>())
+        .is_none());
+    assert_eq!(
+        sc.get(0)
+            .and_then(|s| s.downcast_ref::<SampleTopK>())
+            .map(|s| s.k),
+        Some(3)
+    );
+
+    let top_k = sc
+        .get_mut(2)
+        .and_then(|s| s.downcast_mut::<SampleTopK>())
+        .expect("Missing top-k");
+    ConfigurableSampler::<usize, f32>::set_option(top_k, "k", SamplerOptionValue::UInt(1))?;
+    assert_eq!(
+        sc.get(2).and_then(|s| s.options_summary()).as_deref(),
+        Some("[k: 1, min_keep: 1, entropy_factor: 0, keep_ties: false]")
+    );
+    assert_eq!(
+        sc.get(0)
+            .and_then(|s| s.downcast_ref::<SampleTopK>())
+            .map(|s| s.k),
+        Some(3)
+    );
+
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    sc.sample(&mut (), &mut logits)?;
+    assert_eq!(logits.iter().map(|l| l.token_id).collect::<Vec<_>>(), [3]);
+    Ok(())
+}
+
+#[test]
+fn test_chain_parameter_schema() -> Result<()> {
+    #[derive(Debug, Clone)]
+    struct NoMetadata;
+
+    impl Sampler for NoMetadata {
+        fn sample<'a>(
+            &mut self,
+            _res: &mut dyn HasSamplerResources,
+            logits: &'a mut Logits,
+        ) -> Result<&'a mut Logits, SamplerError> {
+            Ok(logits)
+        }
+    }
+
+    let mut sc = SamplerChain::new()
+        + SampleTemperature::new(0.8)
+        + NoMetadata
+        + SampleTopK::new(3, 1).boxed()
+        + SampleWhen::new(
+            SampleTopP::new(0.9, 1),
+            |_: &dyn HasSamplerResources, _: &Logits| true,
+        )
+        + SampleGreedy::new();
+    let schema = sc.parameter_schema();
+    let keys = |md: &SamplerMetadata| md.options.iter().map(|o| o.key).collect::<Vec<_>>();
+    assert_eq!(
+        schema
+            .iter()
+            .map(|(idx, md)| (*idx, md.name))
+            .collect::<Vec<_>>(),
+        [
+            (0, "temperature"),
+            (2, "top-k"),
+            (3, "when(top-p)"),
+            (4, "greedy")
+        ]
+    );
+    assert_eq!(keys(&schema[0].1), ["temperature"]);
+    assert_eq!(
+        keys(&schema[1].1),
+        ["k", "min_keep", "entropy_factor", "keep_ties"]
+    );
+    assert_eq!(
+        keys(&schema[2].1),
+        [
+            "p",
+            "min_keep",
+            "entropy_factor",
+            "inclusive_boundary",
+            "negligible_prob"
+        ]
+    );
+    assert!(schema[3].1.options.is_empty());
+    assert_eq!(
+        schema[1].1,
+        HasSamplerMetadata::<usize, f32>::sampler_metadata(&SampleTopK::default())
+    );
+
+    // The index addresses the sampler the options belong to.
+    let (idx, md) = &schema[1];
+    let top_k = sc
+        .get_mut(*idx)
+        .and_then(|s| s.downcast_mut::<SampleTopK>())
+        .expect("Missing top-k");
+    ConfigurableSampler::<usize, f32>::set_option(
+        top_k,
+        md.options[0].key,
+        SamplerOptionValue::UInt(2),
+    )?;
+    assert_eq!(
+        sc.get(2)
+            .and_then(|s| s.downcast_ref::<SampleTopK>())
+            .map(|s| s.k),
+        Some(2)
+    );
+    assert!(SamplerChain::new().parameter_schema().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_chain_record_replay() -> Result<()> {
+    use rand::SeedableRng;
+
+    fn run(sc: &mut SamplerChain, seed: u64, n: usize) -> Result<Vec<Option<u32>>> {
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(seed))),
+            None,
+        );
+        (0..n)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter([1.0f32, 1.0, 1.0, 1.1])?;
+                Ok(sc.sample_token(&mut res, &mut logits)?)
+            })
+            .collect()
+    }
+
+    let mut sc = SamplerChain::new() + SampleRandDistrib::new();
+    assert_eq!(sc.take_recording(), None);
+    sc.record();
+    let recorded = run(&mut sc, 123, 16)?;
+    let log = sc.take_recording().expect("Missing recording");
+    assert_eq!(&*log, recorded.as_slice());
+    assert_ne!(run(&mut sc, 456, 16)?, recorded);
+
+    sc.replay(log.clone());
+    assert!(sc.is_replaying());
+    assert_eq!(run(&mut sc, 456, 16)?, recorded);
+    assert!(!sc.is_replaying());
+    assert_eq!(run(&mut sc, 123, 16)?, recorded);
+
+    sc.replay(SampleLog::from_iter([Some(7)]));
+    assert!(run(&mut sc, 123, 1).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_ranked_candidates() -> Result<()> {
+    let mut sc = SamplerChain::new() + SampleTopK::new(3, 1);
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.2, 0.3])?;
+    let candidates = sc.ranked_candidates(&mut (), &mut logits)?;
+
+    assert_eq!(
+        candidates
+            .iter()
+            .map(|c| (c.rank, c.token))
+            .collect::<Vec<_>>(),
+        [(0, 1), (1, 3), (2, 2)]
+    );
+    assert!(candidates.windows(2).all(|w| w[0].prob >= w[1].prob));
+    assert_eq!(candidates[0].logit, 0.4);
+    assert!((candidates.iter().map(|c| c.prob).sum::<f32>() - 1.0).abs() < 1e-6);
+    assert_eq!(candidates.into_vec().len(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_logits_flags_through_chain() -> Result<()> {
+    let mut res = NilSamplerResources;
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.2, 0.3, 0.05])?;
+    assert!(!logits.is_sorted() && !logits.is_softmax());
+
+    let mut sc = SamplerChain::new() + SampleMinP::new(0.05, 1);
+    sc.sample(&mut res, &mut logits)?;
+    assert!(logits.is_sorted() && logits.is_softmax());
+
+    let mut sc = SamplerChain::new() + SampleTemperature::new(0.5);
+    sc.sample(&mut res, &mut logits)?;
+    assert!(logits.is_sorted() && !logits.is_softmax());
+
+    let mut sc = SamplerChain::new() + SampleTopK::new(3, 1) + SampleFlatBias::new([(3, 1.0)]);
+    sc.sample(&mut res, &mut logits)?;
+    assert!(!logits.is_sorted() && !logits.is_softmax());
+    assert_eq!(logits.len(), 3);
+
+    let mut sc = SamplerChain::new() + SampleTailFree::new(0.99, 1);
+    sc.sample(&mut res, &mut logits)?;
+    assert!(logits.is_sorted() && logits.is_softmax());
+    assert_eq!(logits.is_sorted(), logits.get_sorted());
+    assert_eq!(logits.is_softmax(), logits.get_softmax());
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_logits_random() {
+    use rand::SeedableRng;
+
+    let random = |seed| {
+        let logits = Logits::random(1000, &mut rand::rngs::StdRng::seed_from_u64(seed));
+        assert_eq!(logits.len(), 1000);
+        assert!(logits.iter().map(|l| l.token_id).eq(0..1000));
+        logits.iter().map(|l| l.logit).collect::<Vec<_>>()
+    };
+    let logits = random(123);
+    assert!(logits.iter().all(|l| (-10.0..10.0).contains(l)));
+    assert_eq!(logits, random(123));
+    assert_ne!(logits, random(124));
+}
+
+#[test]
+fn test_stage_survivor_counts() -> Result<()> {
+    use rand::SeedableRng;
+
+    let res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let logits = Logits::try_from_iter((0..50).map(|i| i as f32 / 10.0))?;
+    let sc = SamplerChain::new()
+        + SampleTopK::new(20, 1)
+        + SampleTopP::new(0.5, 1)
+        + SampleRandDistrib::new();
+
+    let counts = sc.stage_survivor_counts(&res, &logits)?;
+    assert_eq!(
+        counts.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        ["SampleTopK", "SampleTopP", "SampleRandDistrib"]
+    );
+    assert_eq!(counts[0].1, 20);
+    assert!(counts.windows(2).all(|w| w[0].1 >= w[1].1));
+    assert!(counts[1].1 < 20);
+    // The input is untouched.
+    assert_eq!(logits.len(), 50);
+    assert!(!logits.get_sorted());
+
+    // Masking stages count as filtering too.
+    let sc = SamplerChain::new()
+        + SampleFlatBias::new([(49, f32::NEG_INFINITY), (48, f32::NEG_INFINITY)])
+        + SampleTopK::new(20, 1);
+    let counts = sc.stage_survivor_counts(&res, &logits)?;
+    assert_eq!(counts[0], ("SampleFlatBias", 48));
+    assert_eq!(counts[1], ("SampleTopK", 20));
+
+    // Generic arguments aren't part of the name.
+    let when = SampleWhen::new(SampleTopK::new(20, 1), |_res, _logits| true);
+    assert_eq!(when.name(), "SampleWhen");
+    let sc = SamplerChain::new() + when;
+    assert_eq!(sc.stage_survivor_counts(&res, &logits)?[0].0, "SampleWhen");
+    assert!(sc.to_string().starts_with("SampleWhen (SampleTopK ["));
+    Ok(())
+}
+
+#[test]
+fn test_chain_requirements() -> Result<()> {
+    /// Requires softmax and checks it was applied before running. Leaves a marker in
+    /// the probabilities without clearing the softmax flag, so if softmax gets
+    /// recomputed later the marker is lost.
+    #[derive(Debug, Clone)]
+    struct Marker(bool);
+
+    impl Sampler for Marker {
+        fn sample<'a>(
+            &mut self,
+            _res: &mut dyn HasSamplerResources,
+            logits: &'a mut Logits,
+        ) -> Result<&'a mut Logits, SamplerError> {
+            assert!(logits.get_softmax() && logits.get_sorted());
+            if self.0 {
+                assert_eq!(logits[0].prob, 0.75, "Softmax was recomputed");
+            } else {
+                logits[0].prob = 0.75;
+            }
+            Ok(logits)
+        }
+
+        fn requires(&self) -> SampleRequirements {
+            SampleRequirements::SOFTMAX
+        }
+    }
+
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    let mut sc = SamplerChain::new() + Marker(false) + Marker(true);
+    assert_eq!(sc.requires(), SampleRequirements::SOFTMAX);
+    sc.sample(&mut (), &mut logits)?;
+
+    assert_eq!(SampleMinP::new(0.0, 1).requires(), SampleRequirements::NONE);
+    assert_eq!(
+        SampleMinP::new(0.1, 1).requires(),
+        SampleRequirements::SOFTMAX
+    );
+    assert_eq!(SampleTopK::default().requires(), SampleRequirements::NONE);
+    Ok(())
+}
+
+#[test]
+fn test_chain_clone() -> Result<()> {
+    use rand::SeedableRng;
+    let mut res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let mut sc = SamplerChain::new() + SampleTemperature::new(0.8) + SampleMirostat2::new(5.0, 0.1);
+    let fresh = sc.clone();
+    let fresh_state = format!("{fresh:?}");
+    assert!(fresh_state.contains("mu: 10.0"));
+
+    sc.sample_token(&mut res, &mut Logits::try_from_iter(T1.iter().copied())?)?;
+    let sampled_state = format!("{sc:?}");
+    assert_ne!(sampled_state, fresh_state);
+    assert_eq!(format!("{fresh:?}"), fresh_state);
+
+    // Cloning after sampling copies the current state.
+    let mut copied = sc.clone();
+    assert_eq!(format!("{copied:?}"), sampled_state);
+    copied.sample_token(&mut res, &mut Logits::try_from_iter(T1.iter().copied())?)?;
+    assert_eq!(format!("{sc:?}"), sampled_state);
+    Ok(())
+}
+
+#[test]
+fn test_chain_iter() -> Result<()> {
+    let mut sc = [
+        SampleFlatBias::new([(3, f32::NEG_INFINITY)]).boxed(),
+        SampleTemperature::new(0.8).boxed(),
+        SampleGreedy::new().boxed(),
+    ]
+    .into_iter()
+    .collect::<SamplerChain>();
+    assert_eq!(
+        sc.sample_token(&mut (), &mut Logits::try_from_iter(T1.iter().copied())?)?,
+        Some(2)
+    );
+
+    let samplers = sc.into_iter().collect::<Vec<_>>();
+    assert_eq!(samplers.len(), 3);
+    assert!(format!("{:?}", samplers[1]).starts_with("SampleTemperature"));
+
+    let mut sc = samplers.into_iter().collect::<SamplerChain>();
+    assert_eq!(
+        sc.sample_token(&mut (), &mut Logits::try_from_iter(T1.iter().copied())?)?,
+        Some(2)
+    );
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_chain_async() -> Result<()> {
+    use std::{future::Future, pin::pin, task};
+
+    #[derive(Debug)]
+    struct AsyncRes(Vec<TID>, Option<usize>);
+
+    impl AsyncHasSamplerResources for AsyncRes {
+        async fn last_tokens(&mut self) -> Result<Vec<TID>, SamplerError> {
+            Ok(self.0.clone())
+        }
+
+        async fn penalty_last_n(&mut self) -> Result<usize, SamplerError> {
+            self.1
+                .ok_or_else(|| SamplerError::MissingResource("penalty_last_n".to_string()))
+        }
+    }
+
+    let sample = |res: &mut AsyncRes| -> Result<Option<TID>> {
+        let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.35])?;
+        let mut sc = SamplerChain::new() + SampleRepetition::new(2.0, 64) + SampleGreedy::new();
+        let mut fut = pin!(sc.sample_token_async(res, &mut logits));
+        let task::Poll::Ready(tid) = fut
+            .as_mut()
+            .poll(&mut task::Context::from_waker(task::Waker::noop()))
+        else {
+            panic!("Expected future to be ready");
+        };
+        Ok(tid?)
+    };
+    // Token 3 was penalized because it came from the async history.
+    assert_eq!(sample(&mut AsyncRes(vec![3], None))?, Some(2));
+    // Both tokens are in the sampler's window, but the host's window only has token 2.
+    assert_eq!(sample(&mut AsyncRes(vec![3, 2], None))?, Some(1));
+    assert_eq!(sample(&mut AsyncRes(vec![3, 2], Some(1)))?, Some(3));
+    Ok(())
+}
+
+#[test]
+fn test_resources() -> Result<()> {
+    use rand::SeedableRng;
+    let mut res = SimpleSamplerResources::new(
+        Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+        Some(vec![0u32]),
+    );
+
+    let mut derp = 0;
+    res.with_rng_mut(&mut |rng| {
+        derp = rng.next_u32();
+    })?;
+    res.with_rng_mut(&mut |rng| {
+        derp = rng.next_u32();
+    })?;
+    Ok(())
+}
+
+mod sampler {
+    use super::*;
+
+    #[test]
+    fn test_greedy() -> Result<()> {
+        do_test_greedy(T1.iter().copied(), Some(3))?;
+        do_test_greedy(T1.iter().rev().copied(), Some(0))
+    }
+
+    #[test]
+    fn test_top_k() {
+        let mut res = NilSamplerResources;
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(1, 0),
+            T1,
+            &TE1[0..1],
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(3, 0),
+            T1,
+            &TE1[0..3],
+            validate,
+        );
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert_eq!(
+            SampleTopP::try_new(0.9, 1).ok(),
+            Some(SampleTopP::new(0.9, 1))
+        );
+        assert!(SampleTopP::try_new(1.0, 1).is_ok());
+        assert!(matches!(
+            SampleTopP::try_new(1.5, 1),
+            Err(SamplerError::InvalidOption(_))
+        ));
+        assert!(SampleTopP::try_new(-0.1, 1).is_err());
+        assert!(SampleTopP::try_new(f32::NAN, 1).is_err());
+        assert!(SampleTopP::try_new(0.9, 0).is_err());
+
+        assert!(SampleTopK::try_new(0, 1).is_ok());
+        assert!(SampleTopK::try_new(40, 0).is_err());
+        assert!(SampleMinP::try_new(2.0, 1).is_err());
+        assert!(SampleTemperature::try_new(-1.0).is_err());
+        assert!(SampleTemperature::try_new(f32::INFINITY).is_err());
+        assert!(SampleRepetition::try_new(0.5, 64).is_err());
+        assert!(SampleFreqPresence::try_new(-0.5, 0.5, 64).is_ok());
+        assert!(SampleMirostat2::try_new(5.0, -0.1).is_err());
+        assert!(SampleMirostat1::try_new(0, 5.0, 0.1).is_err());
+        assert!(
+            SampleMixture::try_new(SampleGreedy::new(), SampleRandDistrib::new(), 1.1).is_err()
+        );
+
+        let Err(err) = SampleTailFree::try_new(1.5, 1) else {
+            panic!("Expected an error");
+        };
+        assert_eq!(
+            err.to_string(),
+            "invalid option: z must be between 0.0 and 1.0 but got 1.5"
+        );
+    }
+
+    #[test]
+    fn test_min_keep_exceeds_len() -> Result<()> {
+        let samplers = [
+            SampleTopK::new(1, 100).boxed(),
+            SampleTopK::new(1, 100).entropy_factor(2.0).boxed(),
+            SampleTopP::new(0.1, 100).boxed(),
+            SampleTopP::new(0.1, 100)
+                .inclusive_boundary(false)
+                .entropy_factor(2.0)
+                .boxed(),
+            SampleMinP::new(0.9, 100).boxed(),
+            SampleTopA::new(1.0, 2.0, 100).boxed(),
+            SampleTailFree::new(0.1, 100).boxed(),
+            SampleLocallyTypical::new(0.1, 100).boxed(),
+            SampleRenyiP::new(0.1, 100).boxed(),
+        ];
+        for mut sampler in samplers {
+            let mut logits = Logits::try_from_iter((0..10).map(|i| i as f32))?;
+            sampler.sample(&mut NilSamplerResources, &mut logits)?;
+            assert_eq!(logits.len(), 10, "{sampler:?}");
+        }
+        let logits = Logits::try_from_iter([1.0f32, 2.0])?;
+        assert_eq!(logits.effective_min_keep(100), 2);
+        assert_eq!(logits.effective_min_keep(1), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_k_selection() -> Result<()> {
+        let mut res = NilSamplerResources;
+        let mut logits = Logits::try_from_iter([0.3f32, 0.9, 0.1, 0.9, 0.5, 0.3])?;
+        SampleTopK::new(4, 1).sample(&mut res, &mut logits)?;
+        assert!(logits.get_sorted() && !logits.get_softmax());
+        // Ties keep token id order, like a stable sort would.
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [1, 3, 4, 0]
+        );
+
+        let mut logits = Logits::try_from_iter([0.3f32, 0.9, 0.1, 0.9, 0.5, 0.3])?;
+        SampleTopK::new(1, 3).sample(&mut res, &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [1, 3, 4]
+        );
+        logits.ensure_softmax()?;
+        assert!((logits.iter().map(|l| l.prob).sum::<f32>() - 1.0).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_k_keep_ties() -> Result<()> {
+        let mut res = NilSamplerResources;
+        let input = [0.1f32, 0.5, 0.9, 0.5, 0.2, 0.5, 0.5];
+        for sorted in [false, true] {
+            let mut logits = Logits::try_from_iter(input)?;
+            if sorted {
+                logits.ensure_sorted()?;
+            }
+            let mut strict = logits.clone();
+            SampleTopK::new(2, 1).sample(&mut res, &mut strict)?;
+            assert_eq!(strict.len(), 2);
+
+            SampleTopK::new(2, 1)
+                .keep_ties(true)
+                .sample(&mut res, &mut logits)?;
+            assert!(logits.get_sorted());
+            assert_eq!(
+                logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+                [2, 1, 3, 5, 6]
+            );
+        }
+
+        // Nothing is tied with the boundary token here.
+        let mut logits = Logits::try_from_iter(input)?;
+        SampleTopK::new(1, 1)
+            .keep_ties(true)
+            .sample(&mut res, &mut logits)?;
+        assert_eq!(logits.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_logit_smoothing() -> Result<()> {
+        let mut res = NilSamplerResources;
+        let mut sampler = SampleLogitSmoothing::new(0.5);
+        let run = |sampler: &mut SampleLogitSmoothing, input: [f32; 3]| -> Result<Vec<f32>> {
+            let mut logits = Logits::try_from_iter(input)?;
+            sampler.sample(&mut NilSamplerResources, &mut logits)?;
+            Ok(logits.iter().map(|l| l.logit).collect())
+        };
+
+        // The first step has nothing to blend with.
+        assert_eq!(run(&mut sampler, [4.0, 0.0, -2.0])?, [4.0, 0.0, -2.0]);
+        assert_eq!(run(&mut sampler, [0.0, 2.0, -2.0])?, [2.0, 1.0, -2.0]);
+        let mut last = vec![];
+        for _ in 0..32 {
+            last = run(&mut sampler, [0.0, 2.0, -2.0])?;
+        }
+        assert!(last
+            .iter()
+            .zip([0.0, 2.0, -2.0])
+            .all(|(a, b)| (a - b).abs() < 1e-6));
+
+        run(&mut sampler, [4.0, 0.0, -2.0])?;
+        sampler.reset();
+        assert_eq!(run(&mut sampler, [0.0, 2.0, -2.0])?, [0.0, 2.0, -2.0]);
+
+        // Smoothing still applies to the tokens left after an earlier filter.
+        sampler.reset();
+        run(&mut sampler, [4.0, 0.0, -2.0])?;
+        let mut logits = Logits::try_from_iter([0.0f32, 2.0, -2.0])?;
+        logits.retain(|l| l.token_id != 2);
+        sampler.sample(&mut res, &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [2.0, 1.0]
+        );
+
+        // Changing the vocabulary size starts over.
+        let mut sampler = SampleLogitSmoothing::new(0.5).n_vocab(3);
+        run(&mut sampler, [4.0, 0.0, -2.0])?;
+        sampler.n_vocab = 4;
+        assert_eq!(run(&mut sampler, [0.0, 2.0, -2.0])?, [0.0, 2.0, -2.0]);
+        sampler.n_vocab = 2;
+        assert!(run(&mut sampler, [0.0, 2.0, -2.0]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json() -> Result<()> {
+        const VOCAB: &[&str] = &[
+            "{", "}", "\"", "key", ":", " ", "1", "2.5", ",", "[", "]", "true", "\"a\"", "x", "",
+        ];
+        const EOS: u32 = 15;
+        let tid = |s: &str| VOCAB.iter().position(|t| *t == s).expect("Unknown token") as u32;
+        let allowed = |sampler: &mut SampleJson, res: &mut SimpleSamplerResources| {
+            let mut logits = Logits::try_from_iter((0..=EOS).map(|_| 0f32)).expect("Bad logits");
+            sampler.sample(res, &mut logits).expect("Sampling failed");
+            logits
+                .iter()
+                .filter(|l| l.logit.is_finite())
+                .map(|l| l.token_id)
+                .collect::<Vec<_>>()
+        };
+
+        let mut res = SimpleSamplerResources::new(None, Some(vec![tid("x")]));
+        let mut sampler = SampleJson::new(VOCAB.iter().copied()).eos_token(Some(EOS));
+        let mut sc = SamplerChain::new() + sampler.clone() + SampleGreedy::new();
+
+        // Tokens already in the history when starting are ignored.
+        assert_eq!(
+            allowed(&mut sampler, &mut res),
+            ["{", "\"", " ", "1", "2.5", "[", "true", "\"a\""].map(tid)
+        );
+
+        let expected = [
+            "{", "\"", "key", "\"", ":", "[", "1", ",", "true", "]", ",", "\"a\"", ":", "2.5", "}",
+        ];
+        let mut in_string = false;
+        for s in expected {
+            // Prefer an invalid token, so the JSON sampler has to step in. Inside
+            // a string anything goes though.
+            let mut logits = Logits::try_from_iter((0..=EOS).map(|_| 0f32))?;
+            if !in_string {
+                logits[tid("x") as usize].logit = 10.0;
+            }
+            in_string ^= s == "\"";
+            logits[tid(s) as usize].logit = 5.0;
+            assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(tid(s)));
+            res.with_last_tokens_mut(&mut |tokens| tokens.push(tid(s)))?;
+        }
+        let mut logits = Logits::try_from_iter((0..=EOS).map(|_| 0f32))?;
+        logits[EOS as usize].logit = 1.0;
+        assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(EOS));
+
+        // Malformed continuations.
+        sampler.reset();
+        let mut res = SimpleSamplerResources::new(None, Some(vec![]));
+        allowed(&mut sampler, &mut res);
+        for (s, next) in [
+            ("{", vec!["}", "\"", " ", "\"a\""]),
+            ("\"a\"", vec![":", " "]),
+        ] {
+            res.with_last_tokens_mut(&mut |tokens| tokens.push(tid(s)))?;
+            assert_eq!(
+                allowed(&mut sampler, &mut res),
+                next.into_iter().map(tid).collect::<Vec<_>>()
+            );
+        }
+        assert!(!sampler.is_complete());
+        res.with_last_tokens_mut(&mut |tokens| tokens.push(tid(",")))?;
+        assert!(sampler
+            .sample(&mut res, &mut Logits::try_from_iter([0f32])?)
+            .is_err());
+
+        // Top level numbers are complete as soon as they're valid.
+        sampler.reset();
+        let mut res = SimpleSamplerResources::new(None, Some(vec![]));
+        allowed(&mut sampler, &mut res);
+        res.with_last_tokens_mut(&mut |tokens| tokens.push(tid("1")))?;
+        assert_eq!(
+            allowed(&mut sampler, &mut res),
+            [" ", "1", "2.5"]
+                .map(tid)
+                .into_iter()
+                .chain([EOS])
+                .collect::<Vec<_>>()
+        );
+        assert!(sampler.is_complete());
+
+        // Tokens without text in the history are an error too.
+        for bad in [tid(""), 100] {
+            sampler.reset();
+            let mut res = SimpleSamplerResources::new(None, Some(vec![]));
+            allowed(&mut sampler, &mut res);
+            res.with_last_tokens_mut(&mut |tokens| tokens.push(bad))?;
+            assert!(sampler
+                .sample(&mut res, &mut Logits::try_from_iter([0f32])?)
+                .is_err());
+        }
+        Ok(())
+    }
 
-    assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(1));
-    Ok(())
-}
+    #[test]
+    fn test_json_nested_tokens() -> Result<()> {
+        // Candidates that open and close several containers at once.
+        const VOCAB: &[&str] = &["[", "[[", "]]", "[]]", "]", "[]]]", "]]]", "[]]]]"];
+        let mut res = SimpleSamplerResources::new(None, Some(vec![]));
+        let mut sampler = SampleJson::new(VOCAB.iter().copied());
+        sampler.sample(&mut res, &mut Logits::try_from_iter([0f32])?)?;
+        res.with_last_tokens_mut(&mut |tokens| tokens.push(1))?;
+
+        let mut logits = Logits::try_from_iter(VOCAB.iter().map(|_| 0f32))?;
+        sampler.sample(&mut res, &mut logits)?;
+        let allowed = logits
+            .iter()
+            .filter(|l| l.logit.is_finite())
+            .map(|l| VOCAB[l.token_id as usize])
+            .collect::<Vec<_>>();
+        assert_eq!(allowed, ["[", "[[", "]]", "[]]", "]", "[]]]"]);
+        assert!(!sampler.is_complete());
+        Ok(())
+    }
 
-#[test]
-fn test_chain2() -> Result<()> {
-    use rand::SeedableRng;
-    let mut res = SimpleSamplerResources::new(
-        Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
-        Some(vec![]),
-    );
-    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
-    let mut logits2 = logits.clone();
+    #[test]
+    fn test_inclusive_thresholds() -> Result<()> {
+        // Powers of two so the cumulative sums land exactly on the thresholds.
+        let exact = |probs: &[f32]| -> Result<Logits> {
+            let mut logits = Logits::try_from_iter(probs.iter().map(|p| p.ln()))?;
+            logits.iter_mut().zip(probs).for_each(|(l, p)| l.prob = *p);
+            logits.set_sorted(true).set_softmax(true);
+            Ok(logits)
+        };
+        let ids = |logits: &Logits| logits.iter().map(|l| l.token_id).collect::<Vec<_>>();
+        let mut res = NilSamplerResources;
 
-    let mut sc = SamplerChain::new()
-        + SampleFlatBias::new([(3, f32::NEG_INFINITY)])
-        + SampleRepetition::new(1.1, 64)
-        + SampleFreqPresence::new(0.05, 0.1, 64)
-        + SampleTemperature::new(0.8)
-        + SampleMirostat1::new(4, 5.0, 0.1);
-    res.with_last_tokens_mut(&mut |tokens| tokens.push(3u32))?;
+        let mut logits = exact(&[0.5, 0.25, 0.125, 0.125])?;
+        assert_eq!(logits.clone().percentile_cutoff(0.75)?, 1);
+        SampleTopP::new(0.75, 1).sample(&mut res, &mut logits)?;
+        assert_eq!(ids(&logits), [0, 1]);
 
-    assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(2));
+        // The most typical token is 1, followed by 0.
+        let mut logits = exact(&[0.5, 0.25, 0.125, 0.125])?;
+        SampleLocallyTypical::new(0.75, 1).sample(&mut res, &mut logits)?;
+        assert_eq!(ids(&logits), [1, 0]);
 
-    res.with_last_tokens_mut(&mut |tokens| tokens.push(2u32))?;
-    assert_eq!(sc.sample_token(&mut res, &mut logits2)?, Some(1));
-    Ok(())
-}
+        // The normalized second derivatives are 0.5, 0.25 and 0.25.
+        let mut logits = exact(&[0.5, 0.25, 0.125, 0.0625, 0.0625])?;
+        SampleTailFree::new(0.75, 1).sample(&mut res, &mut logits)?;
+        assert_eq!(ids(&logits), [0]);
+        Ok(())
+    }
 
-#[test]
-fn test_resources() -> Result<()> {
-    use rand::SeedableRng;
-    let mut res = SimpleSamplerResources::new(
-        Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
-        Some(vec![0u32]),
-    );
+    #[test]
+    fn test_merge_equivalent() -> Result<()> {
+        use rand::SeedableRng;
+        const T: &[f32] = &[0.3, 0.25, 0.25, 0.2];
+        let mut sampler = SampleMergeEquivalent::new([(2, 1), (1, 1), (3, 7)]);
 
-    let mut derp = 0;
-    res.with_rng_mut(&mut |rng| {
-        derp = rng.next_u32();
-    })?;
-    res.with_rng_mut(&mut |rng| {
-        derp = rng.next_u32();
-    })?;
-    Ok(())
-}
+        let mut logits = Logits::try_from_iter(T.iter().map(|p| p.ln()))?;
+        sampler.sample(&mut NilSamplerResources, &mut logits)?;
+        assert!(logits.is_sorted() && logits.is_softmax());
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [1, 0, 3, 2]
+        );
+        assert!((logits[0].prob - 0.5).abs() < 1e-6);
+        assert_eq!(logits[3].prob, 0.0);
+        // The adjusted logits give the same probabilities.
+        let mut check = Logits::try_from_iter(logits.iter().map(|l| l.logit))?;
+        check.ensure_softmax()?;
+        assert!((check[0].prob - 0.5).abs() < 1e-6);
+
+        let mut sc = SamplerChain::new() + sampler.clone() + SampleGreedy::new();
+        let mut logits = Logits::try_from_iter(T.iter().map(|p| p.ln()))?;
+        assert_eq!(
+            sc.sample_token(&mut NilSamplerResources, &mut logits)?,
+            Some(1)
+        );
 
-mod sampler {
-    use super::*;
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let mut sc = SamplerChain::new() + sampler + SampleRandDistrib::new();
+        let draws = 4000;
+        let ones = (0..draws)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter(T.iter().map(|p| p.ln()))?;
+                sc.sample_token(&mut res, &mut logits)
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|tid| *tid == Some(1))
+            .count();
+        assert!((ones as f32 / draws as f32 - 0.5).abs() < 0.03);
+
+        // Chained mappings are resolved to the final canonical token.
+        let mut sampler = SampleMergeEquivalent::new([(0, 1), (1, 2)]);
+        let mut logits = Logits::try_from_iter(T.iter().map(|p| p.ln()))?;
+        sampler.sample(&mut NilSamplerResources, &mut logits)?;
+        assert_eq!(logits[0].token_id, 2);
+        assert!((logits[0].prob - 0.8).abs() < 1e-6);
+        assert!(logits.iter().all(|l| !l.logit.is_nan()));
+
+        // Cycles use the lowest token id in the cycle.
+        let mut sampler = SampleMergeEquivalent::new([(3, 2), (2, 1), (1, 2)]);
+        let mut logits = Logits::try_from_iter(T.iter().map(|p| p.ln()))?;
+        sampler.sample(&mut NilSamplerResources, &mut logits)?;
+        assert_eq!(logits[0].token_id, 1);
+        assert!((logits[0].prob - 0.7).abs() < 1e-6);
+        Ok(())
+    }
 
     #[test]
-    fn test_greedy() -> Result<()> {
-        do_test_greedy(T1.iter().copied(), Some(3))?;
-        do_test_greedy(T1.iter().rev().copied(), Some(0))
+    fn test_renyi_p() -> Result<()> {
+        let survivors = |sampler: &mut dyn Sampler, probs: &[f32]| -> Result<usize> {
+            let mut logits = Logits::try_from_iter(probs.iter().map(|p| p.ln()))?;
+            sampler.sample(&mut (), &mut logits)?;
+            Ok(logits.len())
+        };
+        let peaked = [0.6f32, 0.2, 0.1, 0.05, 0.05];
+        let flat = [0.25f32; 4];
+
+        assert_eq!(survivors(&mut SampleRenyiP::new(0.88, 1), &peaked)?, 2);
+        assert_eq!(survivors(&mut SampleTopP::new(0.88, 1), &peaked)?, 3);
+        assert_eq!(survivors(&mut SampleRenyiP::new(0.88, 3), &peaked)?, 3);
+        assert_eq!(survivors(&mut SampleRenyiP::new(1.0, 1), &peaked)?, 5);
+
+        assert_eq!(survivors(&mut SampleRenyiP::new(0.6, 1), &flat)?, 3);
+        assert_eq!(survivors(&mut SampleTopP::new(0.6, 1), &flat)?, 3);
+        Ok(())
     }
 
     #[test]
-    fn test_top_k() {
+    fn test_top_p() {
         let mut res = NilSamplerResources;
         test_sampler(
             &mut res,
-            &mut SampleTopK::new(1, 0),
+            &mut SampleTopP::new(0.0, 1),
             T1,
             &TE1[0..1],
             validate,
         );
         test_sampler(
             &mut res,
-            &mut SampleTopK::new(3, 0),
+            &mut SampleTopP::new(0.7, 1),
             T1,
-            &TE1[0..3],
+            &TE1[0..2],
             validate,
         );
+        test_sampler(&mut res, &mut SampleTopP::new(1.0, 1), T1, TE1, validate);
     }
 
     #[test]
-    fn test_top_p() {
+    fn test_max_candidates() -> Result<()> {
         let mut res = NilSamplerResources;
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        let mut sc = SamplerChain::new() + SampleTopP::new(1.0, 1) + SampleMaxCandidates::new(2);
+        sc.sample(&mut res, &mut logits)?;
+        assert!(logits.get_sorted());
+        assert!(!logits.get_softmax());
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [3, 2]
+        );
+
         test_sampler(
             &mut res,
-            &mut SampleTopP::new(0.0, 1),
+            &mut SampleMaxCandidates::new(0),
             T1,
-            &TE1[0..1],
+            TE1,
             validate,
         );
         test_sampler(
             &mut res,
-            &mut SampleTopP::new(0.7, 1),
+            &mut SampleMaxCandidates::new(10),
             T1,
-            &TE1[0..2],
+            TE1,
             validate,
         );
-        test_sampler(&mut res, &mut SampleTopP::new(1.0, 1), T1, TE1, validate);
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_p_boundary() -> Result<()> {
+        let mut res = NilSamplerResources;
+        // Probabilities are roughly 0.279, 0.252, 0.228, 0.207 so p=0.5
+        // is reached partway through the second token.
+        let mut sampler = SampleTopP::new(0.5, 1);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [3, 2]
+        );
+        assert!(sampler.cutoff_cum_sum().unwrap() >= 0.5);
+
+        let mut sampler = sampler.inclusive_boundary(false);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert_eq!(logits.iter().map(|l| l.token_id).collect::<Vec<_>>(), [3]);
+        assert!(sampler.cutoff_cum_sum().unwrap() < 0.5);
+
+        // min_keep still takes priority.
+        let mut sampler = sampler.min_keep(2);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert_eq!(logits.len(), 2);
+
+        // A dominant top token is kept even with min_keep 0.
+        let mut sampler = sampler.min_keep(0);
+        let mut logits = Logits::try_from_iter([10f32, 0.0, 0.0])?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert_eq!(logits.iter().map(|l| l.token_id).collect::<Vec<_>>(), [0]);
+        assert_eq!(sampler.cutoff_index(), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_p_disabled() -> Result<()> {
+        let mut sampler = SampleTopP::new(1.0, 1);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        let orig = logits.clone();
+
+        logits.sample(&mut NilSamplerResources, &mut sampler)?;
+        assert_eq!(*logits, *orig);
+        assert!(!logits.get_sorted());
+        assert!(!logits.get_softmax());
+        assert_eq!(sampler.cutoff_index(), Some(T1.len()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_kp() -> Result<()> {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(123);
+        let mut res = NilSamplerResources;
+        for (k, p, min_keep) in [
+            (40, 0.9, 1),
+            (5, 0.5, 1),
+            (100, 0.99, 3),
+            (2, 1.0, 1),
+            (0, 0.9, 4),
+            (300, 0.9, 250),
+        ] {
+            let input = (0..200)
+                .map(|_| rng.gen_range(-10f32..10f32))
+                .collect::<Vec<_>>();
+            let mut stacked = Logits::try_from_iter(input.iter().copied())?;
+            let (mut top_k, mut top_p) =
+                (SampleTopK::new(k, min_keep), SampleTopP::new(p, min_keep));
+            stacked.sample(&mut res, &mut top_k)?;
+            stacked.sample(&mut res, &mut top_p)?;
+
+            let mut combined = Logits::try_from_iter(input.iter().copied())?;
+            let mut top_kp = SampleTopKP::new(k, p, min_keep);
+            combined.sample(&mut res, &mut top_kp)?;
+            assert_eq!(*combined, *stacked);
+            assert_eq!(combined.is_softmax(), stacked.is_softmax());
+            assert_eq!(top_kp.cutoff_index(), top_p.cutoff_index());
+            assert_eq!(
+                top_kp.min_keep_widened(),
+                Some(
+                    top_k.min_keep_widened() == Some(true)
+                        || top_p.min_keep_widened() == Some(true)
+                )
+            );
+        }
+        assert!(SampleTopKP::try_new(40, 1.5, 1).is_err());
+        assert!(SampleTopKP::try_new(40, 0.9, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_p_negligible_prob() -> Result<()> {
+        let flat_tail = || Logits::try_from_iter([10.0f32, 9.0].into_iter().chain([-30.0; 50]));
+        let mut res = NilSamplerResources;
+
+        let mut logits = flat_tail()?;
+        logits.sample(&mut res, &mut SampleTopP::new(0.5, 10))?;
+        assert_eq!(logits.len(), 10);
+
+        let mut sampler = SampleTopP::new(0.5, 10).negligible_prob(1e-6);
+        let mut logits = flat_tail()?;
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [0, 1]
+        );
+
+        let mut logits = flat_tail()?;
+        logits.ensure_softmax()?;
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(logits.len(), 2);
+
+        // More than min_keep tokens are non-negligible.
+        let mut logits = flat_tail()?;
+        logits.sample(&mut res, &mut SampleTopP::new(0.5, 1).negligible_prob(1e-6))?;
+        assert_eq!(logits.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_floor() -> Result<()> {
+        let mut res = NilSamplerResources;
+        let floor = 1e-4;
+        let v = [0.0f32, 3.0, 1.0, 2.5, -1.0, 0.5];
+        let samplers: [Box<dyn Sampler>; 3] = [
+            Box::new(SampleTopP::new(0.8, 1).floor(Some(floor))),
+            Box::new(SampleTopK::new(2, 1).floor(Some(floor))),
+            Box::new(SampleMinP::new(0.3, 1).floor(Some(floor))),
+        ];
+        for mut sampler in samplers {
+            let mut logits = Logits::try_from_iter(v)?;
+            sampler.sample(&mut res, &mut logits)?;
+            assert_eq!(logits.len(), v.len(), "{}", sampler.name());
+            logits.ensure_softmax()?;
+            let eliminated = logits.iter().skip(2).map(|l| l.prob).collect::<Vec<_>>();
+            assert!(eliminated.iter().all(|p| (p - floor).abs() < 1e-6));
+            // The kept tokens keep their relative probabilities.
+            assert!((logits[0].prob / logits[1].prob - 0.5f32.exp()).abs() < 1e-3);
+        }
+
+        let mut logits = Logits::try_from_iter(v)?;
+        logits.sample(&mut res, &mut SampleTopK::new(2, 1).floor(None))?;
+        assert_eq!(logits.len(), 2);
+
+        // A floor that doesn't fit is clamped so the kept tokens have half the probability.
+        let mut logits = Logits::try_from_iter(v)?;
+        logits.sample(&mut res, &mut SampleTopK::new(2, 1).floor(Some(0.5)))?;
+        logits.ensure_softmax()?;
+        assert!((logits[0].prob + logits[1].prob - 0.5).abs() < 1e-5);
+
+        // Realistic vocabulary sizes don't fail.
+        let n_vocab = 32_000;
+        let mut logits = Logits::try_from_iter((0..n_vocab).map(|i| (i as f32 * 0.37).sin()))?;
+        logits.sample(&mut res, &mut SampleTopK::new(10, 1).floor(Some(1e-4)))?;
+        assert_eq!(logits.len(), n_vocab);
+        logits.ensure_softmax()?;
+        let kept = logits.iter().take(10).map(|l| l.prob).sum::<f32>();
+        assert!((kept - Logits::FLOOR_MIN_KEPT_MASS).abs() < 1e-3);
+        assert!(logits
+            .iter()
+            .skip(10)
+            .all(|l| (l.prob - 0.5 / (n_vocab - 10) as f32).abs() < 1e-8));
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_p_cutoff() -> Result<()> {
+        let mut sampler = SampleTopP::new(0.7, 1);
+        assert_eq!(sampler.cutoff_index(), None);
+
+        let mut logits = Logits::try_from_iter(T1.iter().map(|i| i.ln()))?;
+        logits.sample(&mut NilSamplerResources, &mut sampler)?;
+        assert_eq!(logits.len(), 2);
+        assert_eq!(sampler.cutoff_index(), Some(2));
+        assert!((sampler.cutoff_cum_sum().unwrap_or_default() - 0.7).abs() < 0.00001);
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [3, 2]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_keep_widened() -> Result<()> {
+        const PEAKED: &[f32] = &[0.01, 0.02, 0.95, 0.02];
+        let mut res = NilSamplerResources;
+        let peaked = || Logits::try_from_iter(PEAKED.iter().map(|i| i.ln()));
+
+        let mut sampler = SampleTopP::new(0.9, 1);
+        assert_eq!(sampler.min_keep_widened(), None);
+        peaked()?.sample(&mut res, &mut sampler)?;
+        assert_eq!(sampler.cutoff_index(), Some(1));
+        assert_eq!(sampler.min_keep_widened(), Some(false));
+
+        // p alone keeps only the peak, min_keep widens the nucleus to 3 tokens.
+        let mut sampler = SampleTopP::new(0.9, 3);
+        let mut logits = peaked()?;
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(logits.len(), 3);
+        assert_eq!(sampler.cutoff_index(), Some(3));
+        assert!((sampler.cutoff_cum_sum().unwrap_or_default() - 0.99).abs() < 1e-5);
+        assert_eq!(sampler.min_keep_widened(), Some(true));
+
+        let mut sampler = SampleTopK::new(1, 2);
+        peaked()?.sample(&mut res, &mut sampler)?;
+        assert_eq!(sampler.cutoff_index(), Some(2));
+        assert_eq!(sampler.min_keep_widened(), Some(true));
+        let mut sampler = SampleTopK::new(2, 2);
+        peaked()?.sample(&mut res, &mut sampler)?;
+        assert_eq!(sampler.min_keep_widened(), Some(false));
+
+        let mut sampler = SampleMinP::new(0.1, 2);
+        peaked()?.sample(&mut res, &mut sampler)?;
+        assert_eq!(sampler.cutoff_index(), Some(2));
+        assert_eq!(sampler.min_keep_widened(), Some(true));
+        let mut sampler = SampleMinP::new(0.01, 2);
+        peaked()?.sample(&mut res, &mut sampler)?;
+        assert_eq!(sampler.cutoff_index(), Some(4));
+        assert_eq!(sampler.min_keep_widened(), Some(false));
+
+        let mut sampler = SampleTopKP::new(4, 0.9, 1);
+        assert_eq!(sampler.cutoff_index(), None);
+        peaked()?.sample(&mut res, &mut sampler)?;
+        assert_eq!(sampler.cutoff_index(), Some(1));
+        assert_eq!(sampler.min_keep_widened(), Some(false));
+        let mut sampler = SampleTopKP::new(4, 0.9, 3);
+        peaked()?.sample(&mut res, &mut sampler)?;
+        assert_eq!(sampler.cutoff_index(), Some(3));
+        assert_eq!(sampler.min_keep_widened(), Some(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_adaptive_min_keep() -> Result<()> {
+        const PEAKED: &[f32] = &[0.97, 0.01, 0.01, 0.01];
+        const UNIFORM: &[f32] = &[0.25, 0.25, 0.25, 0.25];
+
+        let mut res = NilSamplerResources;
+        let mut logits = Logits::try_from_iter(UNIFORM.iter().map(|i| i.ln()))?;
+        assert!((logits.normalized_entropy()? - 1.0).abs() < 0.00001);
+        assert_eq!(logits.adaptive_min_keep(4, 0.0)?, 4);
+        assert_eq!(logits.adaptive_min_keep(4, 0.5)?, 2);
+
+        let mut logits = Logits::try_from_iter(PEAKED.iter().map(|i| i.ln()))?;
+        assert!(logits.normalized_entropy()? < 0.2);
+        assert_eq!(logits.adaptive_min_keep(4, 1.0)?, 1);
+
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(1, 4).entropy_factor(1.0),
+            UNIFORM,
+            UNIFORM,
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(1, 4).entropy_factor(1.0),
+            PEAKED,
+            &[0.97],
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTopP::new(0.0, 4).entropy_factor(1.0),
+            UNIFORM,
+            UNIFORM,
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTopP::new(0.0, 4).entropy_factor(1.0),
+            PEAKED,
+            &[0.97],
+            validate,
+        );
+        // Without the entropy factor min_keep is fixed.
+        test_sampler(
+            &mut res,
+            &mut SampleTopP::new(0.0, 4),
+            PEAKED,
+            PEAKED,
+            validate,
+        );
+
+        // Zero entropy still keeps a token.
+        for input in [&[1000f32, 0.0, 0.0, 0.0][..], &[5.0]] {
+            let mut logits = Logits::try_from_iter(input.iter().copied())?;
+            assert_eq!(logits.adaptive_min_keep(1, 1.0)?, 1);
+            let mut sampler = SampleTopP::new(0.5, 1)
+                .entropy_factor(1.0)
+                .inclusive_boundary(false);
+            logits.sample(&mut res, &mut sampler)?;
+            assert_eq!(logits.len(), 1);
+        }
+        let mut logits = Logits::try_from_iter([5f32])?;
+        assert_eq!(logits.adaptive_min_keep(0, 1.0)?, 0);
+        Ok(())
     }
 
     #[test]
@@ -292,27 +2025,171 @@ mod sampler {
             &[0.25, 0.25, 0.25, 0.25, 0.0],
             validate_sm,
         );
-        res.with_last_tokens_mut(&mut |lt| {
-            lt.push(1);
-            lt.push(2);
-        })?;
-        test_sampler(
+        res.with_last_tokens_mut(&mut |lt| {
+            lt.push(1);
+            lt.push(2);
+        })?;
+        test_sampler(
+            &mut res,
+            &mut SampleRepetition::new(50.0, 100),
+            T,
+            TER2,
+            validate_sm,
+        );
+        res.with_last_tokens_mut(&mut |lt| {
+            lt.push(0);
+            lt.push(0);
+        })?;
+        test_sampler(
+            &mut res,
+            &mut SampleRepetition::new(50.0, 100),
+            T,
+            TER2,
+            validate_sm,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_repetition_windows() -> Result<()> {
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 2, 3, 4, 5, 1, 2]));
+        let input = [1.5f32, -0.5, 2.0, 0.5, -1.0, 0.1, 3.0];
+
+        let mut combined = SampleRepetition::new(1.2, 6).windows([(3, 2.0), (1, 1.5)]);
+        let mut stacked = SamplerChain::new()
+            + SampleRepetition::new(1.2, 6)
+            + SampleRepetition::new(2.0, 3)
+            + SampleRepetition::new(1.5, 1);
+
+        let mut logits = Logits::try_from_iter(input)?;
+        combined.sample(&mut res, &mut logits)?;
+        let mut expected = Logits::try_from_iter(input)?;
+        stacked.sample(&mut res, &mut expected)?;
+
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            expected.iter().map(|l| l.logit).collect::<Vec<_>>()
+        );
+        // Token 2 is in every window, 0 isn't in any and 6 was never seen.
+        assert_eq!(logits[2].logit, 2.0 / 1.2 / 2.0 / 1.5);
+        assert_eq!(logits[0].logit, 1.5);
+        assert_eq!(logits[6].logit, 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repetition_skip_prompt() -> Result<()> {
+        const T: &[f32] = &[0.2, 0.2, 0.2, 0.2, 0.2];
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 2]));
+
+        // Missing the prompt length resource.
+        assert!(Logits::try_from_iter(T.iter().map(|i| i.ln()))?
+            .sample(
+                &mut res,
+                &mut SampleRepetition::new(50.0, 100).skip_prompt(true)
+            )
+            .is_err());
+
+        // Tokens 0 and 1 came from the prompt so only 2 gets penalized.
+        res.set_prompt_len(Some(2));
+        test_sampler(
+            &mut res,
+            &mut SampleRepetition::new(50.0, 100).skip_prompt(true),
+            T,
+            &[0.25, 0.25, 0.25, 0.25, 0.0],
+            validate_sm,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleFreqPresence::new(5.0, 5.0, 100).skip_prompt(true),
+            T,
+            &[0.249997, 0.249997, 0.249997, 0.249997, 0.000011],
+            validate_sm,
+        );
+
+        // Not skipping the prompt penalizes all three.
+        test_sampler(
+            &mut res,
+            &mut SampleRepetition::new(50.0, 100),
+            T,
+            &[0.5, 0.5, 0.0, 0.0, 0.0],
+            validate_sm,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_repetition_floor() -> Result<()> {
+        const T: &[f32] = &[-1.0, 2.0, -4.0, 0.5];
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 0, 0, 1, 1, 2, 3]));
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleRepetition::new(50.0, 100),
+            T,
+            &[-50.0, 0.04, -200.0, 0.01],
+            validate_eq,
+        );
+        // Token 2 starts below the floor so it's left alone, the rest stop at it.
+        test_sampler_raw(
+            &mut res,
+            &mut SampleRepetition::new(50.0, 100).min_logit_floor(-3.0),
+            T,
+            &[-3.0, 0.04, -4.0, 0.01],
+            validate_eq,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_penalty_last_n_resource() -> Result<()> {
+        const T: &[f32] = &[1.0, 2.0, 3.0, 4.0];
+        let mut res = SimpleSamplerResources::new(None, Some(vec![1, 2, 3]));
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleRepetition::new(2.0, 64),
+            T,
+            &[1.0, 1.0, 1.5, 2.0],
+            validate_eq,
+        );
+        // The resource overrides the constructed window.
+        res.set_penalty_last_n(Some(1));
+        test_sampler_raw(
+            &mut res,
+            &mut SampleRepetition::new(2.0, 64),
+            T,
+            &[1.0, 2.0, 3.0, 2.0],
+            validate_eq,
+        );
+        test_sampler_raw(
+            &mut res,
+            &mut SampleFreqPresence::new(0.0, 1.0, 64),
+            T,
+            &[1.0, 2.0, 3.0, 3.0],
+            validate_eq,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_penalty_exclude() -> Result<()> {
+        const T: &[f32] = &[1.0, 2.0, 3.0, 4.0];
+        let mut res = SimpleSamplerResources::new(None, Some(vec![1, 1, 1, 2, 2, 2]));
+
+        test_sampler_raw(
             &mut res,
-            &mut SampleRepetition::new(50.0, 100),
+            &mut SampleRepetition::new(2.0, 64).exclude([1]),
             T,
-            TER2,
-            validate_sm,
+            &[1.0, 2.0, 1.5, 4.0],
+            validate_eq,
         );
-        res.with_last_tokens_mut(&mut |lt| {
-            lt.push(0);
-            lt.push(0);
-        })?;
-        test_sampler(
+        test_sampler_raw(
             &mut res,
-            &mut SampleRepetition::new(50.0, 100),
+            &mut SampleFreqPresence::new(0.5, 1.0, 64).exclude([2]),
             T,
-            TER2,
-            validate_sm,
+            &[1.0, -0.5, 3.0, 4.0],
+            validate_eq,
         );
         Ok(())
     }
@@ -373,7 +2250,7 @@ mod sampler {
                 .min_length(3)
                 .flat_penalty(5.0),
             T,
-            &[0.249579, 0.249579, 0.249579, 0.001681, 0.249579],
+            &[0.249579, 0.249579, 0.249579, 0.249579, 0.001681],
             validate_sm,
         );
 
@@ -383,7 +2260,7 @@ mod sampler {
                 .min_length(3)
                 .stacking_penalty(1.25),
             T,
-            &[0.249579, 0.249579, 0.249579, 0.001681, 0.249579],
+            &[0.249579, 0.249579, 0.249579, 0.249579, 0.001681],
             validate_sm,
         );
 
@@ -396,7 +2273,7 @@ mod sampler {
                 .tolerance(1)
                 .stacking_penalty(1.25),
             T,
-            &[0.249579, 0.249579, 0.249579, 0.001681, 0.249579],
+            &[0.249579, 0.249579, 0.249579, 0.249579, 0.001681],
             validate_sm,
         );
 
@@ -450,6 +2327,77 @@ mod sampler {
         );
     }
 
+    #[test]
+    fn test_temp_schedule() -> Result<()> {
+        let mut sampler = SampleTempSchedule::new(1.0, 0.5, 10);
+        assert_eq!(sampler.temperature_at(0), 1.0);
+        assert_eq!(sampler.temperature_at(5), 0.75);
+        assert_eq!(sampler.temperature_at(10), 0.5);
+        assert_eq!(sampler.temperature_at(50), 0.5);
+
+        // The prompt is already in the history, so that's step 0.
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 2]));
+        let run = |sampler: &mut SampleTempSchedule, res: &mut SimpleSamplerResources| {
+            let mut logits = Logits::try_from_iter([1.0f32, 2.0])?;
+            logits.sample(res, sampler)?;
+            anyhow::Ok(logits[1].logit)
+        };
+
+        assert_eq!(run(&mut sampler, &mut res)?, 2.0);
+        res.with_last_tokens_mut(&mut |lt| lt.extend([0; 5]))?;
+        assert_eq!(run(&mut sampler, &mut res)?, 2.0 / 0.75);
+        res.with_last_tokens_mut(&mut |lt| lt.extend([0; 20]))?;
+        assert_eq!(run(&mut sampler, &mut res)?, 2.0 / 0.5);
+
+        sampler.reset();
+        assert_eq!(run(&mut sampler, &mut res)?, 2.0);
+
+        // Scheduling down to 0.0 ends up greedy, like SampleTemperature.
+        let mut sampler = SampleTempSchedule::new(1.0, 0.0, 2);
+        res.with_last_tokens_mut(&mut |lt| lt.extend([0; 2]))?;
+        let mut logits = Logits::try_from_iter([1.0f32, 2.0])?;
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(logits.len(), 2);
+        res.with_last_tokens_mut(&mut |lt| lt.extend([0; 2]))?;
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(logits.len(), 1);
+        assert_eq!(logits[0].token_id, 1);
+        assert!(logits[0].logit.is_finite());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forced_prefix() -> Result<()> {
+        let mut res = SimpleSamplerResources::new(None, Some(vec![5, 5]));
+        let mut sc = SamplerChain::new() + SampleForcedPrefix::new([1, 0]) + SampleGreedy::new();
+
+        let run = |res: &mut SimpleSamplerResources, sc: &mut SamplerChain| -> Result<_> {
+            let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+            let tid = sc.sample_token(res, &mut logits)?.unwrap();
+            res.with_last_tokens_mut(&mut |lt| lt.push(tid))?;
+            Ok(tid)
+        };
+        assert_eq!(
+            (0..4)
+                .map(|_| run(&mut res, &mut sc))
+                .collect::<Result<Vec<_>>>()?,
+            [1, 0, 3, 3]
+        );
+
+        // Only re-arms after a reset.
+        let mut samp = SampleForcedPrefix::new([1, 0]);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        assert_eq!(samp.sample_token(&mut res, &mut logits)?, Some(1));
+        res.with_last_tokens_mut(&mut |lt| lt.extend([1, 0]))?;
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        assert_eq!(samp.sample_token(&mut res, &mut logits)?, None);
+        assert_eq!(logits.len(), T1.len());
+        samp.reset();
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        assert_eq!(samp.sample_token(&mut res, &mut logits)?, Some(1));
+        Ok(())
+    }
+
     #[test]
     fn test_flat_bias() {
         const T: &[f32] = &[0.1, 0.15, 0.2, 0.25, 0.3];
@@ -471,6 +2419,411 @@ mod sampler {
         );
     }
 
+    #[test]
+    fn test_clamp_logits() -> Result<()> {
+        const T: &[f32] = &[-50.0, -1.0, 0.5, f32::NEG_INFINITY, 1e9, f32::INFINITY];
+        let mut res = NilSamplerResources;
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleClampLogits::new(-10.0, 10.0),
+            T,
+            &[-10.0, -1.0, 0.5, f32::NEG_INFINITY, 10.0, 10.0],
+            validate_eq,
+        );
+
+        let with_nan = || -> Result<Logits> {
+            let mut logits = Logits::try_from_iter([0.5f32, 0.0, 20.0])?;
+            logits[1].logit = f32::NAN;
+            Ok(logits)
+        };
+        let mut logits = with_nan()?;
+        assert!(logits
+            .sample(&mut res, &mut SampleClampLogits::new(-10.0, 10.0))
+            .is_err());
+
+        let mut logits = with_nan()?;
+        let mut sampler = SampleClampLogits::new(-10.0, 10.0).nan_policy(NanPolicy::MinusInf);
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [0.5, f32::NEG_INFINITY, 10.0]
+        );
+
+        let mut logits = with_nan()?;
+        let mut sampler = SampleClampLogits::new(-10.0, 10.0).nan_policy(NanPolicy::ZeroOut);
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(
+            logits
+                .iter()
+                .map(|l| (l.token_id, l.logit))
+                .collect::<Vec<_>>(),
+            [(0, 0.5), (2, 10.0)]
+        );
+
+        assert!(SampleClampLogits::try_new(1.0, -1.0).is_err());
+        assert!(SampleClampLogits::try_new(f32::NAN, 1.0).is_err());
+        assert!(SampleClampLogits::try_new(0.0, f32::NAN).is_err());
+        assert!(SampleClampLogits::try_new(1.0, 1.0).is_ok());
+
+        // Bounds set without validation are checked when sampling rather than panicking.
+        let mut logits = Logits::try_from_iter([0.5f32, 20.0])?;
+        assert!(logits
+            .sample(&mut res, &mut SampleClampLogits::new(1.0, -1.0))
+            .is_err());
+        let mut sampler = SampleClampLogits::default();
+        ConfigurableSampler::<usize, f32>::set_option(
+            &mut sampler,
+            "max",
+            SamplerOptionValue::Float(f64::NAN),
+        )?;
+        assert!(logits.sample(&mut res, &mut sampler).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_histogram() -> Result<()> {
+        const FORCED: &[u32] = &[1, 1, 2, 1, 3];
+        let mut sc = SamplerChain::new()
+            + SampleHistogram::new()
+            + SampleForcedPrefix::new(FORCED.iter().copied());
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 0, 0]));
+        for _ in FORCED {
+            let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+            let tid = sc.sample_token(&mut res, &mut logits)?.expect("No token");
+            res.with_last_tokens_mut(&mut |tokens| tokens.push(tid))?;
+        }
+
+        let histogram = sc
+            .get_mut(0)
+            .and_then(|s| s.downcast_mut::<SampleHistogram>())
+            .expect("Missing histogram");
+        assert_eq!(histogram.total(), 4);
+        histogram.observe(&res)?;
+        assert_eq!(histogram.total(), FORCED.len());
+        assert_eq!(
+            (histogram.count(1), histogram.count(2), histogram.count(3)),
+            (3, 1, 1)
+        );
+        assert_eq!(histogram.count(0), 0);
+        assert_eq!(histogram.most_common(2), [(1, 3), (2, 1)]);
+
+        histogram.reset();
+        histogram.observe(&res)?;
+        assert_eq!(histogram.total(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_length_bias() -> Result<()> {
+        const EOS: u32 = 3;
+        let mut sampler = SampleLengthBias::new([EOS], 4, 0.5);
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0; 10]));
+
+        let mut biases = vec![];
+        for _ in 0..7 {
+            let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+            logits.sample(&mut res, &mut sampler)?;
+            assert_eq!(
+                logits[..3].iter().map(|l| l.logit).collect::<Vec<_>>(),
+                T1[..3]
+            );
+            biases.push(logits[EOS as usize].logit - T1[EOS as usize]);
+            res.with_last_tokens_mut(&mut |tokens| tokens.push(0))?;
+        }
+        let expected = [-2.0, -1.5, -1.0, -0.5, 0.0, 0.5, 1.0];
+        assert!(biases
+            .iter()
+            .zip(expected)
+            .all(|(bias, expected)| (bias - expected).abs() < 1e-6));
+        assert_eq!(sampler.bias_at(4), 0.0);
+
+        sampler.reset();
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        logits.sample(&mut res, &mut sampler)?;
+        assert!((logits[EOS as usize].logit - (T1[EOS as usize] - 2.0)).abs() < 1e-6);
+        assert!(SampleLengthBias::try_new([EOS], 4, f32::NAN).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_class_cycle() -> Result<()> {
+        // Tokens 0 and 2 are class 0, 1 and 3 are class 1.
+        let mut sampler = SampleClassCycle::new([(0, 0), (1, 1), (2, 0), (3, 1)], [0, 1]);
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0; 10]));
+        let surviving = |logits: &Logits| {
+            logits
+                .iter()
+                .filter(|l| l.logit != f32::NEG_INFINITY)
+                .map(|l| l.token_id)
+                .collect::<Vec<_>>()
+        };
+
+        for step in 0..5 {
+            let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+            logits.sample(&mut res, &mut sampler)?;
+            let expected = if step % 2 == 0 { [0, 2] } else { [1, 3] };
+            assert_eq!(surviving(&logits), expected, "step {step}");
+            res.with_last_tokens_mut(&mut |tokens| tokens.push(expected[0]))?;
+        }
+        assert_eq!(sampler.class_at(5), Some(1));
+
+        sampler.reset();
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        logits.sample(&mut res, &mut sampler)?;
+        assert_eq!(surviving(&logits), [0, 2]);
+
+        // Nothing in class 2.
+        let mut sampler = SampleClassCycle::new([(0, 0)], [2]);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        assert!(logits.sample(&mut res, &mut sampler).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_temperature() -> Result<()> {
+        // Tokens 0 and 1 are class 0 (code), 2 is class 1 (prose), 3 has no class.
+        let mut sampler =
+            SampleTypedTemperature::new(2.0, [(0, 0), (1, 0), (2, 1)], [(0, 0.5), (1, 4.0)]);
+        assert_eq!(sampler.temperature_for(0), 0.5);
+        assert_eq!(sampler.temperature_for(3), 2.0);
+
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        logits.sample(&mut NilSamplerResources, &mut sampler)?;
+        let expected = [T1[0] / 0.5, T1[1] / 0.5, T1[2] / 4.0, T1[3] / 2.0];
+        assert!(logits
+            .iter()
+            .zip(expected)
+            .all(|(l, expected)| (l.logit - expected).abs() < 1e-6));
+
+        assert!(SampleTypedTemperature::try_new(1.0, [(0, 0)], [(0, -1.0)]).is_err());
+        assert!(SampleTypedTemperature::try_new(f32::NAN, [], []).is_err());
+        assert!(SampleTypedTemperature::try_new(1.0, [(0, 0)], [(0, 0.0)]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_grouped_top_p() -> Result<()> {
+        // Group 0 is much more probable overall, so a global top-p would only keep it.
+        let probs = [0.5f32, 0.3, 0.1, 0.05, 0.03, 0.02];
+        let groups = [(0, 0), (1, 0), (2, 0), (3, 1), (4, 1), (5, 1)];
+        let logits = || Logits::try_from_iter(probs.iter().map(|p| p.ln()));
+        let token_ids = |logits: &Logits| {
+            let mut tids = logits.iter().map(|l| l.token_id).collect::<Vec<_>>();
+            tids.sort();
+            tids
+        };
+        let mut res = NilSamplerResources;
+
+        let mut global = logits()?;
+        global.sample(&mut res, &mut SampleTopP::new(0.7, 1))?;
+        assert_eq!(token_ids(&global), [0, 1]);
+
+        // Group 0: 0.5 / 0.9 and 0.3 / 0.9 reach 0.7. Group 1: 0.05 / 0.1 and 0.03 / 0.1.
+        let mut sampler = SampleGroupedTopP::new(0.7, 1, groups);
+        let mut grouped = logits()?;
+        grouped.sample(&mut res, &mut sampler)?;
+        assert_eq!(token_ids(&grouped), [0, 1, 3, 4]);
+        assert!(grouped.is_sorted());
+
+        let mut grouped = logits()?;
+        grouped.sample(&mut res, &mut SampleGroupedTopP::new(0.1, 2, groups))?;
+        assert_eq!(token_ids(&grouped), [0, 1, 3, 4]);
+
+        // Tokens without a group form their own group.
+        let mut grouped = logits()?;
+        grouped.sample(&mut res, &mut SampleGroupedTopP::new(0.1, 1, [(3, 1)]))?;
+        assert_eq!(token_ids(&grouped), [0, 3]);
+
+        assert!(SampleGroupedTopP::try_new(1.5, 1, groups).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_when() -> Result<()> {
+        // Ban token 3 only right after it was selected.
+        let mut sampler = SampleWhen::new(
+            SampleFlatBias::new([(3, f32::NEG_INFINITY)]),
+            |res: &dyn HasSamplerResources, _logits: &Logits| {
+                let mut repeated = false;
+                let _ = res.with_last_tokens(&mut |tokens| repeated = tokens.last() == Some(&3));
+                repeated
+            },
+        );
+        let mut sc = SamplerChain::new() + sampler.clone() + SampleGreedy::new();
+        let mut res = SimpleSamplerResources::new(None, Some(vec![]));
+        let sampled = (0..4)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+                let tid = sc.sample_token(&mut res, &mut logits)?.expect("No token");
+                res.with_last_tokens_mut(&mut |tokens| tokens.push(tid))?;
+                Ok(tid)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(sampled, [3, 2, 3, 2]);
+
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert!(logits.iter().all(|l| l.logit.is_finite()));
+        assert_eq!(
+            sampler.options_summary().as_deref(),
+            Some("(SampleFlatBias)")
+        );
+
+        let mut sampler = SampleWhen::new(SampleTopK::new(3, 1), |_res, _logits| true);
+        ConfigurableSampler::<usize, f32>::set_option(
+            &mut sampler,
+            "k",
+            SamplerOptionValue::UInt(2),
+        )?;
+        assert_eq!(sampler.sampler().k, 2);
+        assert_eq!(
+            HasSamplerMetadata::<usize, f32>::sampler_metadata(&sampler).name,
+            "when(top-k)"
+        );
+        assert_eq!(sampler.metadata().map(|md| md.name), Some("when(top-k)"));
+        assert!(sampler
+            .options_summary()
+            .is_some_and(|s| s.starts_with("(SampleTopK [k: 2, ")));
+
+        // The wrapped sampler's requirements are only ensured when it runs.
+        let mut sampler = SampleWhen::new(SampleTopP::new(0.5, 1), |_res, logits| logits.len() > 3);
+        assert_eq!(sampler.requires(), SampleRequirements::NONE);
+        let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3])?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert!(!logits.is_sorted() && !logits.get_softmax());
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert!(logits.is_sorted() && logits.len() < T1.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_temperature_greedy() -> Result<()> {
+        use rand::SeedableRng;
+        const T: &[f32] = &[0.1, 0.4, 0.2, 0.3];
+
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        let greedy = logits.sample_token(&mut NilSamplerResources, &mut SampleGreedy::new())?;
+        let mut sc = SamplerChain::new() + SampleTemperature::new(0.0) + SampleRandDistrib::new();
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        for _ in 0..8 {
+            let mut logits = Logits::try_from_iter(T.iter().copied())?;
+            assert_eq!(sc.sample_token(&mut res, &mut logits)?, greedy);
+            assert_eq!(logits.len(), 1);
+        }
+
+        for temp in [1e-5f32, 1e-3] {
+            let mut logits = Logits::try_from_iter([0.1f32, 0.4, 50.0, 0.3])?;
+            SampleTemperature::new(temp).sample(&mut NilSamplerResources, &mut logits)?;
+            logits.ensure_softmax()?;
+            assert!(logits
+                .iter()
+                .all(|l| l.logit.is_finite() && l.prob.is_finite()));
+            assert_eq!(logits.first().map(|l| l.token_id), Some(2));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_flat_bias_from_reader() -> Result<()> {
+        let sampler = SampleFlatBias::from_reader("# bias table\n0\t-inf\n\n3\t1.5\n".as_bytes())?;
+        assert_eq!(*sampler, vec![(0, f32::NEG_INFINITY), (3, 1.5)]);
+        test_sampler_raw(
+            &mut NilSamplerResources,
+            &mut sampler.clone(),
+            &[0.1, 0.15, 0.2, 0.25, 0.3],
+            &[f32::NEG_INFINITY, 0.15, 0.2, 1.75, 0.3],
+            validate_eq,
+        );
+
+        let err = SampleFlatBias::from_reader("0\t-1\n1 2.0\n".as_bytes())
+            .expect_err("Malformed line should fail");
+        assert!(
+            err.to_string().contains("line 2") && err.to_string().contains("\"1 2.0\""),
+            "unexpected error: {err}"
+        );
+        assert!(SampleFlatBias::from_reader("x\t1.0".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_confidence() -> Result<()> {
+        use rand::SeedableRng;
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let mut sc = SamplerChain::new() + SampleConfidence::new(0.8) + SampleRandDistrib::new();
+
+        // Peaked: collapses to the top token.
+        let mut logits = Logits::try_from_iter([0.0f32, 5.0, 0.0, 1.0])?;
+        assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(1));
+        assert_eq!(logits.len(), 1);
+
+        // Flat: passes through.
+        let mut samp = SampleConfidence::new(0.8);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        samp.sample(&mut res, &mut logits)?;
+        assert_eq!(samp.sampled_token_id(), None);
+        assert_eq!(logits.len(), T1.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rank_penalty() -> Result<()> {
+        let mut res = NilSamplerResources;
+        let mut logits = Logits::try_from_iter([1.0f32, 4.0, 2.0, 3.0])?;
+        SampleRankPenalty::new(1.0).sample(&mut res, &mut logits)?;
+        assert!(logits.get_sorted());
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            [1, 3, 2, 0]
+        );
+        let penalties = logits
+            .iter()
+            .zip([4.0f32, 3.0, 2.0, 1.0])
+            .map(|(l, orig)| orig - l.logit)
+            .collect::<Vec<_>>();
+        assert_eq!(penalties[0], 0.0);
+        assert!(penalties.windows(2).all(|w| w[0] < w[1]));
+        assert!((penalties[3] - 4f32.ln()).abs() < 1e-6);
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleRankPenalty::new(0.0),
+            T1,
+            T1,
+            validate_eq,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_vocab_clamp() {
+        const T: &[f32] = &[0.1, 0.15, 0.2, 0.25, 0.3];
+        let mut res = NilSamplerResources;
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleVocabClamp::new(2),
+            T,
+            &[0.1, 0.15, 0.2, f32::NEG_INFINITY, f32::NEG_INFINITY],
+            validate_eq,
+        );
+        test_sampler_raw(&mut res, &mut SampleVocabClamp::new(4), T, T, validate_eq);
+        test_sampler_raw(
+            &mut res,
+            &mut SampleVocabClamp::default(),
+            T,
+            T,
+            validate_eq,
+        );
+    }
+
     #[test]
     fn test_rand_distrib() -> Result<()> {
         use rand::SeedableRng;
@@ -492,6 +2845,154 @@ mod sampler {
         Ok(())
     }
 
+    #[test]
+    fn test_rand_distrib_gamma() -> Result<()> {
+        use rand::SeedableRng;
+        const T: &[f32] = &[0.4, 0.3, 0.2, 0.1];
+        const DRAWS: usize = 4000;
+
+        let top_share = |gamma: f32| -> Result<f32> {
+            let mut res = SimpleSamplerResources::new(
+                Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+                None,
+            );
+            let mut sampler = SampleRandDistrib::new().gamma(gamma);
+            let mut top = 0;
+            for _ in 0..DRAWS {
+                let mut logits = Logits::try_from_iter(T.iter().map(|i| i.ln()))?;
+                if sampler.sample_token(&mut res, &mut logits)? == Some(0) {
+                    top += 1;
+                }
+                // The probabilities aren't changed by gamma.
+                assert!((logits[0].prob - 0.4).abs() < 1e-6);
+            }
+            Ok(top as f32 / DRAWS as f32)
+        };
+
+        let shares = [0.0f32, 1.0, 2.0, 4.0, 50.0]
+            .into_iter()
+            .map(top_share)
+            .collect::<Result<Vec<_>>>()?;
+        assert!(shares.windows(2).all(|w| w[0] < w[1]), "{shares:?}");
+        // Uniform, then proportional to the probabilities.
+        assert!((shares[0] - 0.25).abs() < 0.03, "{shares:?}");
+        assert!((shares[1] - 0.4).abs() < 0.03, "{shares:?}");
+        assert!(shares[4] > 0.99, "{shares:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rand_distrib_statistical() -> Result<()> {
+        use rand::SeedableRng;
+        const T: &[f32] = &[0.7, 0.2, 0.1];
+        const DRAWS: usize = 10000;
+
+        let mut sampler = SampleRandDistrib::new();
+        assert!(sampler.is_stochastic());
+        assert!(!SampleGreedy::new().is_stochastic());
+        assert!(
+            (SamplerChain::new() + SampleTopK::default() + SampleRandDistrib::new())
+                .is_stochastic()
+        );
+
+        let mut draw_all = |seed| -> Result<Vec<u32>> {
+            let mut res = SimpleSamplerResources::new(
+                Some(Box::new(rand::rngs::StdRng::seed_from_u64(seed))),
+                None,
+            );
+            (0..DRAWS)
+                .map(|_| {
+                    let mut logits = Logits::try_from_iter(T.iter().map(|i| i.ln()))?;
+                    let tid = logits.sample_token(&mut res, &mut sampler)?;
+                    assert_eq!(sampler.sampled_token_id(), tid);
+                    tid.ok_or_else(|| anyhow::anyhow!("No token selected"))
+                })
+                .collect()
+        };
+
+        let draws = draw_all(123)?;
+        assert_eq!(
+            draws,
+            draw_all(123)?,
+            "Same seed should give the same draws"
+        );
+        assert_ne!(draws, draw_all(456)?);
+
+        T.iter().enumerate().for_each(|(tid, prob)| {
+            let freq = draws.iter().filter(|t| **t as usize == tid).count() as f32 / DRAWS as f32;
+            assert!(
+                (freq - prob).abs() < 0.02,
+                "token {tid} frequency {freq} too far from {prob}"
+            );
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixture() -> Result<()> {
+        use rand::SeedableRng;
+        const STEPS: usize = 5000;
+
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        // The first sampler picks token 3, the second picks token 2.
+        let mut sampler = SampleMixture::new(
+            SampleGreedy::new(),
+            SamplerChain::new()
+                + SampleFlatBias::new([(3, f32::NEG_INFINITY)])
+                + SampleGreedy::new(),
+            0.2,
+        );
+        assert!(sampler.is_stochastic());
+
+        let first = (0..STEPS)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+                let tid = sampler.sample_token(&mut res, &mut logits)?;
+                assert!(matches!(tid, Some(2) | Some(3)));
+                anyhow::Ok(tid == Some(3))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|first| *first)
+            .count();
+        let freq = first as f32 / STEPS as f32;
+        assert!((freq - 0.2).abs() < 0.02, "first sampler frequency {freq}");
+
+        // Without an RNG the coin can't be drawn.
+        assert!(Logits::try_from_iter(T1.iter().copied())?
+            .sample_token(&mut NilSamplerResources, &mut sampler)
+            .is_err());
+
+        // The requirements of the sampler that runs are ensured first.
+        #[derive(Debug, Clone)]
+        struct NeedsSoftmax;
+
+        impl Sampler for NeedsSoftmax {
+            fn sample<'a>(
+                &mut self,
+                _res: &mut dyn HasSamplerResources,
+                logits: &'a mut Logits,
+            ) -> Result<&'a mut Logits, SamplerError> {
+                assert!(logits.is_sorted() && logits.is_softmax());
+                Ok(logits)
+            }
+
+            fn requires(&self) -> SampleRequirements {
+                SampleRequirements::SOFTMAX
+            }
+        }
+
+        let mut sampler = SampleMixture::new(NeedsSoftmax, SampleGreedy::new(), 1.0);
+        assert_eq!(sampler.requires(), SampleRequirements::NONE);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert!(logits.is_softmax());
+        Ok(())
+    }
+
     #[test]
     fn test_mirostat1() -> Result<()> {
         use rand::SeedableRng;
@@ -535,6 +3036,189 @@ mod sampler {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_mirostat_stats() -> Result<()> {
+        use rand::SeedableRng;
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let mut sampler = SampleMirostat2::new(5.0, 0.1);
+        assert_eq!(sampler.last_stats(), None);
+
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        let tid = sampler.sample_token(&mut res, &mut logits)?.unwrap();
+        let prob = logits.iter().find(|l| l.token_id == tid).unwrap().prob;
+        let stats = sampler.last_stats().unwrap();
+        assert_eq!(stats.observed_surprise, -prob.log2());
+        assert_eq!(stats.target_surprise, 5.0);
+        assert!((stats.mu - (10.0 - 0.1 * (stats.observed_surprise - 5.0))).abs() < 1e-6);
+
+        let mut sampler = SampleMirostat1::new(4, 5.0, 0.1);
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        let tid = sampler.sample_token(&mut res, &mut logits)?.unwrap();
+        let prob = logits.iter().find(|l| l.token_id == tid).unwrap().prob;
+        assert_eq!(
+            sampler.last_stats().unwrap().observed_surprise,
+            -prob.log2()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirostat_context_shift() {
+        let mut sampler = SampleMirostat2::new(5.0, 0.1).mu(6.0);
+        sampler.notify_context_shift(10);
+        assert_eq!(sampler.mu, 6.0);
+
+        let mut sampler = sampler.shift_decay(0.5);
+        sampler.notify_context_shift(0);
+        assert_eq!(sampler.mu, 6.0);
+        sampler.notify_context_shift(10);
+        assert_eq!(sampler.mu, 8.0);
+
+        let mut sc =
+            SamplerChain::new() + SampleMirostat1::new(4, 5.0, 0.1).mu(6.0).shift_decay(1.0);
+        sc.notify_context_shift(10);
+        let opts = format!("{sc}");
+        assert!(opts.contains("mu: 10,"), "unexpected options: {opts}");
+    }
+
+    #[test]
+    fn test_mirostat_state() -> Result<()> {
+        use rand::SeedableRng;
+
+        let logits = || Logits::try_from_iter((0..20).map(|n| (n as f32 * 0.7).sin() * 3.0));
+        let run = |sampler: &mut SampleMirostat2, seed, steps| -> Result<Vec<(u32, f32)>> {
+            let mut res = SimpleSamplerResources::new(
+                Some(Box::new(rand::rngs::StdRng::seed_from_u64(seed))),
+                None,
+            );
+            (0..steps)
+                .map(|_| {
+                    let tid = logits()?
+                        .sample_token(&mut res, &mut *sampler)?
+                        .expect("No token selected");
+                    Ok((tid, sampler.mu))
+                })
+                .collect()
+        };
+
+        let mut sampler = SampleMirostat2::new(2.0, 0.5);
+        run(&mut sampler, 123, 5)?;
+        let state = sampler.state();
+        assert_ne!(state.mu, 4.0);
+        assert_eq!(state.token, sampler.sampled_token_id());
+
+        let mut restored = SampleMirostat2::new(2.0, 0.5);
+        restored.set_state(state);
+        assert_eq!(restored.state(), state);
+        assert_eq!(restored.last_stats(), sampler.last_stats());
+        assert_eq!(run(&mut restored, 456, 10)?, run(&mut sampler, 456, 10)?);
+
+        let mut sampler = SampleMirostat1::new(20, 2.0, 0.5);
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        logits()?.sample_token(&mut res, &mut sampler)?;
+        let mut restored = SampleMirostat1::new(20, 2.0, 0.5);
+        restored.set_state(sampler.state());
+        assert_eq!(restored.mu, sampler.mu);
+        assert_eq!(restored.sampled_token_id(), sampler.sampled_token_id());
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_serde() -> Result<()> {
+        use rand::SeedableRng;
+
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            Some(vec![0; 4]),
+        );
+        let logits = || Logits::try_from_iter((0..20).map(|n| (n as f32 * 0.7).sin() * 3.0));
+
+        let mut sampler = SampleMirostat2::new(2.0, 0.5);
+        logits()?.sample_token(&mut res, &mut sampler)?;
+        let json = sampler.serialize_state(serde_json::value::Serializer)?;
+        let mut restored = SampleMirostat2::new(2.0, 0.5);
+        restored.deserialize_state(json)?;
+        assert_eq!(restored.state(), sampler.state());
+
+        let mut sampler = SampleMirostat1::new(20, 2.0, 0.5);
+        logits()?.sample_token(&mut res, &mut sampler)?;
+        let json = serde_json::to_string(&sampler.state())?;
+        let mut restored = SampleMirostat1::new(20, 2.0, 0.5);
+        restored.deserialize_state(&mut serde_json::Deserializer::from_str(&json))?;
+        assert_eq!(restored.state(), sampler.state());
+
+        let mut sampler = SampleLogitSmoothing::new(0.5);
+        let mut partial = logits()?;
+        partial.retain(|l| l.token_id % 2 == 0);
+        sampler.sample(&mut res, &mut partial)?;
+        let json = serde_json::to_string(&sampler.state())?;
+        let mut restored = SampleLogitSmoothing::new(0.5);
+        restored.set_state(serde_json::from_str(&json)?);
+        assert_eq!(restored.state(), sampler.state());
+        let blended = |sampler: &mut SampleLogitSmoothing| -> Result<Vec<f32>> {
+            let mut logits = logits()?;
+            sampler.sample(&mut NilSamplerResources, &mut logits)?;
+            Ok(logits.iter().map(|l| l.logit).collect())
+        };
+        assert_eq!(blended(&mut restored)?, blended(&mut sampler)?);
+
+        let mut sampler = SampleClassCycle::new([(0, 0), (1, 1)], [0, 1]);
+        sampler.sample(&mut res, &mut logits()?)?;
+        res.with_last_tokens_mut(&mut |tokens| tokens.push(0))?;
+        sampler.notify_context_shift(1);
+        let json = serde_json::to_string(&sampler.position())?;
+        let mut restored = SampleClassCycle::new([(0, 0), (1, 1)], [0, 1]);
+        restored.set_position(serde_json::from_str(&json)?);
+        assert_eq!(restored.position(), sampler.position());
+        assert_eq!(restored.position().step(&res)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_position_context_shift() -> Result<()> {
+        let mut pos = PositionAware::new();
+        pos.context_shift(5);
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0; 10]));
+        assert_eq!(pos.step(&res)?, 0);
+        res.with_last_tokens_mut(&mut |tokens| tokens.extend([1, 2, 3]))?;
+        assert_eq!(pos.step(&res)?, 3);
+        res.with_last_tokens_mut(&mut |tokens| drop(tokens.drain(..8)))?;
+        pos.context_shift(8);
+        assert_eq!(pos.step(&res)?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_position_new_tokens() -> Result<()> {
+        let mut pos = PositionAware::new();
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0; 10]));
+        let new_tokens = |pos: &mut PositionAware, res: &SimpleSamplerResources| {
+            let mut new = vec![];
+            pos.new_tokens(res, &mut |tokens| new.extend_from_slice(tokens))
+                .map(|_| new)
+        };
+        assert!(new_tokens(&mut pos, &res)?.is_empty());
+        res.with_last_tokens_mut(&mut |tokens| tokens.extend([1, 2, 3]))?;
+        assert_eq!(new_tokens(&mut pos, &res)?, [1, 2, 3]);
+        assert_eq!(pos.step(&res)?, 0);
+        res.with_last_tokens_mut(&mut |tokens| {
+            tokens.drain(..8);
+            tokens.push(4);
+        })?;
+        pos.context_shift(8);
+        assert_eq!(new_tokens(&mut pos, &res)?, [4]);
+        pos.context_shift(20);
+        assert_eq!(new_tokens(&mut pos, &res)?, [0, 0, 1, 2, 3, 4]);
+        Ok(())
+    }
 }
 
 mod configure {
@@ -542,6 +3226,66 @@ mod configure {
 
     use crate::configure::*;
 
+    #[test]
+    fn test_display_metadata() {
+        let md = HasSamplerMetadata::<usize, f32>::sampler_metadata(&SampleTemperature::default());
+        assert_eq!(
+            md.to_string(),
+            concat!(
+                "temperature: Temperature value. Higher values make the output more random. ",
+                "[temperature: float]"
+            )
+        );
+
+        let mut md = SampleTopP::default().sampler_metadata();
+        md.description = None;
+        assert_eq!(
+            md.to_string(),
+            concat!(
+                "top-p [p: float, min_keep: uint, entropy_factor: float, ",
+                "inclusive_boundary: bool, negligible_prob: float]"
+            )
+        );
+        md.options.clear();
+        assert_eq!(md.to_string(), "top-p");
+    }
+
+    #[test]
+    fn test_configure_exact_key() -> Result<()> {
+        // "k" is also a prefix of "keep_ties", but an exact match wins.
+        let mut top_k = SampleTopK::default();
+        ConfigurableSampler::<usize, f32>::configure(&mut top_k, "k=7:keep=true")?;
+        assert_eq!((top_k.k, top_k.keep_ties), (7, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_chain() {
+        let mut sc = SamplerChain::new()
+            + SampleTopK::new(40, 1)
+            + SampleTemperature::new(0.8)
+            + SampleRandDistrib::new();
+        assert_eq!(
+            sc.to_string(),
+            concat!(
+                "SampleTopK [k: 40, min_keep: 1, entropy_factor: 0, keep_ties: false] -> ",
+                "SampleTemperature [temperature: 0.8] -> SampleRandDistrib [gamma: 1]"
+            )
+        );
+
+        sc.set_warmup_steps(2);
+        let sc = SamplerChain::new() + SampleRepetition::new(1.1, 64) + sc;
+        assert_eq!(
+            sc.to_string(),
+            concat!(
+                "SampleRepetition [penalty: 1.1, last_n: 64, skip_prompt: false, ",
+                "min_logit_floor: -inf] -> SamplerChain (SampleTopK [k: 40, min_keep: 1, ",
+                "entropy_factor: 0, keep_ties: false] -> SampleTemperature [temperature: 0.8] -> ",
+                "SampleRandDistrib [gamma: 1] (warmup_steps: 2))"
+            )
+        );
+    }
+
     #[test]
     fn test_parse_uint() -> Result<()> {
         assert_eq!(
@@ -608,6 +3352,36 @@ mod configure {
         Ok(())
     }
 
+    #[test]
+    fn test_advanced_options() {
+        fn advanced<S: HasSamplerMetadata<usize, f32>>(samp: &S) -> Vec<(&'static str, bool)> {
+            samp.sampler_metadata()
+                .options
+                .into_iter()
+                .map(|omd| (omd.key, omd.advanced))
+                .collect()
+        }
+
+        assert_eq!(
+            advanced(&SampleTopP::default()),
+            [
+                ("p", false),
+                ("min_keep", true),
+                ("entropy_factor", true),
+                ("inclusive_boundary", true),
+                ("negligible_prob", true)
+            ]
+        );
+        assert_eq!(
+            advanced(&SampleMinP::default()),
+            [("p", false), ("min_keep", true)]
+        );
+        assert_eq!(
+            advanced(&SampleTemperature::default()),
+            [("temperature", false)]
+        );
+    }
+
     #[test]
     fn test_config_from_str1() -> Result<()> {
         let mut samp = SampleTemperature::new(5.0);
@@ -687,7 +3461,7 @@ mod build {
         let mut sc = ss.into_chain();
 
         let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 2, 3, 3, 0, 0]));
-        let mut logits = Logits::try_from_iter([0.2, 0.2, 0.19, 0.2].into_iter())?;
+        let mut logits = Logits::try_from_iter([0.2, 0.2, 0.19, 0.2])?;
         let tok = sc.sample_token(&mut res, &mut logits)?;
         assert_eq!(tok, Some(1));
 
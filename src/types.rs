@@ -9,6 +9,8 @@ use thiserror::Error;
 
 pub use crate::{chain::*, resource::*};
 
+use crate::configure::SamplerMetadata;
+
 /// Type for token IDs.
 pub type TID = u32;
 
@@ -57,6 +59,10 @@ pub enum SamplerError {
     /// RNG weights-related errors
     RandWeightedError(rand::distributions::WeightedError),
 
+    #[error("invalid option: {0}")]
+    /// A sampler option has an invalid value.
+    InvalidOption(String),
+
     #[error("Interrupted Sampler")]
     /// Interrupted sampler
     Interrupted(Interruption),
@@ -69,11 +75,30 @@ pub enum LogitsError {
     /// Contains the position (AKA token id) of the offending logit.
     /// Logits cannot be NaN.
     InvalidLogit(usize),
+    #[error("Length mismatch: {logits} logits but {ids} token ids")]
+    /// The number of logits didn't match the number of token ids.
+    LengthMismatch { logits: usize, ids: usize },
     #[error("internal logits error: {0}")]
     /// General internal error type.
     InternalError(String),
 }
 
+/// Returns a [SamplerError::InvalidOption] error for the option `key` unless `valid` is
+/// `true`. `requirement` describes what a valid value looks like.
+pub(crate) fn check_option(
+    key: &str,
+    valid: bool,
+    requirement: &str,
+    val: impl Display,
+) -> Result<(), SamplerError> {
+    if valid {
+        return Ok(());
+    }
+    Err(SamplerError::InvalidOption(format!(
+        "{key} must be {requirement} but got {val}"
+    )))
+}
+
 impl From<LogitsError> for SamplerError {
     fn from(value: LogitsError) -> Self {
         SamplerError::LogitsError(value)
@@ -86,10 +111,60 @@ impl From<anyhow::Error> for SamplerError {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Controls how NaN logits (for example from a buggy model or quantization) are handled.
+pub enum NanPolicy {
+    #[default]
+    /// NaN logits result in a [LogitsError::InvalidLogit] error.
+    Error,
+    /// NaN logits are treated as having zero probability: the entry is dropped
+    /// from the [Logits].
+    ZeroOut,
+    /// NaN logits are replaced with negative infinity.
+    MinusInf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// The base used for the exponentials when applying the softmax function to [Logits].
+///
+/// Base-2 softmax of the logits `x` is the same as natural softmax of `x * ln(2)`. In
+/// other words, it's equivalent to natural softmax with the temperature multiplied by
+/// `1 / ln(2)` (about `1.4427`): a temperature of `T` with base-2 softmax gives the same
+/// distribution as a temperature of `T * ln(2)` with natural softmax. Keep this in mind if
+/// you use temperature values tuned for one base with the other.
+pub enum SoftmaxBase {
+    #[default]
+    /// Use `e^x`. Entropies are in nats.
+    Natural,
+    /// Use `2^x`. Entropies are in bits.
+    Two,
+}
+
+impl SoftmaxBase {
+    /// Raise the base to the power of `x`.
+    pub fn exp(self, x: L) -> L {
+        match self {
+            SoftmaxBase::Natural => x.exp(),
+            SoftmaxBase::Two => x.exp2(),
+        }
+    }
+
+    /// Logarithm of `x` in this base.
+    pub fn log(self, x: L) -> L {
+        match self {
+            SoftmaxBase::Natural => x.ln(),
+            SoftmaxBase::Two => x.log2(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// An individual logit with some additional metadata for use by the samplers.
 pub struct Logit {
-    /// The token id.
+    /// The token id. When the [Logits] were built from the result of evaluating the
+    /// model, this is the original position in the vocabulary. Samplers never change it,
+    /// it stays attached to its logit through sorting, filtering and truncation.
     pub token_id: TID,
     /// The logit value.
     pub logit: L,
@@ -102,9 +177,19 @@ pub struct Logit {
 /// evaluating the LLM.
 ///
 /// For convenience, this can [Deref] to the internal [Vec].
+///
+/// Samplers may reorder and truncate the [Logit]s, but each one keeps its
+/// [Logit::token_id], so the surviving entries can always be mapped back to their
+/// original vocabulary positions (for example to scatter the probabilities back into
+/// a full-size tensor).
+///
+/// Mutable access to the [Logit]s through [DerefMut] invalidates the cached
+/// probability sum, see [Self::prob_sum].
 pub struct Logits {
     sorted: bool,
     has_softmax: bool,
+    softmax_base: SoftmaxBase,
+    prob_sum: Option<L>,
     logits: Vec<Logit>,
 }
 
@@ -118,6 +203,7 @@ impl Deref for Logits {
 
 impl DerefMut for Logits {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.prob_sum = None;
         &mut self.logits
     }
 }
@@ -125,25 +211,50 @@ impl DerefMut for Logits {
 impl Logits {
     /// Make a new [Logits] from an iterator of `L`. We'd like to
     /// write this as [TryFrom] but unfortunately the types make this impossible.
+    ///
+    /// NaN logits are an error, see [Self::try_from_iter_with_nan_policy].
     pub fn try_from_iter<I: IntoIterator<Item = L>>(it: I) -> Result<Self, LogitsError> {
-        let mut tid = 0;
+        Self::try_from_iter_with_nan_policy(it, NanPolicy::Error)
+    }
+
+    #[cfg(feature = "testing")]
+    /// Make new [Logits] for `vocab_size` tokens with random logits between `-10.0`
+    /// and `10.0`, intended for tests and benchmarks. The result is reproducible
+    /// when using an RNG with a fixed seed.
+    ///
+    /// Requires the `testing` feature.
+    pub fn random(vocab_size: usize, rng: &mut impl rand::Rng) -> Self {
+        Self::try_from_iter((0..vocab_size).map(|_| rng.gen_range(-10f32..10f32)))
+            .expect("Impossible: random logits can't be NaN")
+    }
+
+    /// Make a new [Logits] from an iterator of `L`, handling NaN logits
+    /// as specified by the [NanPolicy].
+    pub fn try_from_iter_with_nan_policy<I: IntoIterator<Item = L>>(
+        it: I,
+        policy: NanPolicy,
+    ) -> Result<Self, LogitsError> {
         Ok(Self {
             sorted: false,
             has_softmax: false,
-            logits: it
-                .into_iter()
-                .enumerate()
-                .map(|(idx, logit)| {
-                    if logit.is_nan() {
-                        Err(LogitsError::InvalidLogit(idx))?
-                    }
-                    let result = Logit {
+            softmax_base: SoftmaxBase::Natural,
+            prob_sum: None,
+            logits: (0u32..)
+                .zip(it)
+                .filter_map(|(tid, logit)| {
+                    let logit = match (logit.is_nan(), policy) {
+                        (false, _) => logit,
+                        (true, NanPolicy::Error) => {
+                            return Some(Err(LogitsError::InvalidLogit(tid as usize)))
+                        }
+                        (true, NanPolicy::ZeroOut) => return None,
+                        (true, NanPolicy::MinusInf) => f32::NEG_INFINITY,
+                    };
+                    Some(Ok(Logit {
                         token_id: tid,
                         logit,
                         prob: 0f32,
-                    };
-                    tid += 1;
-                    Ok(result)
+                    }))
                 })
                 .collect::<Result<Vec<_>, LogitsError>>()?,
         })
@@ -167,6 +278,8 @@ impl Logits {
         Ok(Logits {
             sorted: true,
             has_softmax: false,
+            softmax_base: SoftmaxBase::Natural,
+            prob_sum: None,
             logits: (0u32..)
                 .zip(it)
                 .filter(|(_tid, logit)| logit.is_finite())
@@ -191,6 +304,130 @@ impl Logits {
                 }),
         })
     }
+
+    /// Make a new [Logits] from an iterator of `(token_id, logit)` pairs that the
+    /// caller has already sorted by logit, descending. The result is flagged as
+    /// sorted so samplers won't sort it again, which is useful when the host
+    /// already has sorted data (for example from sorting on the GPU).
+    ///
+    /// NaN logits are an error. The ordering is verified in debug builds only:
+    /// passing unsorted data panics there and silently results in unexpected
+    /// sampling in release builds.
+    pub fn try_from_presorted<I: IntoIterator<Item = (TID, L)>>(
+        it: I,
+    ) -> Result<Self, LogitsError> {
+        let logits = it
+            .into_iter()
+            .map(|(token_id, logit)| {
+                if logit.is_nan() {
+                    return Err(LogitsError::InvalidLogit(token_id as usize));
+                }
+                Ok(Logit {
+                    token_id,
+                    logit,
+                    prob: 0f32,
+                })
+            })
+            .collect::<Result<Vec<_>, LogitsError>>()?;
+        debug_assert!(
+            logits.windows(2).all(|w| w[0].logit >= w[1].logit),
+            "Logits passed to try_from_presorted aren't sorted"
+        );
+        Ok(Self {
+            sorted: true,
+            has_softmax: false,
+            softmax_base: SoftmaxBase::Natural,
+            prob_sum: None,
+            logits,
+        })
+    }
+
+    /// Make a new [Logits] from a slice of `L`. Token ids are assigned
+    /// sequentially based on position, starting from `0`.
+    ///
+    /// NaN logits are an error.
+    pub fn from_logits_slice(logits: &[L]) -> Result<Self, LogitsError> {
+        Self::try_from_iter(logits.iter().copied())
+    }
+
+    /// Make a new [Logits] from a slice of `L` and a slice of token ids
+    /// of the same length. The logit at each position is assigned the
+    /// token id at the same position in `ids`.
+    ///
+    /// NaN logits are an error, as is a length mismatch between the two slices.
+    pub fn from_logits_with_ids(logits: &[L], ids: &[TID]) -> Result<Self, LogitsError> {
+        if logits.len() != ids.len() {
+            return Err(LogitsError::LengthMismatch {
+                logits: logits.len(),
+                ids: ids.len(),
+            });
+        }
+        Ok(Self {
+            sorted: false,
+            has_softmax: false,
+            softmax_base: SoftmaxBase::Natural,
+            prob_sum: None,
+            logits: ids
+                .iter()
+                .zip(logits.iter())
+                .map(|(&token_id, &logit)| {
+                    if logit.is_nan() {
+                        return Err(LogitsError::InvalidLogit(token_id as usize));
+                    }
+                    Ok(Logit {
+                        token_id,
+                        logit,
+                        prob: 0f32,
+                    })
+                })
+                .collect::<Result<Vec<_>, LogitsError>>()?,
+        })
+    }
+
+    /// Refill the [Logits] from a slice of `L` in place, reusing the existing allocation.
+    /// Token ids are assigned sequentially like [Self::from_logits_slice]. The sorted and
+    /// softmax flags are cleared, the [SoftmaxBase] is kept.
+    ///
+    /// NaN logits are an error, in which case the [Logits] are left empty.
+    pub fn clear_and_fill(&mut self, logits: &[L]) -> Result<&mut Self, LogitsError> {
+        self.refill((0u32..).zip(logits.iter().copied()))
+    }
+
+    /// Refill the [Logits] from a slice of `L` and a slice of token ids in place, reusing
+    /// the existing allocation. See [Self::from_logits_with_ids] and [Self::clear_and_fill].
+    pub fn clear_and_fill_with_ids(
+        &mut self,
+        logits: &[L],
+        ids: &[TID],
+    ) -> Result<&mut Self, LogitsError> {
+        if logits.len() != ids.len() {
+            self.refill(std::iter::empty())?;
+            return Err(LogitsError::LengthMismatch {
+                logits: logits.len(),
+                ids: ids.len(),
+            });
+        }
+        self.refill(ids.iter().copied().zip(logits.iter().copied()))
+    }
+
+    fn refill(&mut self, it: impl Iterator<Item = (TID, L)>) -> Result<&mut Self, LogitsError> {
+        self.sorted = false;
+        self.has_softmax = false;
+        self.prob_sum = None;
+        self.logits.clear();
+        for (token_id, logit) in it {
+            if logit.is_nan() {
+                self.logits.clear();
+                return Err(LogitsError::InvalidLogit(token_id as usize));
+            }
+            self.logits.push(Logit {
+                token_id,
+                logit,
+                prob: 0f32,
+            });
+        }
+        Ok(self)
+    }
 }
 
 impl TryFrom<Vec<L>> for Logits {
@@ -218,12 +455,72 @@ impl Logits {
         self.has_softmax
     }
 
+    /// Returns `true` if the [Logits] are currently sorted by logit, descending.
+    /// This is the same as [Logits::get_sorted].
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// Returns `true` if the probabilities currently hold valid softmax results,
+    /// so calling [Logits::ensure_softmax] won't need to do any work. This is the same
+    /// as [Logits::get_softmax].
+    pub fn is_softmax(&self) -> bool {
+        self.has_softmax
+    }
+
     /// Set the softmax flag.
     pub fn set_softmax(&mut self, has_softmax: bool) -> &mut Self {
         self.has_softmax = has_softmax;
         self
     }
 
+    /// Get the base used when applying the softmax function.
+    pub fn get_softmax_base(&self) -> SoftmaxBase {
+        self.softmax_base
+    }
+
+    /// Set the base used when applying the softmax function. Changing it
+    /// clears the softmax flag. See [SoftmaxBase] for how this interacts
+    /// with temperature.
+    pub fn set_softmax_base(&mut self, base: SoftmaxBase) -> &mut Self {
+        if base != self.softmax_base {
+            self.softmax_base = base;
+            self.has_softmax = false;
+        }
+        self
+    }
+
+    /// Handle any NaN logits as specified by the [NanPolicy]. This is useful if
+    /// the [Logits] were modified after they were built.
+    pub fn apply_nan_policy(&mut self, policy: NanPolicy) -> Result<&mut Self, LogitsError> {
+        match policy {
+            NanPolicy::Error => {
+                if let Some(l) = self.iter().find(|l| l.logit.is_nan()) {
+                    Err(LogitsError::InvalidLogit(l.token_id as usize))?
+                }
+            }
+            NanPolicy::ZeroOut => {
+                let len = self.len();
+                self.retain(|l| !l.logit.is_nan());
+                if len != self.len() {
+                    self.set_softmax(false);
+                }
+            }
+            NanPolicy::MinusInf => {
+                let mut changed = 0;
+                self.iter_mut().filter(|l| l.logit.is_nan()).for_each(|l| {
+                    l.logit = f32::NEG_INFINITY;
+                    changed += 1;
+                });
+                if changed > 0 {
+                    self.set_sorted(false);
+                    self.set_softmax(false);
+                }
+            }
+        }
+        Ok(self)
+    }
+
     /// Ensure the [Logits] are sorted. Generally not necessary to call this directly.
     pub fn ensure_sorted(&mut self) -> Result<&mut Self> {
         if self.get_sorted() {
@@ -252,16 +549,290 @@ impl Logits {
             return Ok(self);
         }
         self.ensure_sorted()?;
-        let max_l = self[0].logit;
-        let cum_sum = self.iter_mut().fold(0f32, |cs, l| {
-            l.prob = (l.logit - max_l).exp();
+        let len = self.len();
+        self.softmax_range(0, len)
+    }
+
+    /// Apply the softmax function to only the [Logit]s in the range `start..end`,
+    /// leaving the rest alone. Useful for renormalizing the survivors after a
+    /// truncation in sorted order without touching the discarded tail.
+    ///
+    /// The softmax flag is set if the range covers all the [Logits], otherwise
+    /// it's cleared.
+    pub fn softmax_range(&mut self, start: usize, end: usize) -> Result<&mut Self> {
+        if start > end || end > self.len() {
+            Err(LogitsError::InternalError(format!(
+                "Bad softmax range {start}..{end} for {} logits",
+                self.len()
+            )))?
+        }
+        let base = self.softmax_base;
+        let range = &mut self.logits[start..end];
+        let max_l = range
+            .iter()
+            .fold(f32::NEG_INFINITY, |max_l, l| max_l.max(l.logit));
+        let cum_sum = range.iter_mut().fold(0f32, |cs, l| {
+            l.prob = base.exp(l.logit - max_l);
             cs + l.prob
         });
-        self.iter_mut().for_each(|l| l.prob /= cum_sum);
-        self.has_softmax = true;
+        let prob_sum = range.iter_mut().fold(0f32, |ps, l| {
+            l.prob /= cum_sum;
+            ps + l.prob
+        });
+        self.has_softmax = start == 0 && end == self.len();
+        self.prob_sum = None;
+        if self.has_softmax {
+            debug_assert!(
+                !(cum_sum.is_finite() && cum_sum > 0f32) || (prob_sum - 1f32).abs() < 1e-3,
+                "Probabilities sum to {prob_sum} after softmax"
+            );
+            self.prob_sum = Some(prob_sum);
+        }
         Ok(self)
     }
 
+    /// Returns the total probability of the [Logit]s. This will be close to `1.0`
+    /// right after softmax has been applied and lower after truncation.
+    ///
+    /// The result is cached until the [Logit]s are mutably accessed, so repeated
+    /// calls don't need to rescan the list. Softmax fills the cache, which top-p,
+    /// [Self::percentile_cutoff] and [SampleRandDistrib] use rather than summing the
+    /// probabilities again.
+    ///
+    /// [SampleRandDistrib]: crate::samplers::SampleRandDistrib
+    pub fn prob_sum(&mut self) -> L {
+        if let Some(prob_sum) = self.prob_sum {
+            return prob_sum;
+        }
+        let prob_sum = self.logits.iter().fold(0f32, |ps, l| ps + l.prob);
+        self.prob_sum = Some(prob_sum);
+        prob_sum
+    }
+
+    /// Returns the index in the sorted [Logits] of the token where the cumulative
+    /// probability reaches `q`, so keeping the tokens up to and including that index covers
+    /// at least `q` of the probability mass. `q` is clamped to `0.0..=1.0`. This will ensure
+    /// the softmax function has been applied.
+    ///
+    /// If the cumulative probability never reaches `q` due to rounding, the index of the
+    /// last token is returned. For empty [Logits] the result is `0`.
+    pub fn percentile_cutoff(&mut self, q: L) -> Result<usize> {
+        self.ensure_softmax()?;
+        let q = q.clamp(0f32, 1f32) * self.prob_sum();
+        let mut cum_sum = 0f32;
+        Ok(self
+            .iter()
+            .position(|l| {
+                cum_sum += l.prob;
+                cum_sum >= q
+            })
+            .unwrap_or(self.len().saturating_sub(1)))
+    }
+
+    /// Returns the token ids and probabilities the [Logits] would have at temperature `temp`,
+    /// most probable first. This works on a copy, so the [Logits] aren't changed. Useful
+    /// for comparing the effect of several temperatures on the same logits.
+    ///
+    /// Temperatures below [SampleTemperature::GREEDY_THRESHOLD] collapse to the most
+    /// probable token like [SampleTemperature] does: it gets a probability of `1.0` and
+    /// the others `0.0`.
+    ///
+    /// [SampleTemperature]: crate::samplers::SampleTemperature
+    /// [SampleTemperature::GREEDY_THRESHOLD]: crate::samplers::SampleTemperature::GREEDY_THRESHOLD
+    pub fn with_temperature_probs(&self, temp: L) -> Result<Vec<(TID, L)>> {
+        let mut logits = self.clone();
+        if temp < crate::samplers::SampleTemperature::GREEDY_THRESHOLD {
+            logits.ensure_sorted()?;
+            return Ok(logits
+                .iter()
+                .enumerate()
+                .map(|(idx, l)| (l.token_id, if idx == 0 { 1f32 } else { 0f32 }))
+                .collect());
+        }
+        logits.iter_mut().for_each(|l| l.logit /= temp);
+        logits.set_softmax(false);
+        logits.ensure_softmax()?;
+        Ok(logits.iter().map(|l| (l.token_id, l.prob)).collect())
+    }
+
+    /// Returns the entropy of the distribution in units of the [SoftmaxBase]: nats
+    /// for natural softmax and bits for base-2. This will ensure the softmax function
+    /// has been applied.
+    pub fn entropy(&mut self) -> Result<L> {
+        self.ensure_softmax()?;
+        let base = self.softmax_base;
+        Ok(self
+            .iter()
+            .filter(|l| l.prob > 0f32)
+            .fold(0f32, |ent, l| ent - l.prob * base.log(l.prob)))
+    }
+
+    /// Returns the entropy of the distribution divided by the maximum possible entropy
+    /// for the number of [Logit]s, so the result is between `0.0` (completely confident)
+    /// and `1.0` (uniform). This will ensure the softmax function has been applied.
+    pub fn normalized_entropy(&mut self) -> Result<L> {
+        self.ensure_softmax()?;
+        if self.len() < 2 {
+            return Ok(0f32);
+        }
+        let ent = self
+            .iter()
+            .filter(|l| l.prob > 0f32)
+            .fold(0f32, |ent, l| ent - l.prob * l.prob.ln());
+        Ok((ent / (self.len() as L).ln()).clamp(0f32, 1f32))
+    }
+
+    /// Helper for filtering samplers: returns `min_keep` clamped to the number of
+    /// [Logits], so a `min_keep` larger than the vocabulary simply keeps everything.
+    pub fn effective_min_keep(&self, min_keep: usize) -> usize {
+        min_keep.min(self.len())
+    }
+
+    /// Helper for filtering samplers that want `min_keep` to scale with the
+    /// uncertainty of the distribution. The result is
+    /// `ceil(base_min_keep * normalized_entropy * factor)`, see [Self::normalized_entropy].
+    ///
+    /// When `factor` is `0.0` or less, `base_min_keep` is used unchanged and the
+    /// [Logits] are not modified. Otherwise the result is at least `1` unless
+    /// `base_min_keep` is `0`, so a distribution with zero entropy (for example a single
+    /// token) still keeps a token. Either way, the result is clamped with
+    /// [Self::effective_min_keep].
+    pub fn adaptive_min_keep(&mut self, base_min_keep: usize, factor: L) -> Result<usize> {
+        if factor <= 0f32 || base_min_keep == 0 {
+            return Ok(self.effective_min_keep(base_min_keep));
+        }
+        let ent = self.normalized_entropy()?;
+        let min_keep = (base_min_keep as L * ent * factor).ceil() as usize;
+        Ok(self.effective_min_keep(min_keep.max(1)))
+    }
+
+    /// Like [Vec::truncate] but the removed [Logit]s are returned in order rather than
+    /// dropped, for example to log what a sampler eliminated. The retained [Logit]s
+    /// stay where they are and the allocation is left alone. If anything is removed,
+    /// the softmax flag is cleared.
+    ///
+    /// The [Logits] are truncated even if the returned iterator isn't consumed.
+    pub fn drain_truncate(&mut self, n: usize) -> std::vec::Drain<'_, Logit> {
+        let n = n.min(self.len());
+        if n != self.len() {
+            self.set_softmax(false);
+        }
+        self.drain(n..)
+    }
+
+    /// Eliminate the [Logit]s after the first `n` like [Vec::truncate]. When `floor` is
+    /// set they are kept instead and their logits are changed so each one ends up with
+    /// probability `floor` once softmax is applied, while the first `n` keep their
+    /// relative probabilities. This allows softer filtering where eliminated tokens can
+    /// still be selected, rarely. `None` or a `floor` of `0.0` or less truncates.
+    ///
+    /// Whether a floor fits depends on how many tokens are eliminated, so it's clamped:
+    /// the first `n` always keep at least [Logits::FLOOR_MIN_KEPT_MASS] of the
+    /// probability in total. For example, with `5e-4` and 32,000 eliminated tokens each
+    /// one ends up with `0.5 / 32000` instead.
+    ///
+    /// If anything is changed, the softmax flag is cleared.
+    pub fn truncate_with_floor(&mut self, n: usize, floor: Option<L>) -> Result<&mut Self> {
+        let n = n.min(self.len());
+        if n == self.len() {
+            return Ok(self);
+        }
+        self.set_softmax(false);
+        let floor = floor.unwrap_or_default();
+        if n == 0 || floor <= 0f32 {
+            self.truncate(n);
+            return Ok(self);
+        }
+        let eliminated = (self.len() - n) as L;
+        let floor = floor.min((1f32 - Self::FLOOR_MIN_KEPT_MASS) / eliminated);
+
+        let base = self.softmax_base;
+        let (kept, rest) = self.logits.split_at_mut(n);
+        let max_l = kept.iter().fold(L::NEG_INFINITY, |m, l| m.max(l.logit));
+        if !max_l.is_finite() {
+            self.truncate(n);
+            return Ok(self);
+        }
+        let kept_sum = kept
+            .iter()
+            .fold(0f32, |sum, l| sum + base.exp(l.logit - max_l));
+        let floor_logit = max_l + base.log(floor * kept_sum / (1f32 - floor * eliminated));
+        rest.iter_mut().for_each(|l| l.logit = floor_logit);
+        if self.sorted && floor_logit > self.logits[n - 1].logit {
+            self.sorted = false;
+        }
+        Ok(self)
+    }
+
+    /// The smallest total probability [Logits::truncate_with_floor] leaves for the
+    /// tokens it keeps, the floor gets clamped so the eliminated tokens never have more.
+    pub const FLOOR_MIN_KEPT_MASS: L = 0.5;
+
+    /// Logit used by [Logits::probs_to_logits] for tokens with a probability of `0.0`.
+    /// It's finite so samplers that do arithmetic on the logits still work, but low
+    /// enough that the token gets a probability of `0.0` when softmax is applied again.
+    pub const ZERO_PROB_LOGIT: L = -1e4;
+
+    /// Set each logit to the logarithm of its probability, using the softmax base. This
+    /// is the inverse of the softmax function up to a constant, so applying softmax
+    /// again gives the same probabilities. It's useful when the host only has
+    /// probabilities: fill in [Logit::prob] and call this so samplers that work on raw
+    /// logits (like temperature or the penalties) can be used.
+    ///
+    /// Tokens with a probability of `0.0` or less get [Logits::ZERO_PROB_LOGIT]. The
+    /// softmax flag is cleared. The sorted flag is also cleared unless softmax was
+    /// applied, since otherwise the probabilities may not follow the logit order.
+    pub fn probs_to_logits(&mut self) -> &mut Self {
+        if !self.has_softmax {
+            self.sorted = false;
+        }
+        let base = self.softmax_base;
+        self.logits.iter_mut().for_each(|l| {
+            l.logit = if l.prob > 0f32 {
+                base.log(l.prob)
+            } else {
+                Self::ZERO_PROB_LOGIT
+            };
+        });
+        self.set_softmax(false)
+    }
+
+    /// Returns up to `n` of the most probable [Logit]s. This will ensure
+    /// the softmax function has been applied.
+    pub fn top_n(&mut self, n: usize) -> Result<&[Logit]> {
+        self.ensure_softmax()?;
+        Ok(&self.logits[..n.min(self.logits.len())])
+    }
+
+    /// Debugging helper that formats the `top_n` [Logit]s with the highest logit values,
+    /// one per line, like:
+    ///
+    /// `"the" (id=278): logit=3.21 prob=0.41`
+    ///
+    /// `lookup` should convert a token id to its string. Note that the probabilities are
+    /// shown as-is, so they will only be meaningful if the softmax function has been applied.
+    pub fn debug_with_tokens(&self, lookup: impl Fn(TID) -> String, top_n: usize) -> String {
+        use std::fmt::Write;
+
+        let mut top = self.iter().collect::<Vec<_>>();
+        if !self.sorted {
+            top.sort_by(|a, b| b.logit.total_cmp(&a.logit));
+        }
+        top.into_iter()
+            .take(top_n)
+            .fold(String::new(), |mut out, l| {
+                let _ = writeln!(
+                    out,
+                    "{:?} (id={}): logit={:.2} prob={:.2}",
+                    lookup(l.token_id),
+                    l.token_id,
+                    l.logit,
+                    l.prob
+                );
+                out
+            })
+    }
+
     /// Convenience method
     pub fn sample<S: Sampler>(
         &mut self,
@@ -281,8 +852,129 @@ impl Logits {
     }
 }
 
+/// Probability used by [kl_divergence] for tokens that are missing from `q` or
+/// have zero probability there.
+pub const KL_EPSILON: L = 1e-10;
+
+/// Computes the KL divergence `D(p || q)` between two distributions, matching
+/// tokens by token id. The result uses the softmax base of `p`, so it's in nats
+/// by default.
+///
+/// Tokens that are missing from `p` or have zero probability there don't contribute.
+/// Tokens that are missing from `q` are treated as having a probability of [KL_EPSILON],
+/// so the result is large but finite when `p` has tokens `q` doesn't.
+///
+/// Only the probabilities are used, so the softmax function is applied to both [Logits]
+/// if necessary.
+pub fn kl_divergence(p: &mut Logits, q: &mut Logits) -> Result<L> {
+    p.ensure_softmax()?;
+    q.ensure_softmax()?;
+    let base = p.softmax_base;
+    let q_probs = q
+        .iter()
+        .map(|l| (l.token_id, l.prob))
+        .collect::<std::collections::HashMap<_, _>>();
+    Ok(p.iter()
+        .filter(|l| l.prob > 0f32)
+        .map(|l| {
+            let q_prob = q_probs
+                .get(&l.token_id)
+                .copied()
+                .unwrap_or_default()
+                .max(KL_EPSILON);
+            l.prob * base.log(l.prob / q_prob)
+        })
+        .sum())
+}
+
+/// Helper trait that allows cloning and downcasting boxed [Sampler]s. It is automatically
+/// implemented for any [Sampler] that implements [Clone].
+pub trait SamplerClone {
+    /// Clone the [Sampler] into a new [Box].
+    fn clone_box(&self) -> Box<dyn Sampler>;
+
+    /// Returns the [Sampler] as [Any](std::any::Any). Use [dyn Sampler::downcast_ref]
+    /// rather than calling this directly.
+    fn as_any(&self) -> &dyn std::any::Any;
+
+    /// Returns the [Sampler] as mutable [Any](std::any::Any). Use
+    /// [dyn Sampler::downcast_mut] rather than calling this directly.
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any;
+}
+
+impl<T: Sampler + Clone + 'static> SamplerClone for T {
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Describes the state a [Sampler] needs the [Logits] to be in before it runs.
+/// See [Sampler::requires].
+pub struct SampleRequirements {
+    /// The [Logits] must be sorted.
+    pub sorted: bool,
+    /// The softmax function must have been applied. This implies sorted.
+    pub softmax: bool,
+}
+
+impl SampleRequirements {
+    /// No requirements.
+    pub const NONE: Self = Self {
+        sorted: false,
+        softmax: false,
+    };
+
+    /// The [Logits] must be sorted.
+    pub const SORTED: Self = Self {
+        sorted: true,
+        softmax: false,
+    };
+
+    /// The [Logits] must be sorted and have softmax applied.
+    pub const SOFTMAX: Self = Self {
+        sorted: true,
+        softmax: true,
+    };
+
+    /// Returns these requirements if `cond` is `true`, otherwise no requirements.
+    pub fn when(self, cond: bool) -> Self {
+        if cond {
+            self
+        } else {
+            Self::NONE
+        }
+    }
+
+    /// Make sure the [Logits] meet the requirements.
+    pub fn ensure(self, logits: &mut Logits) -> Result<(), SamplerError> {
+        if self.softmax {
+            logits.ensure_softmax().map_err(|e| {
+                SamplerError::InternalError(format!("Failed to ensure softmax: {}", e))
+            })?;
+        } else if self.sorted {
+            logits.ensure_sorted().map_err(|e| {
+                SamplerError::InternalError(format!("Failed to ensure sorted: {}", e))
+            })?;
+        }
+        Ok(())
+    }
+}
+
 /// The main sampler trait.
-pub trait Sampler: Debug + Send + Sync {
+///
+/// Samplers must implement [Clone] (see [SamplerClone]). Cloning a
+/// stateful sampler like Mirostat copies its current state, the clone
+/// is independent from that point on.
+pub trait Sampler: Debug + Send + Sync + SamplerClone {
     /// Returns a string to prepend to the input before sampling.
     fn sample_prepend(&self, _res: &mut dyn HasSamplerResources) {}
 
@@ -300,6 +992,63 @@ pub trait Sampler: Debug + Send + Sync {
         None
     }
 
+    /// Returns `true` if the [Sampler] uses the RNG resource, meaning repeated
+    /// runs on the same input may give different results.
+    ///
+    /// A default implementation is provided which returns `false`.
+    fn is_stochastic(&self) -> bool {
+        false
+    }
+
+    /// Returns the state the [Sampler] needs the [Logits] to be in before it runs.
+    /// [SamplerChain] uses this to make sure the requirements are met, so samplers
+    /// later in the chain can skip work that was already done.
+    ///
+    /// A default implementation is provided which has no requirements.
+    fn requires(&self) -> SampleRequirements {
+        SampleRequirements::NONE
+    }
+
+    /// Returns a short name for the [Sampler], useful for diagnostics.
+    ///
+    /// A default implementation is provided which returns the type name
+    /// without the module path or generic arguments.
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        let name = &name[..name.find('<').unwrap_or(name.len())];
+        name.rfind("::").map_or(name, |idx| &name[idx + 2..])
+    }
+
+    /// Returns a human-readable summary of the current option values of the [Sampler],
+    /// for example `[p: 0.9, min_keep: 1]`. This is used when displaying a [SamplerChain].
+    ///
+    /// A default implementation is provided which simply returns [None].
+    fn options_summary(&self) -> Option<String> {
+        None
+    }
+
+    /// Notifies the [Sampler] that the host dropped `dropped` tokens from the start of
+    /// the context, for example when truncating or shifting the context window during
+    /// a long generation. The last tokens resource is expected to have had the same
+    /// tokens removed. Stateful samplers can use this to keep their state meaningful.
+    ///
+    /// A default implementation is provided which does nothing.
+    fn notify_context_shift(&mut self, _dropped: usize) {}
+
+    /// Resets whatever state the [Sampler] keeps between steps, so it can start over
+    /// with a new generation. [SamplerChain::reset] calls this for each of its samplers.
+    ///
+    /// A default implementation is provided which does nothing.
+    fn reset(&mut self) {}
+
+    /// Returns the metadata for the [Sampler], describing the options it has. This
+    /// is used by [SamplerChain::parameter_schema].
+    ///
+    /// A default implementation is provided which simply returns [None].
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        None
+    }
+
     /// Run the sampler and return the last sampled token id if available.
     ///
     /// A default implementation is provided which just calls [Sampler::sample] followed by
@@ -312,6 +1061,59 @@ pub trait Sampler: Debug + Send + Sync {
         let _ = self.sample(res, logits)?;
         Ok(self.sampled_token_id())
     }
+
+    /// Convenience method to box the [Sampler]. Useful when building a
+    /// collection of different samplers.
+    ///
+    /// ```rust
+    /// use llm_samplers::prelude::*;
+    ///
+    /// let samplers = vec![
+    ///     SampleTopK::default().boxed(),
+    ///     SampleTemperature::new(0.8).boxed(),
+    ///     SampleGreedy::new().boxed(),
+    /// ];
+    /// let mut sc = samplers.into_iter().fold(SamplerChain::new(), |sc, s| sc + s);
+    /// let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+    /// assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(3));
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    fn boxed(self) -> Box<dyn Sampler>
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl dyn Sampler {
+    /// Returns a reference to the [Sampler] if it's of type `T`. Samplers that were
+    /// boxed more than once are unwrapped, but ones behind a [Mutex] are not.
+    pub fn downcast_ref<T: Sampler + 'static>(&self) -> Option<&T> {
+        let any = self.as_any();
+        match any.downcast_ref::<Box<dyn Sampler>>() {
+            Some(inner) => inner.as_ref().downcast_ref(),
+            None => any.downcast_ref(),
+        }
+    }
+
+    /// Returns a mutable reference to the [Sampler] if it's of type `T`. See
+    /// [dyn Sampler::downcast_ref].
+    pub fn downcast_mut<T: Sampler + 'static>(&mut self) -> Option<&mut T> {
+        if self.as_any().is::<Box<dyn Sampler>>() {
+            return self
+                .as_any_mut()
+                .downcast_mut::<Box<dyn Sampler>>()
+                .and_then(|inner| inner.as_mut().downcast_mut());
+        }
+        self.as_any_mut().downcast_mut()
+    }
+}
+
+impl Clone for Box<dyn Sampler> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
 }
 
 impl Sampler for Box<dyn Sampler> {
@@ -319,6 +1121,34 @@ impl Sampler for Box<dyn Sampler> {
         (**self).sampled_token_id()
     }
 
+    fn is_stochastic(&self) -> bool {
+        (**self).is_stochastic()
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        (**self).requires()
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn options_summary(&self) -> Option<String> {
+        (**self).options_summary()
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        (**self).metadata()
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        (**self).notify_context_shift(dropped)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
     fn sample_token(
         &mut self,
         res: &mut dyn HasSamplerResources,
@@ -336,11 +1166,45 @@ impl Sampler for Box<dyn Sampler> {
     }
 }
 
+/// Note: Cloning this will share the same underlying [Sampler].
 impl Sampler for Arc<Mutex<dyn Sampler>> {
     fn sampled_token_id(&self) -> Option<TID> {
         self.lock().ok()?.sampled_token_id()
     }
 
+    fn is_stochastic(&self) -> bool {
+        self.lock().is_ok_and(|s| s.is_stochastic())
+    }
+
+    fn requires(&self) -> SampleRequirements {
+        self.lock()
+            .map_or(SampleRequirements::NONE, |s| s.requires())
+    }
+
+    fn name(&self) -> &'static str {
+        self.lock().map_or("<poisoned>", |s| s.name())
+    }
+
+    fn options_summary(&self) -> Option<String> {
+        self.lock().ok()?.options_summary()
+    }
+
+    fn metadata(&self) -> Option<SamplerMetadata> {
+        self.lock().ok()?.metadata()
+    }
+
+    fn notify_context_shift(&mut self, dropped: usize) {
+        if let Ok(mut s) = self.lock() {
+            s.notify_context_shift(dropped)
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Ok(mut s) = self.lock() {
+            s.reset()
+        }
+    }
+
     fn sample_token(
         &mut self,
         res: &mut dyn HasSamplerResources,